@@ -1,12 +1,33 @@
 CREATE EXTENSION IF NOT EXISTS citext;
 CREATE OR REPLACE LANGUAGE plpgsql;
 
+DROP TABLE IF EXISTS SchemaMigration;
+DROP TABLE IF EXISTS RateLimitBucket;
+DROP TABLE IF EXISTS ShareLink;
+DROP TABLE IF EXISTS Friend;
+DROP TABLE IF EXISTS Privacy;
+DROP TABLE IF EXISTS PendingDeletion;
+DROP TABLE IF EXISTS EmailVerification;
+DROP TABLE IF EXISTS AuthEvent;
+DROP TABLE IF EXISTS AccountToken;
+DROP TABLE IF EXISTS AuthFailure;
+DROP TABLE IF EXISTS GameCooccurrence;
+DROP TABLE IF EXISTS GamePopularity;
+DROP TABLE IF EXISTS EntryTag;
 DROP TABLE IF EXISTS Library;
 DROP TABLE IF EXISTS Entry;
 DROP TABLE IF EXISTS Game;
 DROP TABLE IF EXISTS Login;
 DROP TYPE IF EXISTS Status;
 
+-- The migrations the schema has had, see `storage::SCHEMA_VERSION`. This
+-- file is always the latest schema with every migration applied.
+CREATE TABLE SchemaMigration (
+	version INT PRIMARY KEY,
+	applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+INSERT INTO SchemaMigration (version) VALUES (1), (2), (3), (4), (5), (6), (7), (8), (9), (10), (11);
+
 CREATE TYPE Status AS ENUM (
 	'Frozen',
 	'CurrentlyPlaying',
@@ -36,12 +57,22 @@ CREATE TABLE Entry (
 	FOREIGN KEY (game_id) REFERENCES Game(id)
 );
 
+-- Deleting a user deletes their library, and deleting an entry its row in
+-- it. Entries themselves have to be deleted after the library, see
+-- `remove_cooccurrence`.
 CREATE TABLE Library (
 	id SERIAL PRIMARY KEY,
 	login_id INT NOT NULL,
 	entry_id INT NOT NULL UNIQUE,
-	FOREIGN KEY (login_id) REFERENCES Login(id),
-	FOREIGN KEY (entry_id) REFERENCES Entry(id)
+	FOREIGN KEY (login_id) REFERENCES Login(id) ON DELETE CASCADE,
+	FOREIGN KEY (entry_id) REFERENCES Entry(id) ON DELETE CASCADE
+);
+
+CREATE TABLE EntryTag (
+	entry_id INT NOT NULL,
+	tag TEXT NOT NULL,
+	PRIMARY KEY (entry_id, tag),
+	FOREIGN KEY (entry_id) REFERENCES Entry(id) ON DELETE CASCADE
 );
 
 CREATE OR REPLACE FUNCTION update_last_update()
@@ -56,6 +87,207 @@ CREATE TRIGGER entry_last_update BEFORE UPDATE
 ON Entry FOR EACH ROW EXECUTE PROCEDURE
 update_last_update();
 
+-- Number of users having a game in their library.
+CREATE TABLE GamePopularity (
+	game_id INT PRIMARY KEY,
+	players INT NOT NULL,
+	FOREIGN KEY (game_id) REFERENCES Game(id)
+);
+
+-- Number of users having both games in their library. Every pair is
+-- stored in both directions to keep lookups for a single game cheap.
+CREATE TABLE GameCooccurrence (
+	game_id INT NOT NULL,
+	other_id INT NOT NULL,
+	players INT NOT NULL,
+	PRIMARY KEY (game_id, other_id),
+	FOREIGN KEY (game_id) REFERENCES Game(id),
+	FOREIGN KEY (other_id) REFERENCES Game(id)
+);
+
+-- Adds a player to the count of `game` and `other` being played together,
+-- or of `game` alone if `other` is null. Two libraries getting the same new
+-- game at once both find no row to update, so whichever inserts it second
+-- gets a unique_violation and updates the row of the first instead.
+CREATE OR REPLACE FUNCTION count_player(game INT, other INT)
+RETURNS VOID AS $$
+BEGIN
+	LOOP
+		IF other IS NULL THEN
+			UPDATE GamePopularity SET players = players + 1 WHERE game_id = game;
+		ELSE
+			UPDATE GameCooccurrence SET players = players + 1
+				WHERE game_id = game AND other_id = other;
+		END IF;
+		IF FOUND THEN
+			RETURN;
+		END IF;
+
+		BEGIN
+			IF other IS NULL THEN
+				INSERT INTO GamePopularity (game_id, players) VALUES (game, 1);
+			ELSE
+				INSERT INTO GameCooccurrence (game_id, other_id, players)
+					VALUES (game, other, 1);
+			END IF;
+			RETURN;
+		EXCEPTION WHEN unique_violation THEN
+			-- Loop to update the row inserted in the meantime.
+		END;
+	END LOOP;
+END;
+$$ language 'plpgsql';
+
+-- Keeps the counts above up to date as libraries grow, so that finding
+-- similar games never has to scan every library.
+CREATE OR REPLACE FUNCTION update_cooccurrence()
+RETURNS TRIGGER AS $$
+DECLARE
+	new_game INT;
+	other_game INT;
+BEGIN
+	SELECT game_id INTO new_game FROM Entry WHERE id = NEW.entry_id;
+
+	-- A user having the same game twice shouldn't count twice.
+	IF EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = NEW.login_id AND li.id <> NEW.id AND e.game_id = new_game) THEN
+		RETURN NEW;
+	END IF;
+
+	PERFORM count_player(new_game, NULL);
+	FOR other_game IN
+		SELECT DISTINCT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE li.login_id = NEW.login_id AND li.id <> NEW.id
+	LOOP
+		PERFORM count_player(new_game, other_game);
+		PERFORM count_player(other_game, new_game);
+	END LOOP;
+	RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER library_cooccurrence AFTER INSERT
+ON Library FOR EACH ROW EXECUTE PROCEDURE
+update_cooccurrence();
+
+-- Takes a game out of the counts above when it leaves a library. It runs
+-- before the row is deleted since postgres runs AFTER row triggers at the
+-- end of the statement, when the other rows deleted with it are gone too.
+-- The entry has to be there still, so libraries are emptied before their
+-- entries are deleted.
+CREATE OR REPLACE FUNCTION remove_cooccurrence()
+RETURNS TRIGGER AS $$
+DECLARE
+	old_game INT;
+BEGIN
+	SELECT game_id INTO old_game FROM Entry WHERE id = OLD.entry_id;
+
+	-- The game stays if the user has it twice.
+	IF old_game IS NULL OR EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id AND e.game_id = old_game) THEN
+		RETURN OLD;
+	END IF;
+
+	UPDATE GamePopularity SET players = players - 1 WHERE game_id = old_game;
+	UPDATE GameCooccurrence SET players = players - 1
+		WHERE game_id = old_game AND other_id IN (
+			SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id);
+	UPDATE GameCooccurrence SET players = players - 1
+		WHERE other_id = old_game AND game_id IN (
+			SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id);
+	DELETE FROM GamePopularity WHERE game_id = old_game AND players <= 0;
+	DELETE FROM GameCooccurrence
+		WHERE (game_id = old_game OR other_id = old_game) AND players <= 0;
+	RETURN OLD;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER library_cooccurrence_delete BEFORE DELETE
+ON Library FOR EACH ROW EXECUTE PROCEDURE
+remove_cooccurrence();
+
+
+-- Token buckets of `ratelimit::PgRateLimitStore`, shared by every server.
+-- Times are in seconds since the epoch.
+CREATE TABLE RateLimitBucket (
+	key TEXT PRIMARY KEY,
+	tokens DOUBLE PRECISION NOT NULL,
+	updated_at DOUBLE PRECISION NOT NULL
+);
+
+-- Failed attempts to log in in a row, by login (`login:<id>`) or by IP
+-- (`ip:<address>`), see `lockout`.
+CREATE TABLE AuthFailure (
+	key TEXT PRIMARY KEY,
+	failures INT NOT NULL,
+	last_failure TIMESTAMP WITH TIME ZONE NOT NULL,
+	blocked_until TIMESTAMP WITH TIME ZONE
+);
+
+-- Secrets mailed to users, used once for `purpose`.
+CREATE TABLE AccountToken (
+	token TEXT PRIMARY KEY,
+	login_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	purpose TEXT NOT NULL,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- The audit trail of logging in. Attempts with unknown usernames have no
+-- login.
+CREATE TABLE AuthEvent (
+	id SERIAL PRIMARY KEY,
+	login_id INT REFERENCES Login(id) ON DELETE CASCADE,
+	username TEXT NOT NULL,
+	ip TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX auth_event_login ON AuthEvent (login_id);
+
+-- The email of a login verified with a token mailed to it, which only counts
+-- while the login still has that email.
+CREATE TABLE EmailVerification (
+	login_id INT PRIMARY KEY REFERENCES Login(id) ON DELETE CASCADE,
+	email CITEXT NOT NULL,
+	verified_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- Users who asked to be deleted, which they are at `delete_at` unless they
+-- change their mind before.
+CREATE TABLE PendingDeletion (
+	login_id INT PRIMARY KEY REFERENCES Login(id) ON DELETE CASCADE,
+	delete_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX pending_deletion_at ON PendingDeletion (delete_at);
+
+-- Who can see the library of a login, which is anyone without a row.
+CREATE TABLE Privacy (
+	login_id INT PRIMARY KEY REFERENCES Login(id) ON DELETE CASCADE,
+	library TEXT NOT NULL CHECK (library IN ('Public', 'Friends', 'Private'))
+);
+
+-- Who a login lets see a library visible to friends. It only goes one way:
+-- adding a friend doesn't let you see their library.
+CREATE TABLE Friend (
+	login_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	friend_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	PRIMARY KEY (login_id, friend_id)
+);
+CREATE INDEX friend_friend ON Friend (friend_id);
+
+-- Links letting anyone who has the token see the library of a login, or only
+-- the entries with `status` or `tag` if set, until it is deleted.
+CREATE TABLE ShareLink (
+	token TEXT PRIMARY KEY,
+	login_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	status Status NULL,
+	tag TEXT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX share_link_login ON ShareLink (login_id);
+
 
 INSERT INTO Login (username, password, email) VALUES ('user', 'hunter2', 'user@example.com');
 INSERT INTO Game (name, description) VALUES
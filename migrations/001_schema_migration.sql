@@ -0,0 +1,15 @@
+-- Starts keeping track of migrations in databases created from db.sql
+-- before it had SchemaMigration. Version 1 is the schema without the
+-- counts of games played together and without EntryTag; databases created
+-- from a db.sql which already had both are at version 3, so after this
+-- insert versions 2 and 3 instead of running their migrations.
+BEGIN;
+
+CREATE TABLE SchemaMigration (
+	version INT PRIMARY KEY,
+	applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+INSERT INTO SchemaMigration (version) VALUES (1);
+
+COMMIT;
@@ -0,0 +1,77 @@
+-- Counts of users having a game and of users having both games of a pair,
+-- kept up to date as libraries grow, for finding similar games.
+BEGIN;
+
+CREATE TABLE GamePopularity (
+	game_id INT PRIMARY KEY,
+	players INT NOT NULL,
+	FOREIGN KEY (game_id) REFERENCES Game(id)
+);
+
+CREATE TABLE GameCooccurrence (
+	game_id INT NOT NULL,
+	other_id INT NOT NULL,
+	players INT NOT NULL,
+	PRIMARY KEY (game_id, other_id),
+	FOREIGN KEY (game_id) REFERENCES Game(id),
+	FOREIGN KEY (other_id) REFERENCES Game(id)
+);
+
+CREATE OR REPLACE FUNCTION update_cooccurrence()
+RETURNS TRIGGER AS $$
+DECLARE
+	new_game INT;
+	other_game INT;
+BEGIN
+	SELECT game_id INTO new_game FROM Entry WHERE id = NEW.entry_id;
+
+	-- A user having the same game twice shouldn't count twice.
+	IF EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = NEW.login_id AND li.id <> NEW.id AND e.game_id = new_game) THEN
+		RETURN NEW;
+	END IF;
+
+	UPDATE GamePopularity SET players = players + 1 WHERE game_id = new_game;
+	IF NOT FOUND THEN
+		INSERT INTO GamePopularity (game_id, players) VALUES (new_game, 1);
+	END IF;
+
+	FOR other_game IN
+		SELECT DISTINCT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE li.login_id = NEW.login_id AND li.id <> NEW.id
+	LOOP
+		UPDATE GameCooccurrence SET players = players + 1
+			WHERE game_id = new_game AND other_id = other_game;
+		IF NOT FOUND THEN
+			INSERT INTO GameCooccurrence (game_id, other_id, players)
+				VALUES (new_game, other_game, 1), (other_game, new_game, 1);
+		ELSE
+			UPDATE GameCooccurrence SET players = players + 1
+				WHERE game_id = other_game AND other_id = new_game;
+		END IF;
+	END LOOP;
+	RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER library_cooccurrence AFTER INSERT
+ON Library FOR EACH ROW EXECUTE PROCEDURE
+update_cooccurrence();
+
+-- Counts the libraries there already are.
+INSERT INTO GamePopularity (game_id, players)
+	SELECT e.game_id, COUNT(DISTINCT li.login_id)
+	FROM Library li JOIN Entry e ON e.id = li.entry_id
+	GROUP BY e.game_id;
+INSERT INTO GameCooccurrence (game_id, other_id, players)
+	SELECT a.game_id, b.game_id, COUNT(*)
+	FROM (SELECT DISTINCT li.login_id, e.game_id
+		FROM Library li JOIN Entry e ON e.id = li.entry_id) a
+	JOIN (SELECT DISTINCT li.login_id, e.game_id
+		FROM Library li JOIN Entry e ON e.id = li.entry_id) b
+	ON a.login_id = b.login_id AND a.game_id <> b.game_id
+	GROUP BY a.game_id, b.game_id;
+
+INSERT INTO SchemaMigration (version) VALUES (2);
+
+COMMIT;
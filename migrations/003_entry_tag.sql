@@ -0,0 +1,13 @@
+-- Free-form tags of entries, which imports can set, see `import`.
+BEGIN;
+
+CREATE TABLE EntryTag (
+	entry_id INT NOT NULL,
+	tag TEXT NOT NULL,
+	PRIMARY KEY (entry_id, tag),
+	FOREIGN KEY (entry_id) REFERENCES Entry(id)
+);
+
+INSERT INTO SchemaMigration (version) VALUES (3);
+
+COMMIT;
@@ -0,0 +1,13 @@
+-- Token buckets of `ratelimit::PgRateLimitStore`, shared by every server.
+-- Times are in seconds since the epoch.
+BEGIN;
+
+CREATE TABLE RateLimitBucket (
+	key TEXT PRIMARY KEY,
+	tokens DOUBLE PRECISION NOT NULL,
+	updated_at DOUBLE PRECISION NOT NULL
+);
+
+INSERT INTO SchemaMigration (version) VALUES (4);
+
+COMMIT;
@@ -0,0 +1,31 @@
+-- Failed attempts to log in, the tokens mailed to unlock locked logins and
+-- the audit trail of logging in, see `lockout`.
+BEGIN;
+
+CREATE TABLE AuthFailure (
+	key TEXT PRIMARY KEY,
+	failures INT NOT NULL,
+	last_failure TIMESTAMP WITH TIME ZONE NOT NULL,
+	blocked_until TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE AccountToken (
+	token TEXT PRIMARY KEY,
+	login_id INT NOT NULL REFERENCES Login(id),
+	purpose TEXT NOT NULL,
+	expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE AuthEvent (
+	id SERIAL PRIMARY KEY,
+	login_id INT REFERENCES Login(id),
+	username TEXT NOT NULL,
+	ip TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX auth_event_login ON AuthEvent (login_id);
+
+INSERT INTO SchemaMigration (version) VALUES (5);
+
+COMMIT;
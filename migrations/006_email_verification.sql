@@ -0,0 +1,12 @@
+-- Which emails of logins have been verified, see `account`.
+BEGIN;
+
+CREATE TABLE EmailVerification (
+	login_id INT PRIMARY KEY REFERENCES Login(id),
+	email CITEXT NOT NULL,
+	verified_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+INSERT INTO SchemaMigration (version) VALUES (6);
+
+COMMIT;
@@ -0,0 +1,30 @@
+-- Deleting users along with everything of theirs, see `account`.
+BEGIN;
+
+ALTER TABLE Library
+	DROP CONSTRAINT library_login_id_fkey,
+	DROP CONSTRAINT library_entry_id_fkey,
+	ADD FOREIGN KEY (login_id) REFERENCES Login(id) ON DELETE CASCADE,
+	ADD FOREIGN KEY (entry_id) REFERENCES Entry(id) ON DELETE CASCADE;
+ALTER TABLE EntryTag
+	DROP CONSTRAINT entrytag_entry_id_fkey,
+	ADD FOREIGN KEY (entry_id) REFERENCES Entry(id) ON DELETE CASCADE;
+ALTER TABLE AccountToken
+	DROP CONSTRAINT accounttoken_login_id_fkey,
+	ADD FOREIGN KEY (login_id) REFERENCES Login(id) ON DELETE CASCADE;
+ALTER TABLE AuthEvent
+	DROP CONSTRAINT authevent_login_id_fkey,
+	ADD FOREIGN KEY (login_id) REFERENCES Login(id) ON DELETE CASCADE;
+ALTER TABLE EmailVerification
+	DROP CONSTRAINT emailverification_login_id_fkey,
+	ADD FOREIGN KEY (login_id) REFERENCES Login(id) ON DELETE CASCADE;
+
+CREATE TABLE PendingDeletion (
+	login_id INT PRIMARY KEY REFERENCES Login(id) ON DELETE CASCADE,
+	delete_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX pending_deletion_at ON PendingDeletion (delete_at);
+
+INSERT INTO SchemaMigration (version) VALUES (7);
+
+COMMIT;
@@ -0,0 +1,18 @@
+-- Who can see the library of a user, see `privacy`.
+BEGIN;
+
+CREATE TABLE Privacy (
+	login_id INT PRIMARY KEY REFERENCES Login(id) ON DELETE CASCADE,
+	library TEXT NOT NULL CHECK (library IN ('Public', 'Friends', 'Private'))
+);
+
+CREATE TABLE Friend (
+	login_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	friend_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	PRIMARY KEY (login_id, friend_id)
+);
+CREATE INDEX friend_friend ON Friend (friend_id);
+
+INSERT INTO SchemaMigration (version) VALUES (8);
+
+COMMIT;
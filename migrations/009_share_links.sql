@@ -0,0 +1,15 @@
+-- Links letting anyone who has them see a library, see `privacy`.
+BEGIN;
+
+CREATE TABLE ShareLink (
+	token TEXT PRIMARY KEY,
+	login_id INT NOT NULL REFERENCES Login(id) ON DELETE CASCADE,
+	status Status NULL,
+	tag TEXT NULL,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX share_link_login ON ShareLink (login_id);
+
+INSERT INTO SchemaMigration (version) VALUES (9);
+
+COMMIT;
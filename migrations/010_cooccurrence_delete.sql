@@ -0,0 +1,45 @@
+-- Keeps the counts of games played together up to date as libraries
+-- shrink, not only as they grow.
+BEGIN;
+
+-- Takes a game out of the counts above when it leaves a library. It runs
+-- before the row is deleted since postgres runs AFTER row triggers at the
+-- end of the statement, when the other rows deleted with it are gone too.
+-- The entry has to be there still, so libraries are emptied before their
+-- entries are deleted.
+CREATE OR REPLACE FUNCTION remove_cooccurrence()
+RETURNS TRIGGER AS $$
+DECLARE
+	old_game INT;
+BEGIN
+	SELECT game_id INTO old_game FROM Entry WHERE id = OLD.entry_id;
+
+	-- The game stays if the user has it twice.
+	IF old_game IS NULL OR EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id AND e.game_id = old_game) THEN
+		RETURN OLD;
+	END IF;
+
+	UPDATE GamePopularity SET players = players - 1 WHERE game_id = old_game;
+	UPDATE GameCooccurrence SET players = players - 1
+		WHERE game_id = old_game AND other_id IN (
+			SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id);
+	UPDATE GameCooccurrence SET players = players - 1
+		WHERE other_id = old_game AND game_id IN (
+			SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id);
+	DELETE FROM GamePopularity WHERE game_id = old_game AND players <= 0;
+	DELETE FROM GameCooccurrence
+		WHERE (game_id = old_game OR other_id = old_game) AND players <= 0;
+	RETURN OLD;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER library_cooccurrence_delete BEFORE DELETE
+ON Library FOR EACH ROW EXECUTE PROCEDURE
+remove_cooccurrence();
+
+INSERT INTO SchemaMigration (version) VALUES (10);
+
+COMMIT;
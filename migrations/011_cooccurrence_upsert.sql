@@ -0,0 +1,68 @@
+-- Counts players of games concurrently without failing when two libraries
+-- get the same new game at once.
+BEGIN;
+
+-- Adds a player to the count of `game` and `other` being played together,
+-- or of `game` alone if `other` is null. Two libraries getting the same new
+-- game at once both find no row to update, so whichever inserts it second
+-- gets a unique_violation and updates the row of the first instead.
+CREATE OR REPLACE FUNCTION count_player(game INT, other INT)
+RETURNS VOID AS $$
+BEGIN
+	LOOP
+		IF other IS NULL THEN
+			UPDATE GamePopularity SET players = players + 1 WHERE game_id = game;
+		ELSE
+			UPDATE GameCooccurrence SET players = players + 1
+				WHERE game_id = game AND other_id = other;
+		END IF;
+		IF FOUND THEN
+			RETURN;
+		END IF;
+
+		BEGIN
+			IF other IS NULL THEN
+				INSERT INTO GamePopularity (game_id, players) VALUES (game, 1);
+			ELSE
+				INSERT INTO GameCooccurrence (game_id, other_id, players)
+					VALUES (game, other, 1);
+			END IF;
+			RETURN;
+		EXCEPTION WHEN unique_violation THEN
+			-- Loop to update the row inserted in the meantime.
+		END;
+	END LOOP;
+END;
+$$ language 'plpgsql';
+
+-- Keeps the counts above up to date as libraries grow, so that finding
+-- similar games never has to scan every library.
+CREATE OR REPLACE FUNCTION update_cooccurrence()
+RETURNS TRIGGER AS $$
+DECLARE
+	new_game INT;
+	other_game INT;
+BEGIN
+	SELECT game_id INTO new_game FROM Entry WHERE id = NEW.entry_id;
+
+	-- A user having the same game twice shouldn't count twice.
+	IF EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = NEW.login_id AND li.id <> NEW.id AND e.game_id = new_game) THEN
+		RETURN NEW;
+	END IF;
+
+	PERFORM count_player(new_game, NULL);
+	FOR other_game IN
+		SELECT DISTINCT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE li.login_id = NEW.login_id AND li.id <> NEW.id
+	LOOP
+		PERFORM count_player(new_game, other_game);
+		PERFORM count_player(other_game, new_game);
+	END LOOP;
+	RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+INSERT INTO SchemaMigration (version) VALUES (11);
+
+COMMIT;
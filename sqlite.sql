@@ -0,0 +1,214 @@
+-- The schema of db.sql for SQLite, which is created automatically when
+-- opening a database with the sqlite feature. Timestamps are stored as ISO
+-- 8601 text in UTC with microseconds like the exports, which sorts correctly.
+
+-- The migrations the schema has had, see `storage::SCHEMA_VERSION`.
+CREATE TABLE IF NOT EXISTS SchemaMigration (
+	version INTEGER PRIMARY KEY,
+	applied_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z','now'))
+);
+INSERT OR IGNORE INTO SchemaMigration (version) VALUES (1), (2), (3), (4), (5), (6), (7), (8), (9), (10), (11);
+
+CREATE TABLE IF NOT EXISTS Login (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	username TEXT NOT NULL UNIQUE CHECK (length(username) <= 20),
+	password TEXT NOT NULL CHECK (length(password) <= 128),
+	-- Instead of CITEXT.
+	email TEXT NOT NULL UNIQUE COLLATE NOCASE
+);
+
+CREATE TABLE IF NOT EXISTS Game (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT NOT NULL,
+	description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS Entry (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	game_id INT NOT NULL,
+	time_played REAL NOT NULL,
+	last_update TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+	-- Instead of the Status enum.
+	status TEXT NOT NULL
+		CHECK (status IN ('Frozen', 'CurrentlyPlaying', 'Dropped', 'PlanToPlay')),
+	FOREIGN KEY (game_id) REFERENCES Game(id)
+);
+
+CREATE TABLE IF NOT EXISTS Library (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	login_id INT NOT NULL,
+	entry_id INT NOT NULL UNIQUE,
+	FOREIGN KEY (login_id) REFERENCES Login(id),
+	FOREIGN KEY (entry_id) REFERENCES Entry(id)
+);
+
+CREATE TABLE IF NOT EXISTS EntryTag (
+	entry_id INT NOT NULL,
+	tag TEXT NOT NULL,
+	PRIMARY KEY (entry_id, tag),
+	FOREIGN KEY (entry_id) REFERENCES Entry(id)
+);
+
+-- SQLite has millisecond precision, which is padded to microseconds.
+CREATE TRIGGER IF NOT EXISTS entry_last_update AFTER UPDATE OF game_id, time_played, status
+ON Entry FOR EACH ROW
+BEGIN
+	UPDATE Entry SET last_update = strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')
+		WHERE id = NEW.id;
+END;
+
+CREATE TABLE IF NOT EXISTS GamePopularity (
+	game_id INT PRIMARY KEY,
+	players INT NOT NULL,
+	FOREIGN KEY (game_id) REFERENCES Game(id)
+);
+
+CREATE TABLE IF NOT EXISTS GameCooccurrence (
+	game_id INT NOT NULL,
+	other_id INT NOT NULL,
+	players INT NOT NULL,
+	PRIMARY KEY (game_id, other_id),
+	FOREIGN KEY (game_id) REFERENCES Game(id),
+	FOREIGN KEY (other_id) REFERENCES Game(id)
+);
+
+-- Does the same as update_cooccurrence in db.sql, but with statements over
+-- sets since triggers in SQLite can't loop.
+CREATE TRIGGER IF NOT EXISTS library_cooccurrence AFTER INSERT
+ON Library FOR EACH ROW
+WHEN NOT EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE li.login_id = NEW.login_id AND li.id <> NEW.id
+		AND e.game_id = (SELECT game_id FROM Entry WHERE id = NEW.entry_id))
+BEGIN
+	INSERT OR IGNORE INTO GamePopularity (game_id, players)
+		SELECT game_id, 0 FROM Entry WHERE id = NEW.entry_id;
+	UPDATE GamePopularity SET players = players + 1
+		WHERE game_id = (SELECT game_id FROM Entry WHERE id = NEW.entry_id);
+
+	INSERT OR IGNORE INTO GameCooccurrence (game_id, other_id, players)
+		SELECT DISTINCT n.game_id, e.game_id, 0
+		FROM Entry n, Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE n.id = NEW.entry_id AND li.login_id = NEW.login_id AND li.id <> NEW.id;
+	INSERT OR IGNORE INTO GameCooccurrence (game_id, other_id, players)
+		SELECT DISTINCT e.game_id, n.game_id, 0
+		FROM Entry n, Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE n.id = NEW.entry_id AND li.login_id = NEW.login_id AND li.id <> NEW.id;
+
+	UPDATE GameCooccurrence SET players = players + 1
+		WHERE game_id = (SELECT game_id FROM Entry WHERE id = NEW.entry_id)
+		AND other_id IN (SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = NEW.login_id AND li.id <> NEW.id);
+	UPDATE GameCooccurrence SET players = players + 1
+		WHERE other_id = (SELECT game_id FROM Entry WHERE id = NEW.entry_id)
+		AND game_id IN (SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = NEW.login_id AND li.id <> NEW.id);
+END;
+
+-- Does the same as remove_cooccurrence in db.sql.
+CREATE TRIGGER IF NOT EXISTS library_cooccurrence_delete BEFORE DELETE
+ON Library FOR EACH ROW
+WHEN NOT EXISTS (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id
+		WHERE li.login_id = OLD.login_id AND li.id <> OLD.id
+		AND e.game_id = (SELECT game_id FROM Entry WHERE id = OLD.entry_id))
+BEGIN
+	UPDATE GamePopularity SET players = players - 1
+		WHERE game_id = (SELECT game_id FROM Entry WHERE id = OLD.entry_id);
+	UPDATE GameCooccurrence SET players = players - 1
+		WHERE game_id = (SELECT game_id FROM Entry WHERE id = OLD.entry_id)
+		AND other_id IN (SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id);
+	UPDATE GameCooccurrence SET players = players - 1
+		WHERE other_id = (SELECT game_id FROM Entry WHERE id = OLD.entry_id)
+		AND game_id IN (SELECT e.game_id FROM Library li JOIN Entry e ON e.id = li.entry_id
+			WHERE li.login_id = OLD.login_id AND li.id <> OLD.id);
+	DELETE FROM GamePopularity WHERE players <= 0;
+	DELETE FROM GameCooccurrence WHERE players <= 0;
+END;
+
+-- Only used by postgres, but kept to have the same schema version.
+CREATE TABLE IF NOT EXISTS RateLimitBucket (
+	key TEXT PRIMARY KEY,
+	tokens REAL NOT NULL,
+	updated_at REAL NOT NULL
+);
+
+-- Failed attempts to log in in a row, by login (`login:<id>`) or by IP
+-- (`ip:<address>`), see `lockout`.
+CREATE TABLE IF NOT EXISTS AuthFailure (
+	key TEXT PRIMARY KEY,
+	failures INT NOT NULL,
+	last_failure TEXT NOT NULL,
+	blocked_until TEXT
+);
+
+-- Secrets mailed to users, used once for `purpose`.
+CREATE TABLE IF NOT EXISTS AccountToken (
+	token TEXT PRIMARY KEY,
+	login_id INT NOT NULL,
+	purpose TEXT NOT NULL,
+	expires_at TEXT NOT NULL,
+	FOREIGN KEY (login_id) REFERENCES Login(id)
+);
+
+-- The audit trail of logging in. Attempts with unknown usernames have no
+-- login.
+CREATE TABLE IF NOT EXISTS AuthEvent (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	login_id INT,
+	username TEXT NOT NULL,
+	ip TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	created_at TEXT NOT NULL,
+	FOREIGN KEY (login_id) REFERENCES Login(id)
+);
+CREATE INDEX IF NOT EXISTS auth_event_login ON AuthEvent (login_id);
+
+-- The email of a login verified with a token mailed to it, which only counts
+-- while the login still has that email.
+CREATE TABLE IF NOT EXISTS EmailVerification (
+	login_id INTEGER PRIMARY KEY,
+	email TEXT NOT NULL COLLATE NOCASE,
+	verified_at TEXT NOT NULL,
+	FOREIGN KEY (login_id) REFERENCES Login(id)
+);
+
+-- Users who asked to be deleted, which they are at `delete_at` unless they
+-- change their mind before. Foreign keys here don't cascade since the tables
+-- can't be altered, so users are deleted table by table.
+CREATE TABLE IF NOT EXISTS PendingDeletion (
+	login_id INTEGER PRIMARY KEY,
+	delete_at TEXT NOT NULL,
+	FOREIGN KEY (login_id) REFERENCES Login(id)
+);
+CREATE INDEX IF NOT EXISTS pending_deletion_at ON PendingDeletion (delete_at);
+
+-- Who can see the library of a login, which is anyone without a row.
+CREATE TABLE IF NOT EXISTS Privacy (
+	login_id INTEGER PRIMARY KEY,
+	library TEXT NOT NULL CHECK (library IN ('Public', 'Friends', 'Private')),
+	FOREIGN KEY (login_id) REFERENCES Login(id)
+);
+
+-- Who a login lets see a library visible to friends. It only goes one way:
+-- adding a friend doesn't let you see their library.
+CREATE TABLE IF NOT EXISTS Friend (
+	login_id INT NOT NULL,
+	friend_id INT NOT NULL,
+	PRIMARY KEY (login_id, friend_id),
+	FOREIGN KEY (login_id) REFERENCES Login(id),
+	FOREIGN KEY (friend_id) REFERENCES Login(id)
+);
+CREATE INDEX IF NOT EXISTS friend_friend ON Friend (friend_id);
+
+-- Links letting anyone who has the token see the library of a login, or only
+-- the entries with `status` or `tag` if set, until it is deleted.
+CREATE TABLE IF NOT EXISTS ShareLink (
+	token TEXT PRIMARY KEY,
+	login_id INT NOT NULL,
+	status TEXT NULL
+		CHECK (status IN ('Frozen', 'CurrentlyPlaying', 'Dropped', 'PlanToPlay')),
+	tag TEXT NULL,
+	created_at TEXT NOT NULL,
+	FOREIGN KEY (login_id) REFERENCES Login(id)
+);
+CREATE INDEX IF NOT EXISTS share_link_login ON ShareLink (login_id);
@@ -0,0 +1,221 @@
+//! Verifying the emails of users and resetting forgotten passwords, both
+//! with tokens mailed to them.
+//!
+//! Every new user is mailed a token to verify their email with:
+//!
+//! ```text
+//! POST /api/verify {"token": "..."}
+//! ```
+//!
+//! which counts for as long as they keep that email. Users who forgot their
+//! password ask for a token and set a new password with it:
+//!
+//! ```text
+//! POST /api/password/reset {"email": "..."}
+//! POST /api/password/reset/confirm {"token": "...", "password": "..."}
+//! ```
+//!
+//! Asking always answers the same whether there is a user with the email or
+//! not, so that it can't be used to find out who has an account.
+//!
+//! Users changing their email have to verify the new one. Users deleting
+//! themselves are deleted right away, or with a grace period at its end by
+//! `spawn_purger` unless they cancel before.
+use std::collections::BTreeMap;
+use std::thread;
+use chrono::{DateTime, Duration, UTC};
+use rustc_serialize::json::ToJson;
+use auth;
+use clock::{Clock, SystemClock};
+use lockout;
+use logging::{LogLevel, Logger};
+use mail::{templates, Mailer, Template};
+use models::{User, UserRows, UserUpdate};
+use storage::{AccountToken, Storage};
+use {Db, LibError};
+
+/// The purpose of the `AccountToken`s mailed to verify emails.
+pub const VERIFY: &'static str = "verify";
+/// The purpose of the `AccountToken`s mailed to reset passwords.
+pub const RESET: &'static str = "reset";
+
+/// The kind of `AuthEvent` kept when a password is reset.
+pub const PASSWORD_RESET: &'static str = "password_reset";
+/// The kind of `AuthEvent` kept when a user changes their password.
+pub const PASSWORD_CHANGED: &'static str = "password_changed";
+
+/// Mails `user` a token to verify their email with, valid for a week.
+/// Tokens mailed before stay valid.
+pub fn send_verification(db: &Storage, mailer: &Mailer, now: DateTime<UTC>, user: &User)
+    -> Result<(), LibError>
+{
+    mail_token(db, mailer, now, user, VERIFY, Duration::days(7), templates::VERIFY)
+}
+
+/// Verifies the email of the user `token` was mailed to and returns them,
+/// unless the token is unknown or expired. Either way the token can't be
+/// used again.
+pub fn verify(db: &Storage, now: DateTime<UTC>, token: &str) -> Result<Option<User>, LibError> {
+    let (id, user) = match try!(take_token(db, now, token, VERIFY)) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    try!(db.verify_email(id, &user.email, now));
+    Ok(Some(user))
+}
+
+/// Mails the user with `email`, if there is one, a token to reset their
+/// password with, valid for an hour.
+pub fn request_reset(db: &Storage, mailer: &Mailer, now: DateTime<UTC>, email: &str)
+    -> Result<(), LibError>
+{
+    match try!(db.user_by_email(email)) {
+        Some(user) => mail_token(db, mailer, now, &user, RESET, Duration::hours(1),
+                                 templates::RESET),
+        None => Ok(()),
+    }
+}
+
+/// Sets the password of the user `token` was mailed to and returns them,
+/// unless the token is unknown or expired. Every other token to reset their
+/// password stops working, and their login is unlocked.
+pub fn reset(db: &Storage, now: DateTime<UTC>, token: &str, password: &str, ip: &str)
+    -> Result<Option<User>, LibError>
+{
+    let (id, user) = match try!(take_token(db, now, token, RESET)) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    try!(db.transaction(&mut |db| {
+        try!(db.set_password(id, password));
+        try!(db.delete_tokens(id, RESET));
+        try!(lockout::forget_failures(db, id));
+        // Reading the mail proves the email as much as verifying it does.
+        try!(db.verify_email(id, &user.email, now));
+        lockout::audit(db, now, Some(&user), &user.username, ip, PASSWORD_RESET)
+    }));
+    Ok(Some(user))
+}
+
+/// Changes the username or email of a user. A new email has to be verified
+/// again, so the tokens mailed to verify the old one stop working.
+pub fn update(db: &Storage, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+    let before = match try!(db.user(id)) {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    let user = try!(db.update_user(id, update));
+    if let Some(ref user) = user {
+        if user.email.to_lowercase() != before.email.to_lowercase() {
+            try!(db.delete_tokens(id, VERIFY));
+        }
+    }
+    Ok(user)
+}
+
+/// Changes the password of `user` if `current` is their password, returning
+/// whether it was. Tokens to reset the password stop working.
+pub fn change_password(db: &Storage, now: DateTime<UTC>, user: &User, current: &str,
+                       password: &str, ip: &str) -> Result<bool, LibError>
+{
+    let id = match try!(db.authenticate(&user.username, current)).and_then(|x| x.id) {
+        Some(id) if Some(id) == user.id => id,
+        _ => return Ok(false),
+    };
+    try!(db.transaction(&mut |db| {
+        try!(db.set_password(id, password));
+        try!(db.delete_tokens(id, RESET));
+        lockout::audit(db, now, Some(user), &user.username, ip, PASSWORD_CHANGED)
+    }));
+    Ok(true)
+}
+
+/// Deletes a user with everything of theirs, returning what was removed if
+/// there was such a user. Their games stop counting as played together as
+/// their library is emptied. Fails, keeping everything, if any row referring
+/// to the user would be left.
+pub fn delete(db: &Storage, id: i32) -> Result<Option<UserRows>, LibError> {
+    let mut removed = None;
+    try!(db.transaction(&mut |db| {
+        let rows = try!(db.user_rows(id));
+        if rows.logins == 0 {
+            return Ok(());
+        }
+        try!(lockout::forget_failures(db, id));
+        try!(db.delete_user(id));
+        let left = try!(db.user_rows(id));
+        if left.total() > 0 {
+            return Err(LibError::Cause(format!("Deleting user {} left {:?}", id, left)));
+        }
+        removed = Some(rows);
+        Ok(())
+    }));
+    Ok(removed)
+}
+
+/// Deletes the users whose grace period is over by `now`, returning their
+/// ids.
+pub fn purge_deletions(db: &Storage, now: DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+    let due = try!(db.due_deletions(now));
+    for &id in due.iter() {
+        try!(delete(db, id));
+    }
+    Ok(due)
+}
+
+/// Runs `purge_deletions` every `every` in a thread of its own, logging what
+/// it deleted.
+pub fn spawn_purger(db: Db, logger: Logger, every: Duration) {
+    thread::spawn(move || loop {
+        let mut fields = BTreeMap::new();
+        match purge_deletions(&**db, SystemClock.now()) {
+            Ok(ref ids) if ids.is_empty() => {},
+            Ok(ids) => {
+                fields.insert("message".to_string(), "deleted users".to_json());
+                fields.insert("user_ids".to_string(), ids.to_json());
+                logger.log(LogLevel::Info, fields);
+            },
+            Err(err) => {
+                fields.insert("message".to_string(),
+                              format!("Failed deleting users: {}", err).to_json());
+                logger.log(LogLevel::Error, fields);
+            },
+        }
+        thread::sleep_ms(every.num_milliseconds() as u32);
+    });
+}
+
+/// Takes the token for `purpose`, returning the id of the user it was
+/// mailed to and the user if it hasn't expired.
+fn take_token(db: &Storage, now: DateTime<UTC>, token: &str, purpose: &str)
+    -> Result<Option<(i32, User)>, LibError>
+{
+    match try!(db.take_token(token, purpose)) {
+        Some(token) if token.expires_at > now => {
+            Ok(try!(db.user(token.login_id)).map(|user| (token.login_id, user)))
+        },
+        _ => Ok(None),
+    }
+}
+
+fn mail_token(db: &Storage, mailer: &Mailer, now: DateTime<UTC>, user: &User, purpose: &str,
+              valid_for: Duration, template: &str) -> Result<(), LibError>
+{
+    let id = try!(user.id.ok_or(LibError::Cause("The user has no id".to_string())));
+    let until = now + valid_for;
+    let token = AccountToken {
+        token: try!(auth::random_token()),
+        login_id: id,
+        purpose: purpose.to_string(),
+        expires_at: until,
+    };
+    try!(db.add_token(&token));
+
+    let template = try!(Template::parse(template));
+    let message = try!(template.render(&user.email, &[
+        ("username", &user.username[..]),
+        ("token", &token.token[..]),
+        ("until", &until.format("%Y-%m-%d %H:%M UTC").to_string()[..]),
+    ]));
+    mailer.send(&message)
+}
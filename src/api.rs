@@ -0,0 +1,188 @@
+//! The `/api` prefix and versions of the API.
+//!
+//! Paths look like `/api/v2/user`, where the version can be left out for the
+//! default version. `Api` strips the prefix before routing and remembers the
+//! version, which `Versions` then uses to pick the router of that version:
+//!
+//! ```rust
+//! let api = Api::new(ApiVersion(1)).version(ApiVersion(2)).deprecate(ApiVersion(1), None);
+//! let mut chain = Chain::new(Versions::new().route(ApiVersion(1), v1).route(ApiVersion(2), v2));
+//! chain.link_before(api.clone());
+//! chain.link_after(api);
+//! ```
+use std::fmt;
+use std::str::FromStr;
+use iron::prelude::*;
+use iron::{status, AfterMiddleware, BeforeMiddleware, Handler};
+use plugin::Extensible;
+use typemap;
+use LibError;
+
+/// A version of the API, written `v1`, `v2` and so on in paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVersion(pub u32);
+
+impl typemap::Key for ApiVersion {
+    type Value = ApiVersion;
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<ApiVersion, LibError> {
+        if s.starts_with("v") {
+            if let Ok(n) = s[1..].parse() {
+                return Ok(ApiVersion(n));
+            }
+        }
+        Err(LibError::Cause(format!("Not an API version: {}", s)))
+    }
+}
+
+/// Strips `/api` and the version from the path of every request, leaving
+/// the version in the extensions of the request. Paths without the prefix
+/// or with an unknown version are not found.
+///
+/// Linked as an `AfterMiddleware` as well it adds deprecation headers to the
+/// responses of deprecated versions, pointing to the latest version.
+#[derive(Debug, Clone)]
+pub struct Api {
+    default: ApiVersion,
+    versions: Vec<ApiVersion>,
+    deprecated: Vec<(ApiVersion, Option<String>)>,
+}
+
+impl Api {
+    /// An API with only the `default` version, which is also used for paths
+    /// without a version.
+    pub fn new(default: ApiVersion) -> Api {
+        Api {
+            default: default,
+            versions: vec![default],
+            deprecated: vec![],
+        }
+    }
+
+    pub fn version(mut self, version: ApiVersion) -> Api {
+        if !self.versions.contains(&version) {
+            self.versions.push(version);
+        }
+        self
+    }
+
+    /// Marks `version` as deprecated, optionally with the HTTP date after
+    /// which it will be removed.
+    pub fn deprecate(mut self, version: ApiVersion, sunset: Option<&str>) -> Api {
+        self.deprecated.push((version, sunset.map(|x| x.to_string())));
+        self
+    }
+
+    pub fn latest(&self) -> ApiVersion {
+        *self.versions.iter().max().unwrap()
+    }
+}
+
+impl BeforeMiddleware for Api {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        if req.url.path.first().map(|x| &x[..]) != Some("api") {
+            return Err(IronError::new(LibError::Cause("Lacking api prefix".to_string()),
+                                      status::NotFound));
+        }
+
+        let (version, prefix) = match req.url.path.get(1).map(|x| x.parse::<ApiVersion>()) {
+            Some(Ok(version)) => (version, 2),
+            _ => (self.default, 1),
+        };
+        if !self.versions.contains(&version) {
+            return Err(IronError::new(
+                    LibError::Cause(format!("Unknown API version {}", version)),
+                    status::NotFound));
+        }
+
+        let mut path = req.url.path[prefix..].to_vec();
+        if path.is_empty() {
+            path.push(String::new());
+        }
+        req.url.path = path;
+        req.extensions_mut().insert::<ApiVersion>(version);
+        Ok(())
+    }
+}
+
+impl Api {
+    fn deprecation_headers(&self, req: &Request, res: &mut Response) {
+        let version = match req.api_version() {
+            Some(version) => version,
+            None => return,
+        };
+        if let Some(&(_, ref sunset)) = self.deprecated.iter().find(|x| x.0 == version) {
+            res.headers.set_raw("Deprecation", vec![b"true".to_vec()]);
+            if let Some(ref sunset) = *sunset {
+                res.headers.set_raw("Sunset", vec![sunset.clone().into_bytes()]);
+            }
+            let successor = format!("</api/{}/{}>; rel=\"successor-version\"",
+                                    self.latest(), req.url.path.connect("/"));
+            res.headers.set_raw("Link", vec![successor.into_bytes()]);
+        }
+    }
+}
+
+impl AfterMiddleware for Api {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        self.deprecation_headers(req, &mut res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        self.deprecation_headers(req, &mut err.response);
+        Err(err)
+    }
+}
+
+/// Hands every request to the handler of the version set by `Api`.
+pub struct Versions {
+    handlers: Vec<(ApiVersion, Box<Handler>)>,
+}
+
+impl Versions {
+    pub fn new() -> Versions {
+        Versions {
+            handlers: vec![],
+        }
+    }
+
+    pub fn route<H: Handler>(mut self, version: ApiVersion, handler: H) -> Versions {
+        self.handlers.push((version, Box::new(handler)));
+        self
+    }
+}
+
+impl Handler for Versions {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let version = req.api_version();
+        match self.handlers.iter().find(|x| Some(x.0) == version) {
+            Some(&(_, ref handler)) => handler.handle(req),
+            None => Err(IronError::new(LibError::Cause("No such API version".to_string()),
+                                       status::NotFound)),
+        }
+    }
+}
+
+/// Provides an extension method for `Request`s to get the version of the API
+/// they were made to.
+pub trait GetApiVersion {
+    fn api_version(&self) -> Option<ApiVersion>;
+}
+
+impl<'a> GetApiVersion for Request<'a> {
+    #[inline]
+    fn api_version(&self) -> Option<ApiVersion> {
+        self.extensions().get::<ApiVersion>().cloned()
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::Read;
+use iron::prelude::*;
+use iron::{headers, status};
+use plugin::Extensible;
+use rustc_serialize::hex::ToHex;
+use typemap;
+use clock::GetClock;
+use lockout::Lockout;
+use mail::GetMailer;
+use models::User;
+use storage::Storage;
+use {BeforeMiddleware, GetDb, LibError, OnError};
+
+/// Identifies the user making the request from HTTP basic auth credentials.
+///
+/// Requests without credentials are let through anonymously, while requests
+/// with credentials that don't match a `Login` are rejected as unauthorized.
+/// Too many of those and attempts are refused before even checking the
+/// password, see `lockout`. Needs to be linked after `DbConnection`,
+/// `ClockMiddleware` and `MailerMiddleware`.
+pub struct Authenticate {
+    lockout: Lockout,
+}
+
+impl Authenticate {
+    pub fn new(lockout: Lockout) -> Authenticate {
+        Authenticate {
+            lockout: lockout,
+        }
+    }
+}
+
+impl typemap::Key for Authenticate {
+    type Value = User;
+}
+
+impl BeforeMiddleware for Authenticate {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let (username, password) = match req.headers.get::<headers::Authorization<headers::Basic>>() {
+            Some(&headers::Authorization(ref basic)) =>
+                (basic.username.clone(), basic.password.clone().unwrap_or(String::new())),
+            None => return Ok(()),
+        };
+
+        let ip = req.remote_addr.ip().to_string();
+        let now = req.now();
+        let db = req.db();
+        let named = try_iron!(db.user_by_name(&username));
+        if let Some(refusal) = try_iron!(self.lockout.check(&**db, now, &username,
+                                                             named.as_ref(), &ip)) {
+            return Err(refusal.to_error(now));
+        }
+
+        match try_iron!(db.authenticate(&username, &password)) {
+            Some(user) => {
+                try_iron!(self.lockout.succeeded(&**db, now, &user, &ip));
+                req.extensions_mut().insert::<Authenticate>(user);
+                Ok(())
+            },
+            None => {
+                try_iron!(self.lockout.failed(&**db, &**req.mailer(), now, &username,
+                                              named.as_ref(), &ip));
+                Err(LibError::Cause("Invalid credentials".to_string()))
+                    .on_err(status::Unauthorized)
+            },
+        }
+    }
+}
+
+/// Provides an extension method for `Request`s to get the user that was
+/// authenticated by the `Authenticate` middleware, if any.
+pub trait CurrentUser {
+    fn current_user(&self) -> Option<&User>;
+}
+
+impl<'a> CurrentUser for Request<'a> {
+    #[inline]
+    fn current_user(&self) -> Option<&User> {
+        self.extensions().get::<Authenticate>()
+    }
+}
+
+/// Returns a new secret to mail to a user, as 64 hex digits.
+pub fn random_token() -> Result<String, LibError> {
+    let mut bytes = vec![];
+    let file = try!(File::open("/dev/urandom").map_err(LibError::other));
+    try!(file.take(32).read_to_end(&mut bytes).map_err(LibError::other));
+    if bytes.len() < 32 {
+        return Err(LibError::Cause("Not enough randomness for a token".to_string()));
+    }
+    Ok(bytes.to_hex())
+}
@@ -1,206 +1,96 @@
 extern crate iron;
-extern crate router;
-extern crate bodyparser;
-#[macro_use] extern crate backlogrs;
-extern crate time;
-extern crate "rustc-serialize" as rustc_serialize;
-
-use backlogrs::*;
-use backlogrs::models::*;
+extern crate chrono;
+extern crate backlogrs;
+
+use backlogrs::{account, handlers, DbConnection, DEFAULT_DATABASE};
+use backlogrs::handlers::Config;
+use backlogrs::logging::{LogLevel, Logger};
+use backlogrs::mail;
+use backlogrs::ratelimit::{PgRateLimitStore, RateLimitStore, RateLimits};
+use chrono::Duration;
 use iron::prelude::*;
-use router::Router;
+use std::env;
+use std::sync::Arc;
 
 fn main() {
-    let mut router = Router::new();
-    router.get("/user", get_users);
-    // Users aren't allowed to update; as soon as a user
-    // is created it is stuck that way. At least for now.
-    router.post("/user", post_login);
-    router.get("/user/:id", get_user_by_id);
-    router.get("/user/:id/library", get_library);
-    router.get("/user/:uid/library/:eid", get_entry);
-    router.post("/user/:id/library", post_entry);
-    router.get("/game", get_games);
-    router.get("/game/:id", get_game_by_id);
-    router.get("/status", get_status);
-
-    let mut chain = Chain::new(router);
-    chain.link_before(Api);
-    chain.link_before(DbConnection::new());
-    // Prints the error in html body
-    chain.link_after(DebugIronError);
+    let database = database();
+    let logger = Logger::new(log_level());
+    let config = Config::new()
+        .logger(logger.clone())
+        .mailer(mail::open(&mail_url(), &mail_from(), logger.clone()).unwrap())
+        .rate_limits(rate_limits(&database))
+        .deletion_grace(deletion_grace());
+    let db = DbConnection::open(&database).unwrap();
+    account::spawn_purger(db.storage(), logger, Duration::hours(1));
+    let chain = handlers::chain(db, config);
 
     println!("Listening on port 3000...");
     Iron::new(chain).http("0.0.0.0:3000").unwrap();
 }
 
-fn post_entry(req: &mut Request) -> IronResult<Response> {
-    let e = status::BadRequest;
-    let mut new_entry = try!(req.get::<bodyparser::Struct<Entry>>()
-                             .on_err(e)).unwrap();
-    let user_id = try!(req.get_from_router::<i32>("id").on_err(e));
-
-    let db = req.db();
-    if let Some(entry_id) = new_entry.id {
-        // The entry should be updated
-        let stmt = try_iron!(db.prepare(
-                "SELECT e.* FROM Login lo JOIN Library li ON lo.id = li.login_id \
-                    JOIN Entry e ON e.id = li.entry_id WHERE e.id = $1 AND lo.id = $2"));
-        let prev_entry = try_iron!(stmt.query(&[&entry_id, &user_id]))
-            .collect_sql::<Vec<Entry>>()[0].clone();
-
-        if new_entry.status.is_none() {
-            new_entry.status = prev_entry.status;
-        }
-        if new_entry.time_played.is_none() {
-            new_entry.time_played = prev_entry.time_played;
-        }
-
-        try_iron!(db.execute(
-                "UPDATE Entry e SET status = $3, time_played = $4 WHERE e.id = $1 AND EXISTS \
-                    (SELECT * FROM Library li WHERE li.entry_id = $1 AND li.login_id = $2)",
-                    &[&entry_id, &user_id, &new_entry.status.unwrap(), &new_entry.time_played])
-            => "failed, probably because name/email already exists");
-    } else {
-        if new_entry.game_id.is_none() {
-            return Err(LibError::Cause(
-                    "Both game and entry ID can't be null!".to_string())).on_err(e);
-        }
-        if new_entry.status.is_none() {
-            new_entry.status = Some(Status::PlanToPlay);
-        }
-        if new_entry.time_played.is_none() {
-            new_entry.time_played = Some(0.0);
-        }
-
-        // Insert new entry
-        // Create transaction and commit if everything went as expected
-        let trans = try_iron!(db.transaction());
-        // First create entry and then map that into a library
-        let stmt = try_iron!(trans.prepare(
-                "INSERT INTO Entry (game_id, time_played, status) \
-                    VALUES ($1, $2, $3) RETURNING *"));
-        new_entry = try_iron!(opt: try_iron!(
-                stmt.query(&[&new_entry.game_id, &new_entry.time_played,
-                           &new_entry.status.unwrap()]))
-            .collect_sql::<Vec<Entry>>().pop()
-            => "Failed inserting new entry");
-
-        // Create library entry
-        try_iron!(trans.execute(
-                "INSERT INTO Library (entry_id, login_id) VALUES ($1, $2)",
-                &[&new_entry.id.unwrap(), &user_id]));
-
-        try_iron!(trans.commit());
+/// The database to use, see `storage::open`, which is taken from
+/// `--database <url>` or else the environment variable `BACKLOGRS_DATABASE`.
+/// With `--demo` everything is kept in memory, starting out with the same
+/// data as db.sql, so no database is needed.
+fn database() -> String {
+    let args = env::args().collect::<Vec<String>>();
+    if args.iter().any(|x| x == "--demo") {
+        return "memory:demo".to_string();
     }
-
-    Ok(Response::with((status::Ok, Json(new_entry))))
-}
-
-fn post_login(req: &mut Request) -> IronResult<Response> {
-    let login = try!(req.get::<bodyparser::Struct<Login>>()
-                     .on_err(status::BadRequest)).unwrap();
-
-    let db = req.db();
-    let stmt = try_iron!(db.prepare(
-            "INSERT INTO Login (username, password, email) \
-                VALUES ($1, $2, $3) RETURNING id, username, NULL, email"));
-    let user = try_iron!(stmt.query(
-            &[&login.username, &login.password, &login.email]))
-        .collect_sql::<Vec<User>>().pop();
-
-    Ok(Response::with((status::Ok, Json(user))))
-}
-
-fn get_status(req: &mut Request) -> IronResult<Response> {
-    let db = req.db();
-    let stmt = try_iron!(db.prepare("SELECT unnest(enum_range(NULL::Status))"));
-    let res = try_iron!(stmt.query(&[])).iter().map(|x| {
-        x.get(0)
-    }).collect::<Vec<Status>>();
-
-    Ok(Response::with((status::Ok, Json(res))))
-}
-
-fn get_game_by_id(req: &mut Request) -> IronResult<Response> {
-    let id = try!(req.get_from_router::<i32>("id")
-                  .on_err(status::BadRequest));
-
-    let db = req.db();
-    let stmt = try_iron!(db.prepare("SELECT * FROM Game WHERE id = $1"));
-    let mut res = try_iron!(stmt.query(&[&id])).collect_sql::<Vec<Game>>();
-
-    if res.is_empty() {
-        Ok(Response::with(status::NoContent))
-    } else {
-        Ok(Response::with((status::Ok, Json(res.pop()))))
+    match args.iter().position(|x| x == "--database").and_then(|i| args.get(i + 1)) {
+        Some(url) => url.clone(),
+        None => env::var("BACKLOGRS_DATABASE").unwrap_or(DEFAULT_DATABASE.to_string()),
     }
 }
 
-fn get_games(req: &mut Request) -> IronResult<Response> {
-    let db = req.db();
-    let stmt = try_iron!(db.prepare("SELECT * FROM Game ORDER BY name"));
-    let res = try_iron!(stmt.query(&[])).collect_sql::<Vec<Game>>();
-
-    Ok(Response::with((status::Ok, Json(res))))
+/// How much to log, taken from `--log <level>` or else the environment
+/// variable `BACKLOGRS_LOG`, see `LogLevel`. Requests are logged by default.
+fn log_level() -> LogLevel {
+    let args = env::args().collect::<Vec<String>>();
+    let level = match args.iter().position(|x| x == "--log").and_then(|i| args.get(i + 1)) {
+        Some(level) => level.clone(),
+        None => env::var("BACKLOGRS_LOG").unwrap_or("info".to_string()),
+    };
+    level.parse().unwrap()
 }
 
-fn get_entry(req: &mut Request) -> IronResult<Response> {
-    let e = status::BadRequest;
-    let user_id = try!(req.get_from_router::<i32>("uid").on_err(e));
-    let entry_id = try!(req.get_from_router::<i32>("eid").on_err(e));
-
-    let db = req.db();
-    let stmt = try_iron!(db.prepare(
-            "SELECT e.* FROM Login lo JOIN Library li ON lo.id = li.login_id \
-                JOIN Entry e ON e.id = li.entry_id WHERE e.id = $1 AND lo.id = $2"));
-    let mut res = try_iron!(stmt.query(&[&entry_id, &user_id]))
-        .collect_sql::<Vec<Entry>>();
-
-    if res.is_empty() {
-        Ok(Response::with(status::NoContent))
-    } else {
-        Ok(Response::with((status::Ok, Json(res.pop()))))
+/// How to mail users, see `mail::open`, which is taken from `--mail <url>`
+/// or else the environment variable `BACKLOGRS_MAIL`. Mail is logged by
+/// default.
+fn mail_url() -> String {
+    let args = env::args().collect::<Vec<String>>();
+    match args.iter().position(|x| x == "--mail").and_then(|i| args.get(i + 1)) {
+        Some(url) => url.clone(),
+        None => env::var("BACKLOGRS_MAIL").unwrap_or("log".to_string()),
     }
 }
 
-fn get_library(req: &mut Request) -> IronResult<Response> {
-    let user_id = try!(req.get_from_router::<i32>("id")
-                       .on_err(status::BadRequest));
-
-    let db = req.db();
-    let stmt = try_iron!(db.prepare(
-            "SELECT e.* FROM Login lo JOIN Library li ON lo.id = li.login_id \
-                JOIN Entry e ON e.id = li.entry_id WHERE lo.id = $1"));
-    let res = try_iron!(stmt.query(&[&user_id]))
-        .collect_sql::<Vec<Entry>>();
-
-    if res.is_empty() {
-        Ok(Response::with(status::NoContent))
-    } else {
-        Ok(Response::with((status::Ok, Json(res))))
-    }
+/// The address mail is sent from, taken from the environment variable
+/// `BACKLOGRS_MAIL_FROM`.
+fn mail_from() -> String {
+    env::var("BACKLOGRS_MAIL_FROM").unwrap_or("backlogrs@localhost".to_string())
 }
 
-fn get_user_by_id(req: &mut Request) -> IronResult<Response> {
-    let id = try!(req.get_from_router::<i32>("id")
-                  .on_err(status::BadRequest));
-
-    let db = req.db();
-    let stmt = try_iron!(db.prepare("SELECT * FROM Login WHERE id = $1"));
-    let mut res = try_iron!(stmt.query(&[&id])).collect_sql::<Vec<User>>();
+/// How many days users deleting themselves have to change their mind, taken
+/// from `--deletion-grace <days>` or else the environment variable
+/// `BACKLOGRS_DELETION_GRACE`. Users are deleted right away by default.
+fn deletion_grace() -> Duration {
+    let args = env::args().collect::<Vec<String>>();
+    let arg = args.iter().position(|x| x == "--deletion-grace").and_then(|i| args.get(i + 1));
+    let days = match arg {
+        Some(days) => days.clone(),
+        None => env::var("BACKLOGRS_DELETION_GRACE").unwrap_or("0".to_string()),
+    };
+    Duration::days(days.parse().unwrap())
+}
 
-    if res.is_empty() {
-        Ok(Response::with(status::NoContent))
+/// Rate limits are shared through postgres when that is the database, so
+/// that every server behind a load balancer counts the same requests.
+fn rate_limits(database: &str) -> RateLimits {
+    if database.starts_with("postgresql://") || database.starts_with("postgres://") {
+        let store = PgRateLimitStore::new(database).unwrap();
+        RateLimits::new(Arc::new(Box::new(store) as Box<RateLimitStore + Send + Sync>))
     } else {
-        Ok(Response::with((status::Ok, Json(res.pop()))))
+        RateLimits::in_memory()
     }
 }
-
-fn get_users(req: &mut Request) -> IronResult<Response> {
-    let db = req.db();
-    let stmt = try_iron!(db.prepare("SELECT * FROM Login"));
-    let res = try_iron!(stmt.query(&[])).collect_sql::<Vec<User>>();
-
-    Ok(Response::with((status::Ok, Json(res))))
-}
@@ -0,0 +1,100 @@
+//! The time as the API sees it, behind a trait so that tests can move it.
+//!
+//! Handlers read it with `GetClock::now` rather than asking the system:
+//!
+//! ```rust
+//! let clock = FakeClock::new(UTC.ymd(2015, 6, 1).and_hms(12, 0, 0));
+//! let server = TestServer::with_config(storage, Config::new().clock(clock.clone()));
+//! clock.advance(Duration::hours(1));
+//! ```
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Timelike, UTC};
+use iron::prelude::*;
+use iron::BeforeMiddleware;
+use plugin::Extensible;
+use typemap;
+
+pub trait Clock {
+    fn now(&self) -> DateTime<UTC>;
+}
+
+/// The clock shared by every request.
+pub type SharedClock = Arc<Box<Clock + Send + Sync>>;
+
+/// The time of the system, with the precision postgres has.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<UTC> {
+        let now = UTC::now();
+        now.with_nanosecond(now.nanosecond() / 1000 * 1000).unwrap_or(now)
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone)]
+pub struct FakeClock {
+    now: Arc<Mutex<DateTime<UTC>>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<UTC>) -> FakeClock {
+        FakeClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<UTC>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + by;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<UTC> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Makes a clock available to every request through `GetClock`.
+pub struct ClockMiddleware {
+    clock: SharedClock,
+}
+
+impl ClockMiddleware {
+    pub fn new(clock: SharedClock) -> ClockMiddleware {
+        ClockMiddleware {
+            clock: clock,
+        }
+    }
+}
+
+impl typemap::Key for ClockMiddleware {
+    type Value = SharedClock;
+}
+
+impl BeforeMiddleware for ClockMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions_mut().insert::<ClockMiddleware>(self.clock.clone());
+        Ok(())
+    }
+}
+
+/// Provides an extension method for `Request`s to get the time from the
+/// clock of `ClockMiddleware`, or the system without one.
+pub trait GetClock {
+    fn now(&self) -> DateTime<UTC>;
+}
+
+impl<'a> GetClock for Request<'a> {
+    fn now(&self) -> DateTime<UTC> {
+        match self.extensions().get::<ClockMiddleware>() {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+}
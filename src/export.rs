@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use rustc_serialize::json::{self, Json};
+use csv;
+use chrono::{DateTime, Timelike, UTC};
+use models::{AuthEvent, EmailStatus, Entry, Game, PublicUser, ShareLink, Status, User,
+             UtcString, Visibility};
+use lockout;
+use repo::Expand;
+use storage::Storage;
+use LibError;
+
+/// Identifies a JSON document as a backup made by `Backup::create`.
+pub const BACKUP_FORMAT: &'static str = "backlogrs-backup";
+
+/// The version of the backup format written by this version of backlogrs.
+/// Any older version can still be restored.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Identifies a JSON document as an archive made by `Archive::create`.
+pub const ARCHIVE_FORMAT: &'static str = "backlogrs-archive";
+
+/// Timestamps of entries are exported as ISO 8601 in UTC with microseconds,
+/// which is the precision postgres stores them in.
+pub const TIMESTAMP_FORMAT: &'static str = "YYYY-MM-DD\"T\"HH24:MI:SS.US\"Z\"";
+
+/// Formats a timestamp like `TIMESTAMP_FORMAT` does in postgres.
+pub fn format_timestamp(dt: &DateTime<UTC>) -> String {
+    format!("{}.{:06}Z", dt.format("%Y-%m-%dT%H:%M:%S"), dt.nanosecond() / 1000)
+}
+
+/// Parses a timestamp formatted by `format_timestamp`.
+pub fn parse_timestamp(s: &str) -> Result<DateTime<UTC>, LibError> {
+    UtcString::from_str(s).map(|x| x.0).map_err(LibError::other)
+}
+
+/// File formats a library can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Backup,
+}
+
+impl FromStr for Format {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<Format, LibError> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "backup" => Ok(Format::Backup),
+            _ => Err(LibError::Cause(format!("Unknown export format: {}", s))),
+        }
+    }
+}
+
+/// A library entry together with everything it refers to.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct ExportEntry {
+    pub id: i32,
+    pub game: Game,
+    pub time_played: f32,
+    pub status: Status,
+    pub last_update: String,
+    pub tags: Vec<String>,
+}
+
+from_sql_row!(ExportEntry {
+    id,
+    game: nested(Game),
+    time_played,
+    status,
+    last_update,
+    tags: skip,
+});
+
+/// A complete and self-describing copy of a library, which can be restored
+/// to any user on any instance. Anyone who can see the library can make one,
+/// so only the public part of the user is kept.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub user: PublicUser,
+    pub entries: Vec<ExportEntry>,
+}
+
+/// Everything kept about a user, for them to download. Secrets are left
+/// out: the password, and of the tokens mailed to them only what they were
+/// for.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Archive {
+    pub format: String,
+    pub exported_at: String,
+    pub user: User,
+    pub email: EmailStatus,
+    /// With when every entry last changed, which is all the history kept of
+    /// a library.
+    pub library: Vec<ExportEntry>,
+    pub tokens: Vec<ArchivedToken>,
+    /// Newest first.
+    pub auth_events: Vec<AuthEvent>,
+    /// Failed attempts to log in in a row, if any are kept.
+    pub auth_failures: Option<ArchivedFailures>,
+    /// When the user is to be deleted, if they asked to be.
+    pub delete_at: Option<String>,
+    pub library_visibility: Visibility,
+    pub friends: Vec<PublicUser>,
+    pub share_links: Vec<ShareLink>,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct ArchivedToken {
+    pub purpose: String,
+    pub expires_at: String,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct ArchivedFailures {
+    pub failures: i32,
+    pub last_failure: String,
+    pub blocked_until: Option<String>,
+}
+
+/// Writes entries as CSV. The columns are compatible with the CSV import, so
+/// the output can be imported as is.
+pub fn to_csv(entries: &[ExportEntry]) -> Result<String, LibError> {
+    let mut writer = csv::Writer::from_memory();
+    try!(writer.encode(("id", "game", "name", "description", "status",
+                        "time_played", "last_update", "tags"))
+         .map_err(LibError::other));
+    for entry in entries.iter() {
+        try!(writer.encode((entry.id, entry.game.id, &entry.game.name, &entry.game.description,
+                            entry.status.to_string(), entry.time_played, &entry.last_update,
+                            entry.tags.connect(";")))
+             .map_err(LibError::other));
+    }
+    Ok(writer.as_string().to_string())
+}
+
+impl Backup {
+    pub fn create(storage: &Storage, user_id: i32) -> Result<Backup, LibError> {
+        let user = try!(try!(storage.user(user_id))
+                        .ok_or(LibError::Cause("No such user".to_string())));
+
+        Ok(Backup {
+            format: BACKUP_FORMAT.to_string(),
+            version: BACKUP_VERSION,
+            exported_at: UTC::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            user: user.public(),
+            entries: try!(storage.export(user_id)),
+        })
+    }
+
+    /// Reads a backup, checking that it really is one and that it isn't from
+    /// a newer version of backlogrs than this one.
+    pub fn from_json(s: &str) -> Result<Backup, LibError> {
+        let json = try!(Json::from_str(s).map_err(LibError::other));
+        if json.find("format").and_then(|x| x.as_string()) != Some(BACKUP_FORMAT) {
+            return Err(LibError::Cause("Not a backlogrs backup".to_string()));
+        }
+        match json.find("version").and_then(|x| x.as_u64()) {
+            Some(v) if v >= 1 && v <= BACKUP_VERSION as u64 => {},
+            Some(v) => return Err(LibError::Cause(
+                    format!("Unsupported backup version {}", v))),
+            None => return Err(LibError::Cause("Backup is missing a version".to_string())),
+        }
+        json::decode(s).map_err(LibError::other)
+    }
+
+    /// Adds every entry of the backup to the library of a user in a single
+    /// transaction, keeping status, time played, last update and tags.
+    ///
+    /// Games are matched by id if the name agrees, otherwise by name, so that
+    /// backups can be moved between instances. Games that can't be found are
+    /// created. Returns the number of restored entries, or `None` without
+    /// restoring anything if the library isn't empty, since restoring twice
+    /// would otherwise add every entry twice.
+    pub fn restore(&self, storage: &Storage, user_id: i32) -> Result<Option<usize>, LibError> {
+        let mut restored = None;
+        try!(storage.transaction(&mut |storage| {
+            if !try!(storage.library(user_id, Expand::default())).is_empty() {
+                return Ok(());
+            }
+
+            let mut game_ids = HashMap::new();
+            for entry in self.entries.iter() {
+                let key = (entry.game.id, entry.game.name.clone());
+                let game_id = match game_ids.get(&key) {
+                    Some(&id) => id,
+                    None => {
+                        let same_id = match entry.game.id {
+                            Some(id) => try!(storage.game(id))
+                                .and_then(|x| if x.name == entry.game.name { x.id } else { None }),
+                            None => None,
+                        };
+                        let id = match same_id {
+                            Some(id) => id,
+                            None => match try!(storage.games_by_name(&entry.game.name)).first() {
+                                Some(game) => game.id.unwrap(),
+                                None => try!(storage.add_game(&entry.game)).id.unwrap(),
+                            },
+                        };
+                        game_ids.insert(key, id);
+                        id
+                    },
+                };
+
+                try!(storage.add_entry(user_id, &Entry {
+                    id: None,
+                    game_id: Some(game_id),
+                    time_played: Some(entry.time_played),
+                    last_update: Some(entry.last_update.clone()),
+                    status: Some(entry.status),
+                    game: None,
+                }, &entry.tags));
+            }
+            restored = Some(self.entries.len());
+            Ok(())
+        }));
+        Ok(restored)
+    }
+}
+
+impl Archive {
+    pub fn create(storage: &Storage, user_id: i32, now: DateTime<UTC>)
+        -> Result<Archive, LibError>
+    {
+        let user = try!(try!(storage.user(user_id))
+                        .ok_or(LibError::Cause("No such user".to_string())));
+        let failures = try!(storage.failures(&lockout::login_key(user_id)));
+
+        Ok(Archive {
+            format: ARCHIVE_FORMAT.to_string(),
+            exported_at: format_timestamp(&now),
+            email: EmailStatus {
+                email: user.email.clone(),
+                verified: try!(storage.email_verified(user_id)),
+            },
+            user: user,
+            library: try!(storage.export(user_id)),
+            tokens: try!(storage.tokens(user_id)).into_iter().map(|x| ArchivedToken {
+                purpose: x.purpose,
+                expires_at: format_timestamp(&x.expires_at),
+            }).collect(),
+            auth_events: try!(storage.auth_events(user_id)),
+            auth_failures: failures.map(|x| ArchivedFailures {
+                failures: x.failures,
+                last_failure: format_timestamp(&x.last_failure),
+                blocked_until: x.blocked_until.map(|x| format_timestamp(&x)),
+            }),
+            delete_at: try!(storage.deletion(user_id)).map(|x| format_timestamp(&x)),
+            library_visibility: try!(storage.library_visibility(user_id)),
+            friends: try!(storage.friends(user_id)).iter().map(User::public).collect(),
+            share_links: try!(storage.share_links(user_id)),
+        })
+    }
+}
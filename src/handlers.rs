@@ -0,0 +1,891 @@
+//! The routes of the API and their handlers.
+use iron::prelude::*;
+use iron::Handler;
+use iron::headers::ContentType;
+use iron::method::Method;
+use router::Router;
+use std::sync::Arc;
+use bodyparser;
+use chrono::Duration;
+use account;
+use auth::{self, Authenticate, CurrentUser};
+use clock::{Clock, ClockMiddleware, GetClock, SharedClock, SystemClock};
+use import::{self, Import};
+use export::{self, Archive, Backup};
+use health;
+use models::*;
+use repo::Expand;
+use storage::Storage;
+use api::{Api, ApiVersion, Versions};
+use limit::{self, BodyLimit};
+use lockout::{self, Lockout, LockoutPolicy};
+use logging::{Log, LogLevel, Logger};
+use mail::{GetMailer, LogMailer, Mailer, MailerMiddleware, SharedMailer};
+use metrics::{Metrics, Route, Routes};
+use negotiate::Negotiate;
+use privacy;
+use ratelimit::RateLimits;
+use validate::GetValid;
+use {DbConnection, DebugIronError, GetDb, GetFromRouter, GetQuery, LibError, OnError};
+use status;
+
+/// Everything the API is built with besides its storage.
+pub struct Config {
+    pub logger: Logger,
+    pub rate_limits: RateLimits,
+    pub clock: SharedClock,
+    pub mailer: SharedMailer,
+    pub lockout: LockoutPolicy,
+    /// How long users deleting themselves have to change their mind.
+    pub deletion_grace: Duration,
+}
+
+impl Config {
+    /// Logs nothing, mails nothing, keeps the default rate limits in memory,
+    /// goes by the system clock and deletes users right away.
+    pub fn new() -> Config {
+        Config {
+            logger: Logger::off(),
+            rate_limits: RateLimits::in_memory(),
+            clock: Arc::new(Box::new(SystemClock) as Box<Clock + Send + Sync>),
+            mailer: Arc::new(Box::new(LogMailer::new(Logger::off())) as Box<Mailer + Send + Sync>),
+            lockout: LockoutPolicy::new(),
+            deletion_grace: Duration::zero(),
+        }
+    }
+
+    pub fn logger(mut self, logger: Logger) -> Config {
+        self.logger = logger;
+        self
+    }
+
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> Config {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Config {
+        self.clock = Arc::new(Box::new(clock) as Box<Clock + Send + Sync>);
+        self
+    }
+
+    pub fn mailer<M: Mailer + Send + Sync + 'static>(mut self, mailer: M) -> Config {
+        self.mailer = Arc::new(Box::new(mailer) as Box<Mailer + Send + Sync>);
+        self
+    }
+
+    pub fn lockout(mut self, lockout: LockoutPolicy) -> Config {
+        self.lockout = lockout;
+        self
+    }
+
+    /// Deletes users this long after they asked, see `account::spawn_purger`.
+    pub fn deletion_grace(mut self, grace: Duration) -> Config {
+        self.deletion_grace = grace;
+        self
+    }
+}
+
+/// Builds the whole API on top of `db`, ready to be served by `Iron`.
+///
+/// Version 1 is what `/api` without a version has always been and is
+/// deprecated in favor of version 2, which answers `404 Not Found` instead of
+/// `204 No Content` for games, users and entries that don't exist. Health
+/// checks and metrics are served outside of the API, without authentication
+/// or rate limits.
+pub fn chain(db: DbConnection, config: Config) -> Chain {
+    let Config { logger, rate_limits, clock, mailer, lockout, deletion_grace } = config;
+    let api = Api::new(ApiVersion(1))
+        .version(ApiVersion(2))
+        .deprecate(ApiVersion(1), None);
+    let versions = Versions::new()
+        .route(ApiVersion(1), routes(&rate_limits, deletion_grace, status::NoContent))
+        .route(ApiVersion(2), routes(&rate_limits, deletion_grace, status::NotFound));
+    let mut api_chain = Chain::new(versions);
+    api_chain.link_before(api.clone());
+    api_chain.link_before(rate_limits.login());
+    api_chain.link_before(Authenticate::new(Lockout::new(lockout)));
+    api_chain.link_before(rate_limits.api());
+    api_chain.link_after(rate_limits.login());
+    api_chain.link_after(rate_limits.api());
+    api_chain.link_after(api);
+
+    let metrics = Metrics::new();
+    let root = Root {
+        api: api_chain,
+        paths: vec![
+            ("/health", Box::new(Route::new("/health", health::health)) as Box<Handler>),
+            ("/ready", Box::new(Route::new("/ready", health::ready)) as Box<Handler>),
+            ("/metrics", Box::new(Route::new("/metrics", metrics.collector())) as Box<Handler>),
+        ],
+    };
+
+    let mut chain = Chain::new(root);
+    // First, so that it times everything and sees the path with the prefix
+    chain.link_before(logger.clone());
+    chain.link_before(metrics.clone());
+    chain.link_before(db);
+    chain.link_before(ClockMiddleware::new(clock));
+    chain.link_before(MailerMiddleware::new(mailer));
+    // Counts errors before they are turned into responses
+    chain.link_after(metrics);
+    // Prints the error in html body
+    chain.link_after(DebugIronError);
+    // Last, to log the response as it is sent
+    chain.link_after(logger);
+    chain
+}
+
+/// Serves the few paths outside of `/api`, which only answer `GET`, and
+/// hands everything else to the API.
+struct Root {
+    api: Chain,
+    paths: Vec<(&'static str, Box<Handler>)>,
+}
+
+impl Handler for Root {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path = format!("/{}", req.url.path.connect("/"));
+        match self.paths.iter().find(|x| x.0 == path) {
+            Some(&(_, ref handler)) if req.method == Method::Get => handler.handle(req),
+            _ => self.api.handle(req),
+        }
+    }
+}
+
+const KB: u64 = 1024;
+const MB: u64 = 1024 * KB;
+
+/// Routes reading a body limit it to what they need with `BodyLimit`, and
+/// routes creating things have stricter rate limits.
+/// `missing` is what is answered for a game, user or entry that doesn't
+/// exist, which is where the versions differ.
+fn routes(rate_limits: &RateLimits, deletion_grace: Duration, missing: status::Status)
+    -> Router
+{
+    let mut router = Router::new();
+    router.get_route("/user", get_users);
+    router.post_route("/user", rate_limits.signup().around(
+            BodyLimit::new(4 * KB).max_depth(2).around(post_login)));
+    router.get_route("/user/:id", move |req: &mut Request| get_user_by_id(req, missing));
+    router.patch_route("/user/:id", BodyLimit::new(KB).max_depth(1).around(patch_user));
+    router.delete_route("/user/:id", move |req: &mut Request| delete_user(req, deletion_grace));
+    router.get_route("/user/:id/deletion", get_deletion);
+    router.get_route("/user/:id/archive", get_archive);
+    router.get_route("/user/:id/privacy", get_privacy);
+    router.put_route("/user/:id/privacy", BodyLimit::new(KB).max_depth(1).around(put_privacy));
+    router.get_route("/user/:id/friends", get_friends);
+    router.post_route("/user/:id/friends", BodyLimit::new(KB).max_depth(1).around(post_friend));
+    router.delete_route("/user/:uid/friends/:fid", delete_friend);
+    router.get_route("/user/:id/shares", get_share_links);
+    router.post_route("/user/:id/shares",
+                      BodyLimit::new(KB).max_depth(1).around(post_share_link));
+    router.delete_route("/user/:uid/shares/:token", delete_share_link);
+    router.get_route("/shared/:token", get_shared_library);
+    router.get_route("/profile/:username", get_profile);
+    router.delete_route("/user/:id/deletion", cancel_deletion);
+    router.post_route("/user/:id/password",
+                      BodyLimit::new(KB).max_depth(1).around(post_password_change));
+    router.get_route("/user/:id/events", get_auth_events);
+    router.post_route("/unlock", BodyLimit::new(KB).max_depth(1).around(post_unlock));
+    router.get_route("/user/:id/email", get_email_status);
+    router.post_route("/user/:id/email/verify", rate_limits.mail().around(resend_verification));
+    router.post_route("/verify", BodyLimit::new(KB).max_depth(1).around(post_verify));
+    router.post_route("/password/reset", rate_limits.mail().around(
+            BodyLimit::new(KB).max_depth(1).around(post_reset_request)));
+    router.post_route("/password/reset/confirm",
+                      BodyLimit::new(KB).max_depth(1).around(post_password_reset));
+    router.get_route("/user/:id/library", move |req: &mut Request| get_library(req, missing));
+    router.get_route("/user/:uid/library/:eid",
+                     move |req: &mut Request| get_entry(req, missing));
+    router.post_route("/user/:id/library", rate_limits.entries().around(
+            BodyLimit::new(4 * KB).max_depth(3).around(
+                move |req: &mut Request| post_entry(req, missing))));
+    router.post_route("/user/:id/library/import",
+                      BodyLimit::new(10 * MB).around(import_library));
+    router.post_route("/user/:id/library/import/backup",
+                      BodyLimit::new(50 * MB).max_depth(4).around(restore_library));
+    router.get_route("/user/:id/library/export", export_library);
+    router.get_route("/game", get_games);
+    router.get_route("/game/:id", move |req: &mut Request| get_game_by_id(req, missing));
+    router.get_route("/game/:id/similar", get_similar_games);
+    router.get_route("/status", get_status);
+    router
+}
+
+/// Updating an entry that isn't in the library answers `missing`.
+fn post_entry(req: &mut Request, missing: status::Status) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let user_id = try!(req.get_from_router::<i32>("id").on_err(e));
+    try!(require_self(req, user_id));
+    let mut new_entry = try!(req.get_valid::<Entry>());
+    // Only ever set by the database
+    new_entry.last_update = None;
+
+    let db = req.db();
+    if let Some(entry_id) = new_entry.id {
+        // The entry should be updated
+        let prev_entry = match try_iron!(db.entry(user_id, entry_id)) {
+            Some(entry) => entry,
+            None => return Ok(Response::with(missing)),
+        };
+
+        if new_entry.status.is_none() {
+            new_entry.status = prev_entry.status;
+        }
+        if new_entry.time_played.is_none() {
+            new_entry.time_played = prev_entry.time_played;
+        }
+
+        // It may have been deleted in the meantime
+        if !try_iron!(db.update_entry(user_id, &new_entry) => "Updating the entry failed") {
+            return Ok(Response::with(missing));
+        }
+    } else {
+        if new_entry.status.is_none() {
+            new_entry.status = Some(Status::PlanToPlay);
+        }
+        if new_entry.time_played.is_none() {
+            new_entry.time_played = Some(0.0);
+        }
+
+        new_entry = try_iron!(db.add_entry(user_id, &new_entry, &[]));
+    }
+
+    req.respond(status::Ok, &new_entry)
+}
+
+/// Adds every entry in a CSV or JSON file to the library of a user, or none
+/// of them if any has errors. With `dry_run=true` nothing is added and only
+/// the report of how the rows matched is returned.
+fn import_library(req: &mut Request) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let user_id = try!(req.get_from_router::<i32>("id").on_err(e));
+    try!(require_self(req, user_id));
+    let dry_run = req.get_query("dry_run").map(|x| x == "true").unwrap_or(false);
+    let format = match req.get_query("format") {
+        Some(format) => try!(format.parse::<import::Format>().on_err(e)),
+        None => match req.headers.get::<ContentType>() {
+            Some(ct) if ct.to_string().starts_with("text/csv") => import::Format::Csv,
+            _ => import::Format::Json,
+        },
+    };
+    let body = try!(req.get::<bodyparser::Raw>().on_err(e)).unwrap_or(String::new());
+    // Brackets in CSV are only text
+    if format.is_json() {
+        try!(limit::check_depth(&body, 3));
+    }
+    let rows = try!(import::parse(format, &body).on_err(e));
+
+    let db = req.db();
+    let import = try_iron!(Import::prepare(&**db, rows));
+    if dry_run {
+        req.respond(status::Ok, import.report())
+    } else if import.report().has_errors() {
+        req.respond(status::UnprocessableEntity, import.report())
+    } else {
+        let report = try_iron!(import.commit(&**db, user_id));
+        req.respond(status::Ok, &report)
+    }
+}
+
+/// Restores a backup made with `format=backup` from `export_library` into
+/// the empty library of a user.
+fn restore_library(req: &mut Request) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let user_id = try!(req.get_from_router::<i32>("id").on_err(e));
+    try!(require_self(req, user_id));
+    let body = try!(req.get::<bodyparser::Raw>().on_err(e)).unwrap_or(String::new());
+    let backup = try!(Backup::from_json(&body).on_err(e));
+
+    let db = req.db();
+    match try_iron!(backup.restore(&**db, user_id)) {
+        Some(restored) => req.respond(status::Ok, &restored),
+        None => Err(LibError::Cause("Backups can only be restored into an empty library"
+                                    .to_string()))
+            .on_err(status::Conflict),
+    }
+}
+
+/// Exports the library of a user as `json` (default), `csv` or as a `backup`
+/// which can be restored through `restore_library`.
+fn export_library(req: &mut Request) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let user_id = try!(req.get_from_router::<i32>("id").on_err(e));
+    let format = match req.get_query("format") {
+        Some(format) => try!(format.parse::<export::Format>().on_err(e)),
+        None => export::Format::Json,
+    };
+    try!(require_library_access(req, user_id));
+
+    let db = req.db();
+    match format {
+        export::Format::Json => {
+            let entries = try_iron!(db.export(user_id));
+            req.respond(status::Ok, &entries)
+        },
+        export::Format::Csv => {
+            let entries = try_iron!(db.export(user_id));
+            let csv = try_iron!(export::to_csv(&entries));
+            let mut res = Response::with((status::Ok, csv));
+            res.headers.set(ContentType("text/csv".parse().unwrap()));
+            Ok(res)
+        },
+        export::Format::Backup => {
+            let backup = try!(Backup::create(&**db, user_id).on_err(status::NotFound));
+            req.respond(status::Ok, &backup)
+        },
+    }
+}
+
+/// Creates a user and mails them a token to verify their email. The user is
+/// created even if the mail can't be sent, they can ask for another.
+fn post_login(req: &mut Request) -> IronResult<Response> {
+    let login = try!(req.get_valid::<Login>());
+
+    let db = req.db();
+    let user = try_iron!(db.add_user(&login));
+    if let Err(err) = account::send_verification(&**db, &**req.mailer(), req.now(), &user) {
+        req.log(LogLevel::Error, &format!("Failed mailing a new user to verify: {}", err));
+    }
+
+    req.respond(status::Ok, &user)
+}
+
+/// Changes the username or email of the current user, which must not belong
+/// to anyone else. A new email is mailed a token to verify it with.
+fn patch_user(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+    let update = try!(req.get_valid::<UserUpdate>());
+
+    let db = req.db();
+    if let Some(ref username) = update.username {
+        if try_iron!(db.user_by_name(username)).map_or(false, |x| x.id != Some(id)) {
+            return Err(LibError::Cause("The username is taken".to_string()))
+                .on_err(status::Conflict);
+        }
+    }
+    if let Some(ref email) = update.email {
+        if try_iron!(db.user_by_email(email)).map_or(false, |x| x.id != Some(id)) {
+            return Err(LibError::Cause("The email is taken".to_string()))
+                .on_err(status::Conflict);
+        }
+    }
+    let before = try_iron!(opt: try_iron!(db.user(id)) => "No such user");
+    let user = try_iron!(opt: try_iron!(account::update(&**db, id, &update)) => "No such user");
+    if user.email.to_lowercase() != before.email.to_lowercase() {
+        if let Err(err) = account::send_verification(&**db, &**req.mailer(), req.now(), &user) {
+            req.log(LogLevel::Error, &format!("Failed mailing a new email to verify: {}", err));
+        }
+    }
+
+    req.respond(status::Ok, &user)
+}
+
+/// Changes the password of the current user, who must know the current one
+/// even though they are logged in.
+fn post_password_change(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+    let change = try!(req.get_valid::<PasswordChange>());
+    let user = try_iron!(opt: req.current_user().cloned() => "Log in to see this");
+    let ip = req.remote_addr.ip().to_string();
+
+    let db = req.db();
+    if try_iron!(account::change_password(&**db, req.now(), &user, &change.current,
+                                          &change.password, &ip)) {
+        Ok(Response::with(status::NoContent))
+    } else {
+        Err(LibError::Cause("Wrong current password".to_string())).on_err(status::Forbidden)
+    }
+}
+
+/// Deletes the current user with their library, answering with how many
+/// rows of every table were removed. With a grace period the user is
+/// deleted at its end instead, answering `202 Accepted` with when.
+fn delete_user(req: &mut Request, grace: Duration) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    if grace == Duration::zero() {
+        let removed = try_iron!(opt: try_iron!(account::delete(&**db, id)) => "No such user");
+        return req.respond(status::Ok, &removed);
+    }
+    let delete_at = req.now() + grace;
+    try_iron!(db.schedule_deletion(id, delete_at));
+    let res = Deletion {
+        delete_at: export::format_timestamp(&delete_at),
+    };
+
+    req.respond(status::Accepted, &res)
+}
+
+/// Everything kept about the current user, see `Archive`.
+fn get_archive(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = try_iron!(Archive::create(&**db, id, req.now()));
+
+    req.respond(status::Ok, &res)
+}
+
+/// When the current user is going to be deleted, if they asked to be.
+fn get_deletion(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let delete_at = try!(try_iron!(db.deletion(id)).ok_or(
+            LibError::Cause("No deletion is pending".to_string())).on_err(status::NotFound));
+    let res = Deletion {
+        delete_at: export::format_timestamp(&delete_at),
+    };
+
+    req.respond(status::Ok, &res)
+}
+
+/// Keeps the current user after all, if their grace period isn't over.
+fn cancel_deletion(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    if try_iron!(db.cancel_deletion(id)) {
+        Ok(Response::with(status::NoContent))
+    } else {
+        Err(LibError::Cause("No deletion is pending".to_string())).on_err(status::NotFound)
+    }
+}
+
+/// Who can see the library of the current user.
+fn get_privacy(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = Privacy {
+        library: try_iron!(db.library_visibility(id)),
+    };
+
+    req.respond(status::Ok, &res)
+}
+
+fn put_privacy(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+    let privacy = try!(req.get_valid::<Privacy>());
+
+    let db = req.db();
+    try_iron!(db.set_library_visibility(id, privacy.library));
+
+    req.respond(status::Ok, &privacy)
+}
+
+/// The users the current user lets see their library when it is visible to
+/// friends.
+fn get_friends(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = try_iron!(db.friends(id)).iter().map(User::public).collect::<Vec<_>>();
+
+    req.respond(status::Ok, &res)
+}
+
+fn post_friend(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+    let new_friend = try!(req.get_valid::<NewFriend>());
+
+    let db = req.db();
+    let friend = try!(try_iron!(db.user_by_name(&new_friend.username))
+                      .ok_or(LibError::Cause("No such user".to_string()))
+                      .on_err(status::NotFound));
+    let friend_id = try_iron!(opt: friend.id => "The user has no id");
+    if friend_id == id {
+        return Err(LibError::Cause("Users can always see their own library".to_string()))
+            .on_err(status::BadRequest);
+    }
+    try_iron!(db.add_friend(id, friend_id));
+
+    req.respond(status::Ok, &friend.public())
+}
+
+fn delete_friend(req: &mut Request) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let id = try!(req.get_from_router::<i32>("uid").on_err(e));
+    let friend_id = try!(req.get_from_router::<i32>("fid").on_err(e));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    if try_iron!(db.remove_friend(id, friend_id)) {
+        Ok(Response::with(status::NoContent))
+    } else {
+        Err(LibError::Cause("No such friend".to_string())).on_err(status::NotFound)
+    }
+}
+
+/// The links sharing the library of the current user, see `privacy`.
+fn get_share_links(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = try_iron!(db.share_links(id));
+
+    req.respond(status::Ok, &res)
+}
+
+fn post_share_link(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+    let new_link = try!(req.get_valid::<NewShareLink>());
+
+    let db = req.db();
+    let link = try_iron!(db.add_share_link(&ShareLink {
+        token: try_iron!(auth::random_token()),
+        login_id: id,
+        status: new_link.status,
+        tag: new_link.tag,
+        created_at: export::format_timestamp(&req.now()),
+    }));
+
+    req.respond(status::Ok, &link)
+}
+
+/// Stops a link from working.
+fn delete_share_link(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("uid")
+                  .on_err(status::BadRequest));
+    let token = try!(path_segment(req, "token"));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    if try_iron!(db.delete_share_link(id, &token)) {
+        Ok(Response::with(status::NoContent))
+    } else {
+        Err(LibError::Cause("No such link".to_string())).on_err(status::NotFound)
+    }
+}
+
+/// What a link shares, to anyone who has it and whoever can see the library
+/// otherwise.
+fn get_shared_library(req: &mut Request) -> IronResult<Response> {
+    let token = try!(path_segment(req, "token"));
+
+    let db = req.db();
+    let res = try!(try_iron!(privacy::shared_library(&**db, &token))
+                   .ok_or(LibError::Cause("No such link".to_string()))
+                   .on_err(status::NotFound));
+
+    req.respond(status::Ok, &res)
+}
+
+/// A user by their username, with their library if the current user can see
+/// it.
+fn get_profile(req: &mut Request) -> IronResult<Response> {
+    let username = try!(path_segment(req, "username"));
+
+    let db = req.db();
+    let user = try!(try_iron!(db.user_by_name(&username))
+                    .ok_or(LibError::Cause("No such user".to_string()))
+                    .on_err(status::NotFound));
+    let id = try_iron!(opt: user.id => "The user has no id");
+    let library = if try_iron!(privacy::can_see_library(&**db, id, req.current_user())) {
+        let library = try_iron!(db.library(id, Expand { game: true, user: false }));
+        Some(library.into_iter().filter_map(|x| x.entry).collect())
+    } else {
+        None
+    };
+    let res = Profile {
+        user: user.public(),
+        library: library,
+    };
+
+    req.respond(status::Ok, &res)
+}
+
+/// A part of the path taken as is, unlike `get_from_router` which parses it.
+fn path_segment(req: &Request, name: &str) -> IronResult<String> {
+    req.extensions.find::<Router>()
+        .and_then(|x| x.find(name))
+        .map(|x| x.to_string())
+        .ok_or(LibError::Cause("Unable to find path".to_string()))
+        .on_err(status::BadRequest)
+}
+
+/// Fails unless the current user can see the library of the user with `id`,
+/// see `privacy`.
+fn require_library_access(req: &Request, id: i32) -> IronResult<()> {
+    let db = req.db();
+    if try_iron!(privacy::can_see_library(&**db, id, req.current_user())) {
+        return Ok(());
+    }
+    match req.current_user() {
+        Some(_) => Err(LibError::Cause("The library is private".to_string()))
+            .on_err(status::Forbidden),
+        None => Err(LibError::Cause("Log in to see this".to_string()))
+            .on_err(status::Unauthorized),
+    }
+}
+
+/// Fails unless the current user is the one with `id`.
+fn require_self(req: &Request, id: i32) -> IronResult<()> {
+    match req.current_user().and_then(|x| x.id) {
+        Some(user_id) if user_id == id => Ok(()),
+        Some(_) => Err(LibError::Cause("Only the user can see this".to_string()))
+            .on_err(status::Forbidden),
+        None => Err(LibError::Cause("Log in to see this".to_string()))
+            .on_err(status::Unauthorized),
+    }
+}
+
+fn email_status(db: &Storage, id: i32) -> Result<Option<EmailStatus>, LibError> {
+    match try!(db.user(id)) {
+        Some(user) => Ok(Some(EmailStatus {
+            email: user.email,
+            verified: try!(db.email_verified(id)),
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Whether the email of a user has been verified, which only the user can
+/// see.
+fn get_email_status(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = try_iron!(opt: try_iron!(email_status(&**db, id)) => "No such user");
+
+    req.respond(status::Ok, &res)
+}
+
+/// Mails the user another token to verify their email with, answering
+/// `202 Accepted`, unless it is verified already.
+fn resend_verification(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = try_iron!(opt: try_iron!(email_status(&**db, id)) => "No such user");
+    if res.verified {
+        return req.respond(status::Ok, &res);
+    }
+    let user = try_iron!(opt: try_iron!(db.user(id)) => "No such user");
+    try_iron!(account::send_verification(&**db, &**req.mailer(), req.now(), &user));
+
+    req.respond(status::Accepted, &res)
+}
+
+/// Verifies an email with the token mailed to it.
+fn post_verify(req: &mut Request) -> IronResult<Response> {
+    let verify = try!(req.get_valid::<Verify>());
+
+    let db = req.db();
+    let user = match try_iron!(account::verify(&**db, req.now(), &verify.token)) {
+        Some(user) => user,
+        None => return Err(LibError::Cause("No such token, or it has expired".to_string()))
+            .on_err(status::BadRequest),
+    };
+    let res = EmailStatus {
+        email: user.email,
+        verified: true,
+    };
+
+    req.respond(status::Ok, &res)
+}
+
+/// Mails a token to reset the password to the user with the email, if any.
+/// Always answers `202 Accepted`, so that it doesn't tell who has an
+/// account.
+fn post_reset_request(req: &mut Request) -> IronResult<Response> {
+    let request = try!(req.get_valid::<ResetRequest>());
+
+    let db = req.db();
+    if let Err(err) = account::request_reset(&**db, &**req.mailer(), req.now(), &request.email) {
+        req.log(LogLevel::Error, &format!("Failed mailing a token to reset a password: {}", err));
+    }
+
+    Ok(Response::with(status::Accepted))
+}
+
+/// Sets a new password with the token mailed to reset it.
+fn post_password_reset(req: &mut Request) -> IronResult<Response> {
+    let reset = try!(req.get_valid::<PasswordReset>());
+    let ip = req.remote_addr.ip().to_string();
+
+    let db = req.db();
+    match try_iron!(account::reset(&**db, req.now(), &reset.token, &reset.password, &ip)) {
+        Some(user) => req.respond(status::Ok, &user),
+        None => Err(LibError::Cause("No such token, or it has expired".to_string()))
+            .on_err(status::BadRequest),
+    }
+}
+
+/// Unlocks a login locked after too many failed attempts to log in, with
+/// the token mailed to its owner.
+fn post_unlock(req: &mut Request) -> IronResult<Response> {
+    let unlock = try!(req.get_valid::<Unlock>());
+    let ip = req.remote_addr.ip().to_string();
+
+    let db = req.db();
+    match try_iron!(lockout::unlock(&**db, req.now(), &unlock.token, &ip)) {
+        Some(user) => req.respond(status::Ok, &user),
+        None => Err(LibError::Cause("No such token, or it has expired".to_string()))
+            .on_err(status::BadRequest),
+    }
+}
+
+/// The audit trail of logging in to a user, newest first, which only the
+/// user can see.
+fn get_auth_events(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    try!(require_self(req, id));
+
+    let db = req.db();
+    let res = try_iron!(db.auth_events(id));
+
+    req.respond(status::Ok, &res)
+}
+
+fn get_status(req: &mut Request) -> IronResult<Response> {
+    let db = req.db();
+    let res = try_iron!(db.statuses());
+
+    req.respond(status::Ok, &res)
+}
+
+fn get_game_by_id(req: &mut Request, missing: status::Status) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+
+    let db = req.db();
+    match try_iron!(db.game(id)) {
+        None => Ok(Response::with(missing)),
+        Some(game) => req.respond(status::Ok, &game),
+    }
+}
+
+/// Lists the games most often found in the same libraries as the given
+/// game, leaving out those already in the library of the current user.
+fn get_similar_games(req: &mut Request) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    let user_id = req.current_user().and_then(|x| x.id);
+
+    let db = req.db();
+    let res = try_iron!(db.similar_games(id, user_id, 10));
+
+    req.respond(status::Ok, &res)
+}
+
+fn get_games(req: &mut Request) -> IronResult<Response> {
+    let db = req.db();
+    let res = try_iron!(db.games());
+
+    req.respond(status::Ok, &res)
+}
+
+/// Reads which nested models to include in a response from the comma
+/// separated `expand` query parameter.
+fn get_expand(req: &Request) -> Result<Expand, LibError> {
+    let mut expand = Expand::default();
+    for x in req.get_query("expand").unwrap_or(String::new()).split(',') {
+        match x.trim() {
+            "game" => expand.game = true,
+            "user" => expand.user = true,
+            "" => {},
+            x => return Err(LibError::Cause(format!("Can't expand {}", x))),
+        }
+    }
+    Ok(expand)
+}
+
+/// With `expand=user` the `Library` row of the entry is returned instead,
+/// with both the user and the entry nested.
+fn get_entry(req: &mut Request, missing: status::Status) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let user_id = try!(req.get_from_router::<i32>("uid").on_err(e));
+    let entry_id = try!(req.get_from_router::<i32>("eid").on_err(e));
+    let expand = try!(get_expand(req).on_err(e));
+    try!(require_library_access(req, user_id));
+
+    let db = req.db();
+    match try_iron!(db.library_entry(user_id, entry_id, expand)) {
+        None => Ok(Response::with(missing)),
+        Some(library) => if expand.user {
+            req.respond(status::Ok, &library)
+        } else {
+            req.respond(status::Ok, &library.entry)
+        },
+    }
+}
+
+/// With `expand=user` the `Library` rows are returned instead of only the
+/// entries, with both the user and the entry nested. An empty library is
+/// `204 No Content`, whereas the library of a user that doesn't exist is
+/// `missing`.
+fn get_library(req: &mut Request, missing: status::Status) -> IronResult<Response> {
+    let e = status::BadRequest;
+    let user_id = try!(req.get_from_router::<i32>("id").on_err(e));
+    let expand = try!(get_expand(req).on_err(e));
+    if try_iron!(req.db().user(user_id)).is_none() {
+        return Ok(Response::with(missing));
+    }
+    try!(require_library_access(req, user_id));
+
+    let db = req.db();
+    let res = try_iron!(db.library(user_id, expand));
+
+    if res.is_empty() {
+        Ok(Response::with(status::NoContent))
+    } else if expand.user {
+        req.respond(status::Ok, &res)
+    } else {
+        let entries = res.into_iter().filter_map(|x| x.entry).collect::<Vec<Entry>>();
+        req.respond(status::Ok, &entries)
+    }
+}
+
+/// Users see all of themselves, and only the `PublicUser` of anyone else.
+fn get_user_by_id(req: &mut Request, missing: status::Status) -> IronResult<Response> {
+    let id = try!(req.get_from_router::<i32>("id")
+                  .on_err(status::BadRequest));
+    let is_self = req.current_user().and_then(|x| x.id) == Some(id);
+
+    let db = req.db();
+    match try_iron!(db.user(id)) {
+        None => Ok(Response::with(missing)),
+        Some(ref user) if is_self => req.respond(status::Ok, user),
+        Some(user) => req.respond(status::Ok, &user.public()),
+    }
+}
+
+fn get_users(req: &mut Request) -> IronResult<Response> {
+    let db = req.db();
+    let res = try_iron!(db.users()).iter().map(User::public).collect::<Vec<_>>();
+
+    req.respond(status::Ok, &res)
+}
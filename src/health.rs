@@ -0,0 +1,91 @@
+//! Whether the server is up, for orchestrators and load balancers.
+//!
+//! `/health` answers as long as the process does, while `/ready` only says
+//! `200 OK` when requests can actually be served, and `503 Service
+//! Unavailable` with what is wrong otherwise:
+//!
+//! ```text
+//! {"ready":false,"checks":[{"name":"database","ok":false,"detail":"..."},...]}
+//! ```
+//!
+//! Both live outside of `/api` and need no credentials.
+use iron::prelude::*;
+use iron::status;
+use storage::{Storage, SCHEMA_VERSION};
+use {GetDb, Json};
+
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct Health {
+    pub status: String,
+}
+
+/// The outcome of one of the checks of `/ready`.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, ok: bool, detail: String) -> Check {
+        Check {
+            name: name.to_string(),
+            ok: ok,
+            detail: detail,
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+/// Checks that a connection to the storage can be had and that its schema
+/// is the one this build expects, with every migration applied.
+pub fn readiness(db: &Storage) -> Readiness {
+    let mut checks = vec![];
+    match db.ping() {
+        Ok(()) => checks.push(Check::new("database", true, "reachable".to_string())),
+        Err(err) => checks.push(Check::new("database", false, err.to_string())),
+    }
+
+    match db.migrations() {
+        Ok(applied) => {
+            let version = applied.iter().cloned().max().unwrap_or(0);
+            checks.push(Check::new("schema", version == SCHEMA_VERSION,
+                format!("version {}, expected {}", version, SCHEMA_VERSION)));
+            let missing = (1..SCHEMA_VERSION + 1)
+                .filter(|x| !applied.contains(x))
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            checks.push(if missing.is_empty() {
+                Check::new("migrations", true, format!("all {} applied", SCHEMA_VERSION))
+            } else {
+                Check::new("migrations", false, format!("missing {}", missing.connect(", ")))
+            });
+        },
+        Err(err) => {
+            checks.push(Check::new("schema", false, err.to_string()));
+            checks.push(Check::new("migrations", false, "unknown without the schema".to_string()));
+        },
+    }
+
+    Readiness {
+        ready: checks.iter().all(|x| x.ok),
+        checks: checks,
+    }
+}
+
+pub fn health(_: &mut Request) -> IronResult<Response> {
+    Ok(Response::with((status::Ok, Json(Health { status: "ok".to_string() }))))
+}
+
+/// Needs to be linked after `DbConnection`.
+pub fn ready(req: &mut Request) -> IronResult<Response> {
+    let readiness = readiness(&**req.db());
+    let status = if readiness.ready { status::Ok } else { status::ServiceUnavailable };
+    Ok(Response::with((status, Json(readiness))))
+}
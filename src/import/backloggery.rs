@@ -0,0 +1,38 @@
+use std::ascii::AsciiExt;
+use models::Status;
+use LibError;
+use super::{GameRef, Importer, ParsedRow, read_csv, to_row};
+
+/// Reads CSV exports from Backloggery, which have the columns `Name`,
+/// `System`, `Status` and `Now Playing`.
+///
+/// Backloggery tracks completion rather than progress, so `Unfinished` games
+/// are planned to be played, `Null` games are dropped and beaten, completed
+/// and mastered games are frozen. Anything marked as now playing is currently
+/// played regardless of completion. The system is kept as a tag.
+pub struct BackloggeryImporter;
+
+impl Importer for BackloggeryImporter {
+    fn parse(&self, input: &str) -> Result<Vec<ParsedRow>, LibError> {
+        read_csv(input, &["Name", "Status"], |record| {
+            let mut errors = vec![];
+            let game = record.get("Name").map(|x| GameRef::Name(x.to_string()));
+            let now_playing = record.get("Now Playing")
+                .map(|x| x == "1" || x.eq_ignore_ascii_case("yes"))
+                .unwrap_or(false);
+            let status = match record.get("Status") {
+                _ if now_playing => Some(Status::CurrentlyPlaying),
+                Some("Unfinished") | Some("Wishlist") | None => Some(Status::PlanToPlay),
+                Some("Null") => Some(Status::Dropped),
+                Some("Beaten") | Some("Completed") | Some("Mastered") => Some(Status::Frozen),
+                Some(x) => {
+                    errors.push(format!("Unknown Backloggery status: {}", x));
+                    None
+                },
+            };
+            let tags = record.get("System").map(|x| vec![x.to_string()]).unwrap_or(vec![]);
+
+            to_row(game, status, None, tags, errors)
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use models::Status;
+use LibError;
+use super::{GameRef, Importer, ParsedRow, keep_err, parse_time_played, read_csv, to_row};
+
+/// Reads CSV exports from HowLongToBeat, which have a `Title` column, one
+/// column per list (`Playing`, `Backlog`, `Completed`, `Retired`) marked
+/// with an `X` for the lists a game is on and the time played as
+/// `Progress`, formatted as `hours:minutes[:seconds]`.
+///
+/// Retired games are dropped and completed games frozen. The platform is
+/// kept as a tag.
+pub struct HowLongToBeatImporter;
+
+impl Importer for HowLongToBeatImporter {
+    fn parse(&self, input: &str) -> Result<Vec<ParsedRow>, LibError> {
+        read_csv(input, &["Title"], |record| {
+            let mut errors = vec![];
+            let game = record.get("Title").map(|x| GameRef::Name(x.to_string()));
+            let on_list = |list: &str| record.get(list).is_some();
+            let status = if on_list("Playing") {
+                Status::CurrentlyPlaying
+            } else if on_list("Retired") {
+                Status::Dropped
+            } else if on_list("Completed") {
+                Status::Frozen
+            } else {
+                Status::PlanToPlay
+            };
+            let time_played = match record.get("Progress").map(parse_progress) {
+                Some(Some(x)) => keep_err(parse_time_played(x), &mut errors),
+                Some(None) => {
+                    errors.push("Progress should be formatted as hours:minutes".to_string());
+                    None
+                },
+                None => None,
+            };
+            let tags = record.get("Platform").map(|x| vec![x.to_string()]).unwrap_or(vec![]);
+
+            to_row(game, Some(status), time_played, tags, errors)
+        })
+    }
+}
+
+/// Parses `hours:minutes[:seconds]` into hours.
+fn parse_progress(s: &str) -> Option<f64> {
+    let parts = s.split(':').map(|x| x.trim().parse::<u32>().ok()).collect::<Option<Vec<_>>>();
+    match parts {
+        Some(ref x) if x.len() == 2 || x.len() == 3 =>
+            Some(x.iter().zip([1.0, 60.0, 3600.0].iter()).fold(0.0, |acc, (&x, d)| acc + x as f64 / d)),
+        _ => None,
+    }
+}
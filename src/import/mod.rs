@@ -0,0 +1,305 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::str::FromStr;
+use csv;
+use models::{Entry, Status};
+use storage::Storage;
+use LibError;
+
+pub use self::native::{CsvImporter, JsonImporter};
+pub use self::steam::SteamImporter;
+pub use self::backloggery::BackloggeryImporter;
+pub use self::hltb::HowLongToBeatImporter;
+
+mod native;
+mod steam;
+mod backloggery;
+mod hltb;
+
+/// Reads library entries from a file exported by backlogrs or some other
+/// application, mapping its statuses and titles onto ours.
+pub trait Importer {
+    /// Reads every row of `input`. Only errors making the whole file
+    /// unreadable are returned as an `Err`, anything wrong with a single row
+    /// is kept with that row.
+    fn parse(&self, input: &str) -> Result<Vec<ParsedRow>, LibError>;
+}
+
+/// File formats a library can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Steam,
+    Backloggery,
+    HowLongToBeat,
+}
+
+impl Format {
+    /// Whether files of the format are JSON, rather than CSV.
+    pub fn is_json(&self) -> bool {
+        match *self {
+            Format::Json | Format::Steam => true,
+            Format::Csv | Format::Backloggery | Format::HowLongToBeat => false,
+        }
+    }
+
+    pub fn importer(&self) -> Box<Importer> {
+        match *self {
+            Format::Csv => Box::new(CsvImporter),
+            Format::Json => Box::new(JsonImporter),
+            Format::Steam => Box::new(SteamImporter),
+            Format::Backloggery => Box::new(BackloggeryImporter),
+            Format::HowLongToBeat => Box::new(HowLongToBeatImporter),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<Format, LibError> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "steam" => Ok(Format::Steam),
+            "backloggery" => Ok(Format::Backloggery),
+            "howlongtobeat" => Ok(Format::HowLongToBeat),
+            _ => Err(LibError::Cause(format!("Unknown import format: {}", s))),
+        }
+    }
+}
+
+/// Refers to a game either by its id or by its name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameRef {
+    Id(i32),
+    Name(String),
+    /// An id that may be from another instance, as in CSV exports, so it is
+    /// only used if the game with it has the name. Otherwise the game is
+    /// matched by name.
+    IdNamed(i32, String),
+    /// A CSV field that looks like an id, which is used as one if there is
+    /// a game with it. Otherwise the game is matched by the field as a name.
+    IdOrName(i32, String),
+}
+
+/// A library entry as read from an import file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRow {
+    pub game: GameRef,
+    pub status: Option<Status>,
+    pub time_played: Option<f32>,
+    pub tags: Vec<String>,
+}
+
+/// A row read from an import file, or the reasons it couldn't be read.
+pub type ParsedRow = Result<ImportRow, Vec<String>>;
+
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct RowReport {
+    /// One-based index of the row in the file, not counting any header.
+    pub row: usize,
+    pub game_id: Option<i32>,
+    pub errors: Vec<String>,
+}
+
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct ImportReport {
+    pub committed: bool,
+    pub matched: usize,
+    pub unmatched: usize,
+    pub rows: Vec<RowReport>,
+}
+
+impl ImportReport {
+    pub fn has_errors(&self) -> bool {
+        self.rows.iter().any(|x| !x.errors.is_empty())
+    }
+}
+
+/// Reads every row of `input` with the importer for `format`.
+pub fn parse(format: Format, input: &str) -> Result<Vec<ParsedRow>, LibError> {
+    format.importer().parse(input)
+}
+
+/// An import whose rows have been matched against existing games but not
+/// yet written to any library.
+pub struct Import {
+    report: ImportReport,
+    rows: Vec<(i32, ImportRow)>,
+}
+
+impl Import {
+    /// Matches the game of every row and validates the rest of its fields.
+    pub fn prepare(storage: &Storage, rows: Vec<ParsedRow>) -> Result<Import, LibError> {
+        let games = try!(storage.games());
+        let mut ids = HashMap::new();
+        let mut names = HashMap::new();
+        for game in games.iter() {
+            ids.insert(game.id.unwrap(), game.name.to_ascii_lowercase());
+            names.entry(game.name.to_ascii_lowercase()).or_insert(vec![]).push(game.id.unwrap());
+        }
+        let by_name = |name: &str| match names.get(&name.to_ascii_lowercase()) {
+            Some(x) if x.len() == 1 => Ok(x[0]),
+            Some(_) => Err(format!("More than one game is named {}", name)),
+            None => Err(format!("No game named {}", name)),
+        };
+
+        let mut import = Import {
+            report: ImportReport {
+                committed: false,
+                matched: 0,
+                unmatched: 0,
+                rows: vec![],
+            },
+            rows: vec![],
+        };
+        for (i, row) in rows.into_iter().enumerate() {
+            let (game_id, errors) = match row {
+                Ok(row) => {
+                    let game_id = match row.game {
+                        GameRef::Id(id) if ids.contains_key(&id) => Ok(id),
+                        GameRef::Id(id) => Err(format!("No game with id {}", id)),
+                        GameRef::Name(ref name) => by_name(name),
+                        GameRef::IdNamed(id, ref name) => match ids.get(&id) {
+                            Some(x) if *x == name.to_ascii_lowercase() => Ok(id),
+                            _ => by_name(name),
+                        },
+                        GameRef::IdOrName(id, _) if ids.contains_key(&id) => Ok(id),
+                        GameRef::IdOrName(_, ref name) => by_name(name),
+                    };
+                    match game_id {
+                        Ok(id) => {
+                            import.rows.push((id, row));
+                            (Some(id), vec![])
+                        },
+                        Err(e) => (None, vec![e]),
+                    }
+                },
+                Err(errors) => (None, errors),
+            };
+
+            if game_id.is_some() {
+                import.report.matched += 1;
+            } else {
+                import.report.unmatched += 1;
+            }
+            import.report.rows.push(RowReport {
+                row: i + 1,
+                game_id: game_id,
+                errors: errors,
+            });
+        }
+        Ok(import)
+    }
+
+    pub fn report(&self) -> &ImportReport {
+        &self.report
+    }
+
+    /// Adds every row to the library of the user in a single transaction.
+    /// Nothing is written if any row has errors.
+    pub fn commit(mut self, storage: &Storage, user_id: i32) -> Result<ImportReport, LibError> {
+        if self.report.has_errors() {
+            return Err(LibError::Cause("Refusing to import rows with errors".to_string()));
+        }
+
+        {
+            let rows = &self.rows;
+            try!(storage.transaction(&mut |storage| {
+                for &(game_id, ref row) in rows.iter() {
+                    try!(storage.add_entry(user_id, &Entry {
+                        id: None,
+                        game_id: Some(game_id),
+                        time_played: Some(row.time_played.unwrap_or(0.0)),
+                        last_update: None,
+                        status: Some(row.status.unwrap_or(Status::PlanToPlay)),
+                        game: None,
+                    }, &row.tags));
+                }
+                Ok(())
+            }));
+        }
+
+        self.report.committed = true;
+        Ok(self.report)
+    }
+}
+
+/// A CSV row whose fields can be looked up by the name of their column.
+struct CsvRecord<'a> {
+    headers: &'a [String],
+    fields: Vec<String>,
+}
+
+impl<'a> CsvRecord<'a> {
+    /// Returns the trimmed field in the column, ignoring case of the column
+    /// name, or `None` if the field is empty or the column missing.
+    fn get(&self, column: &str) -> Option<&str> {
+        self.headers.iter().position(|x| x.trim().eq_ignore_ascii_case(column))
+            .and_then(|i| self.fields.get(i))
+            .map(|x| x.trim())
+            .and_then(|x| if x.is_empty() { None } else { Some(x) })
+    }
+}
+
+/// Reads a CSV file having a header, which must name the `required` columns.
+fn read_csv<F>(input: &str, required: &[&str], f: F) -> Result<Vec<ParsedRow>, LibError>
+    where F: Fn(&CsvRecord) -> ParsedRow
+{
+    let mut reader = csv::Reader::from_string(input).has_headers(true);
+    let headers = try!(reader.headers().map_err(LibError::other));
+    for column in required.iter() {
+        if !headers.iter().any(|x| x.trim().eq_ignore_ascii_case(column)) {
+            return Err(LibError::Cause(format!("Missing {} column", column)));
+        }
+    }
+
+    Ok(reader.records().map(|record| {
+        let fields = try!(record.map_err(|e| vec![e.to_string()]));
+        f(&CsvRecord { headers: &headers, fields: fields })
+    }).collect())
+}
+
+fn to_row(game: Option<GameRef>, status: Option<Status>, time_played: Option<f32>,
+          tags: Vec<String>, mut errors: Vec<String>) -> ParsedRow {
+    if game.is_none() {
+        errors.push("game is required".to_string());
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(ImportRow {
+        game: game.unwrap(),
+        status: status,
+        time_played: time_played,
+        tags: tags,
+    })
+}
+
+fn keep_err<T>(res: Result<T, String>, errors: &mut Vec<String>) -> Option<T> {
+    match res {
+        Ok(x) => Some(x),
+        Err(e) => {
+            errors.push(e);
+            None
+        },
+    }
+}
+
+fn parse_status(s: &str) -> Result<Status, String> {
+    FromStr::from_str(s).map_err(|_| format!("Unknown status: {}", s))
+}
+
+fn parse_time_played(x: f64) -> Result<f32, String> {
+    if x.is_finite() && x >= 0.0 {
+        Ok(x as f32)
+    } else {
+        Err("time_played must be a non-negative number".to_string())
+    }
+}
+
+fn parse_tags(s: &str) -> Vec<String> {
+    s.split(';').map(|x| x.trim()).filter(|x| !x.is_empty()).map(|x| x.to_string()).collect()
+}
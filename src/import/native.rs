@@ -0,0 +1,101 @@
+use rustc_serialize::json::Json;
+use LibError;
+use super::{GameRef, Importer, ParsedRow, keep_err, parse_status, parse_tags,
+            parse_time_played, read_csv, to_row};
+
+/// Reads CSV files having a header naming the columns, which may come in any
+/// order: `game` (id or name), `status`, `time_played` and `tags` (separated
+/// by `;`). Only `game` is required. A `game` that is a number is taken as a
+/// name if there is no game with it as id. If there is also a `name`, as in
+/// CSV exports, a game id is only used if the game has the name, so that
+/// exports can be imported on other instances.
+pub struct CsvImporter;
+
+impl Importer for CsvImporter {
+    fn parse(&self, input: &str) -> Result<Vec<ParsedRow>, LibError> {
+        read_csv(input, &["game"], |record| {
+            let mut errors = vec![];
+            let game = record.get("game").map(|x| match (x.parse::<i32>(), record.get("name")) {
+                (Ok(id), Some(name)) => GameRef::IdNamed(id, name.to_string()),
+                (Ok(id), None) => GameRef::IdOrName(id, x.to_string()),
+                (Err(_), _) => GameRef::Name(x.to_string()),
+            });
+            let status = record.get("status").and_then(|x| keep_err(parse_status(x), &mut errors));
+            let time_played = match record.get("time_played").map(|x| x.parse::<f64>()) {
+                Some(Ok(x)) => keep_err(parse_time_played(x), &mut errors),
+                Some(Err(_)) => {
+                    errors.push("time_played is not a number".to_string());
+                    None
+                },
+                None => None,
+            };
+            let tags = record.get("tags").map(parse_tags).unwrap_or(vec![]);
+
+            to_row(game, status, time_played, tags, errors)
+        })
+    }
+}
+
+/// Reads JSON files that are an array of objects with the fields `game` (a
+/// number for an id, a string for a name), `status`, `time_played` and `tags`
+/// (an array of strings). Only `game` is required.
+pub struct JsonImporter;
+
+impl Importer for JsonImporter {
+    fn parse(&self, input: &str) -> Result<Vec<ParsedRow>, LibError> {
+        let json = try!(Json::from_str(input).map_err(LibError::other));
+        let rows = try!(json.as_array()
+                        .ok_or(LibError::Cause("Expected an array of entries".to_string())));
+
+        Ok(rows.iter().map(|row| {
+            let row = try!(row.as_object().ok_or(vec!["Expected an object".to_string()]));
+            let field = |name: &str| row.get(name).and_then(|x| if x.is_null() { None } else { Some(x) });
+
+            let mut errors = vec![];
+            let game = match field("game") {
+                Some(&Json::String(ref x)) => Some(GameRef::Name(x.to_string())),
+                Some(x) => match x.as_i64() {
+                    Some(id) if id >= 0 && id <= i32::max_value() as i64 => Some(GameRef::Id(id as i32)),
+                    _ => {
+                        errors.push("game must be an id or a name".to_string());
+                        return Err(errors);
+                    },
+                },
+                None => None,
+            };
+            let status = match field("status").map(|x| x.as_string()) {
+                Some(Some(x)) => keep_err(parse_status(x), &mut errors),
+                Some(None) => {
+                    errors.push("status must be a string".to_string());
+                    None
+                },
+                None => None,
+            };
+            let time_played = match field("time_played").map(|x| x.as_f64()) {
+                Some(Some(x)) => keep_err(parse_time_played(x), &mut errors),
+                Some(None) => {
+                    errors.push("time_played is not a number".to_string());
+                    None
+                },
+                None => None,
+            };
+            let tags = match field("tags").map(|x| x.as_array()) {
+                Some(Some(xs)) => {
+                    let tags = xs.iter().filter_map(|x| x.as_string()).map(|x| x.trim().to_string())
+                        .filter(|x| !x.is_empty()).collect::<Vec<_>>();
+                    if tags.len() != xs.len() {
+                        errors.push("tags must be non-empty strings".to_string());
+                    }
+                    tags
+                },
+                Some(None) => {
+                    errors.push("tags must be an array".to_string());
+                    vec![]
+                },
+                None => vec![],
+            };
+
+            to_row(game, status, time_played, tags, errors)
+        }).collect())
+    }
+}
@@ -0,0 +1,37 @@
+use rustc_serialize::json::Json;
+use models::Status;
+use LibError;
+use super::{GameRef, Importer, ParsedRow, to_row};
+
+/// Reads the JSON returned by the `GetOwnedGames` call of the Steam web API,
+/// called with `include_appinfo=1` so that the names of the games are there.
+///
+/// Steam has no notion of status, so it is guessed from the play time: games
+/// played the last two weeks are currently played, games never played are
+/// planned to be played and the rest are frozen.
+pub struct SteamImporter;
+
+impl Importer for SteamImporter {
+    fn parse(&self, input: &str) -> Result<Vec<ParsedRow>, LibError> {
+        let json = try!(Json::from_str(input).map_err(LibError::other));
+        let games = try!(json.find_path(&["response", "games"]).and_then(|x| x.as_array())
+                         .ok_or(LibError::Cause("Expected response.games to be an array".to_string())));
+
+        Ok(games.iter().map(|game| {
+            let name = try!(game.find("name").and_then(|x| x.as_string())
+                            .ok_or(vec!["Missing name, export with include_appinfo=1".to_string()]));
+            let minutes = game.find("playtime_forever").and_then(|x| x.as_u64()).unwrap_or(0);
+            let recent = game.find("playtime_2weeks").and_then(|x| x.as_u64()).unwrap_or(0);
+
+            let status = if recent > 0 {
+                Status::CurrentlyPlaying
+            } else if minutes == 0 {
+                Status::PlanToPlay
+            } else {
+                Status::Frozen
+            };
+            let game = GameRef::Name(name.trim().to_string());
+            to_row(Some(game), Some(status), Some(minutes as f32 / 60.0), vec![], vec![])
+        }).collect())
+    }
+}
@@ -8,26 +8,34 @@ extern crate r2d2;
 extern crate r2d2_postgres;
 extern crate plugin;
 extern crate typemap;
+extern crate csv;
+extern crate chrono;
+extern crate url;
+extern crate bodyparser;
+extern crate hyper;
+extern crate time;
+extern crate cbor;
+extern crate rmp_serialize;
+#[cfg(feature = "sqlite")] extern crate rusqlite;
 
 use ::std::iter::FromIterator;
 use std::sync::Arc;
-use std::default::Default;
 use std::error::Error;
-use std::fmt::{self, Debug};
+use std::fmt;
 use rustc_serialize::{json, Encodable};
-use r2d2_postgres::PostgresConnectionManager;
-use postgres::SslMode;
 use plugin::Extensible;
 use iron::prelude::*;
 use iron::{headers};
 use router::Router;
 use std::str::FromStr;
+use storage::{MemoryStorage, Storage};
 
 // Reexport BeforeMiddleware for DbConnection so that
 // the user doesn't separately need to import it by themselves.
 pub use iron::BeforeMiddleware;
 pub use iron::status;
 pub use postgres::Row;
+pub use api::{Api, ApiVersion};
 
 #[macro_export]
 macro_rules! try_iron {
@@ -45,14 +53,152 @@ macro_rules! try_iron {
     }};
 }
 
+/// Implements `TryFromSqlRow` for a struct by reading every field from the
+/// column of the same name, so that the order of the columns doesn't matter.
+///
+/// Every field is listed, followed by a comma, and may say how to read it:
+///
+/// - `field: rename("column")` reads the field from another column.
+/// - `field: default` uses `Default::default()` if the column is missing.
+/// - `field: skip` never reads the field, always using its default.
+/// - `field: via(T)` or `field: via(Option<T>)` reads a `T` and converts it
+///   with `From`.
+/// - `field: nested(T)` reads a whole model from the columns starting with
+///   `field_`, or with the prefix given by `nested(T, prefix = "")`.
+/// - `field: nested(Option<T>)` is `None` unless every column of `T` is
+///   there, for models that are only sometimes joined.
+///
+/// Example:
+/// ```rust
+/// from_sql_row!(Entry {
+///     id,
+///     last_update: via(Option<UtcString>),
+///     game: nested(Option<Game>),
+/// });
+/// ```
+#[macro_export]
+macro_rules! from_sql_row {
+    ($model:ident { $($field:ident $(: $how:ident $(($($arg:tt)*))*)*,)* }) => {
+        impl $crate::TryFromSqlRow for $model {
+            fn try_from_sql_row_prefixed<'stmt>(row: &$crate::Row<'stmt>, prefix: &str)
+                -> Result<$model, $crate::LibError>
+            {
+                Ok($model {
+                    $($field: sql_field!(@read row, prefix, $model,
+                                         $field $(: $how $(($($arg)*))*)*),)*
+                })
+            }
+
+            fn sql_columns(prefix: &str) -> Vec<String> {
+                let mut columns = vec![];
+                $(sql_field!(@columns columns, prefix, $field $(: $how $(($($arg)*))*)*);)*
+                columns
+            }
+        }
+    };
+}
+
+/// Reads and lists the columns of a single field for `from_sql_row!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sql_field {
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident) => {
+        try!($crate::sql::column($row, &format!("{}{}", $prefix, stringify!($field)),
+                                 stringify!($model)))
+    };
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: rename($column:expr)) => {
+        try!($crate::sql::column($row, &format!("{}{}", $prefix, $column), stringify!($model)))
+    };
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: default) => {
+        try!($crate::sql::column_or_default($row, &format!("{}{}", $prefix, stringify!($field)),
+                                            stringify!($model)))
+    };
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: skip) => {
+        ::std::default::Default::default()
+    };
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: via(Option<$via:ty>)) => {{
+        let value: Option<$via> = sql_field!(@read $row, $prefix, $model, $field);
+        value.map(::std::convert::From::from)
+    }};
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: via($via:ty)) => {{
+        let value: $via = sql_field!(@read $row, $prefix, $model, $field);
+        ::std::convert::From::from(value)
+    }};
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: nested(Option<$nested:ty>)) => {{
+        let nested = format!("{}{}_", $prefix, stringify!($field));
+        let columns = <$nested as $crate::TryFromSqlRow>::sql_columns(&nested);
+        if columns.iter().all(|x| $crate::sql::has_column($row, x)) {
+            Some(try!(<$nested as $crate::TryFromSqlRow>::try_from_sql_row_prefixed($row, &nested)))
+        } else {
+            None
+        }
+    }};
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident:
+     nested($nested:ty, prefix = $nested_prefix:expr)) => {
+        try!(<$nested as $crate::TryFromSqlRow>::try_from_sql_row_prefixed(
+            $row, &format!("{}{}", $prefix, $nested_prefix)))
+    };
+    (@read $row:ident, $prefix:ident, $model:ident, $field:ident: nested($nested:ty)) => {
+        try!(<$nested as $crate::TryFromSqlRow>::try_from_sql_row_prefixed(
+            $row, &format!("{}{}_", $prefix, stringify!($field))))
+    };
+
+    (@columns $columns:ident, $prefix:ident, $field:ident) => {
+        $columns.push(format!("{}{}", $prefix, stringify!($field)))
+    };
+    (@columns $columns:ident, $prefix:ident, $field:ident: rename($column:expr)) => {
+        $columns.push(format!("{}{}", $prefix, $column))
+    };
+    // Neither is needed, and whether an optional model is there is up to
+    // the columns of its own.
+    (@columns $columns:ident, $prefix:ident, $field:ident: default) => {};
+    (@columns $columns:ident, $prefix:ident, $field:ident: skip) => {};
+    (@columns $columns:ident, $prefix:ident, $field:ident: nested(Option<$nested:ty>)) => {};
+    (@columns $columns:ident, $prefix:ident, $field:ident: via($($via:tt)*)) => {
+        sql_field!(@columns $columns, $prefix, $field)
+    };
+    (@columns $columns:ident, $prefix:ident, $field:ident:
+     nested($nested:ty, prefix = $nested_prefix:expr)) => {
+        $columns.extend(<$nested as $crate::TryFromSqlRow>::sql_columns(
+            &format!("{}{}", $prefix, $nested_prefix)).into_iter())
+    };
+    (@columns $columns:ident, $prefix:ident, $field:ident: nested($nested:ty)) => {
+        $columns.extend(<$nested as $crate::TryFromSqlRow>::sql_columns(
+            &format!("{}{}_", $prefix, stringify!($field))).into_iter())
+    };
+}
+
+pub mod api;
+#[macro_use] pub mod validate;
 pub mod models;
+pub mod clock;
+pub mod limit;
+pub mod logging;
+pub mod metrics;
+pub mod negotiate;
+pub mod ratelimit;
+pub mod auth;
+pub mod account;
+pub mod lockout;
+pub mod privacy;
+pub mod mail;
+pub mod import;
+pub mod export;
+pub mod health;
+pub mod repo;
+pub mod storage;
+pub mod handlers;
+pub mod testing;
 
 pub struct DebugIronError;
 impl iron::AfterMiddleware for DebugIronError {
     fn catch(&self, _: &mut Request, err: IronError) -> IronResult<Response> {
-        // In case of error bubbling up
-        // Ok(err.response)
-        Ok(Response::with(format!("{:?}", err)))
+        // Keep the status of the error, and the body if there is one
+        let mut res = err.response;
+        if res.body.is_none() {
+            res.set_mut(format!("{:?}", err.error));
+        }
+        Ok(res)
     }
 }
 
@@ -76,14 +222,105 @@ impl<'stmt,T: FromSqlRow> CollectSql<T> for postgres::Rows<'stmt> {
     }
 }
 
+/// Like `CollectSql` but stops at the first row that can't be converted,
+/// returning why instead of panicking.
+///
+/// Example:
+/// ```rust
+/// let stmt = db.prepare("SELECT * FROM Person");
+/// let res = try_iron!(try_iron!(stmt.query(&[])).try_collect_sql::<Vec<Person>>());
+/// ```
+pub trait TryCollectSql<T> {
+    fn try_collect_sql<R>(self) -> Result<R, LibError>
+        where R: FromIterator<T>;
+}
+
+impl<'stmt,T: TryFromSqlRow> TryCollectSql<T> for postgres::Rows<'stmt> {
+    fn try_collect_sql<R: FromIterator<T>>(self) -> Result<R, LibError> {
+        self.iter().map(|x| TryFromSqlRow::try_from_sql_row(&x)).collect()
+    }
+}
+
 /// Implement this trait for database models in order for them to be
 /// collectable from a postgres query.
 ///
 /// This is a helper trait for `CollectSql` which adds the extension
 /// method `collect_sql` to the `Rows` gained from database queries
 /// in postgres.
-pub trait FromSqlRow {
-    fn from_sql_row<'stmt>(row: &postgres::Row<'stmt>) -> Self;
+///
+/// Every `TryFromSqlRow` is also a `FromSqlRow`, which panics where the
+/// former would return an error.
+pub trait FromSqlRow: Sized {
+    fn from_sql_row<'stmt>(row: &postgres::Row<'stmt>) -> Self {
+        FromSqlRow::from_sql_row_prefixed(row, "")
+    }
+
+    /// Like `from_sql_row` but with the names of the columns starting with
+    /// `prefix`, for when a join selects several models in the same row.
+    fn from_sql_row_prefixed<'stmt>(row: &postgres::Row<'stmt>, prefix: &str) -> Self;
+}
+
+/// The fallible version of `FromSqlRow`, used by `TryCollectSql`.
+///
+/// Rather than implementing it by hand, `from_sql_row!` reads every field
+/// from the column of the same name.
+pub trait TryFromSqlRow: Sized {
+    fn try_from_sql_row<'stmt>(row: &postgres::Row<'stmt>) -> Result<Self, LibError> {
+        TryFromSqlRow::try_from_sql_row_prefixed(row, "")
+    }
+
+    /// Like `try_from_sql_row` but with the names of the columns starting
+    /// with `prefix`, for when a join selects several models in the same row.
+    fn try_from_sql_row_prefixed<'stmt>(row: &postgres::Row<'stmt>, prefix: &str)
+        -> Result<Self, LibError>;
+
+    /// The names of the columns that need to be selected for the model.
+    fn sql_columns(prefix: &str) -> Vec<String>;
+}
+
+impl<T: TryFromSqlRow> FromSqlRow for T {
+    fn from_sql_row_prefixed<'stmt>(row: &postgres::Row<'stmt>, prefix: &str) -> T {
+        match TryFromSqlRow::try_from_sql_row_prefixed(row, prefix) {
+            Ok(x) => x,
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+/// Helpers for the code generated by `from_sql_row!`.
+#[doc(hidden)]
+pub mod sql {
+    use postgres::{self, FromSql, Row};
+    use LibError;
+
+    pub fn has_column<'stmt>(row: &Row<'stmt>, name: &str) -> bool {
+        // Asking for the wrong type is fine since that is checked after
+        // whether the column exists.
+        match row.get_opt::<_, Option<bool>>(name) {
+            Err(postgres::Error::InvalidColumn) => false,
+            _ => true,
+        }
+    }
+
+    pub fn column<'stmt, T: FromSql>(row: &Row<'stmt>, name: &str, model: &str)
+        -> Result<T, LibError>
+    {
+        row.get_opt(name).map_err(|err| match err {
+            postgres::Error::InvalidColumn => LibError::Cause(
+                format!("{} needs the column {} which wasn't selected", model, name)),
+            err => LibError::Cause(
+                format!("Unable to read column {} of {}: {}", name, model, err)),
+        })
+    }
+
+    pub fn column_or_default<'stmt, T: FromSql + Default>(row: &Row<'stmt>, name: &str,
+                                                          model: &str) -> Result<T, LibError> {
+        if has_column(row, name) {
+            column(row, name, model)
+        } else {
+            Ok(Default::default())
+        }
+    }
 }
 
 
@@ -93,9 +330,28 @@ pub enum LibError {
     Other(Box<err::Error>),
 }
 
+impl LibError {
+    /// Wraps any error; handy with `map_err`.
+    pub fn other<E: err::Error + 'static>(err: E) -> LibError {
+        LibError::Other(Box::new(err))
+    }
+
+    /// The name of the variant, for counting errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            LibError::Cause(_) => "cause",
+            LibError::Other(_) => "other",
+        }
+    }
+}
+
 impl fmt::Display for LibError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Debug::fmt(self, f)
+        use LibError::*;
+        match *self {
+            Cause(ref s) => f.write_str(s),
+            Other(ref err) => f.write_str(err.description()),
+        }
     }
 }
 
@@ -109,30 +365,25 @@ impl Error for LibError {
     }
 }
 
-pub struct Api;
-
-impl BeforeMiddleware for Api {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        if req.url.path[0] != "api" {
-            Err(IronError::new(LibError::Cause("Lacking api prefix".to_string()), iron::status::NotFound))
-        } else {
-            // Remove api prefix and continue
-            req.url.path[0].clear();
-            Ok(())
-        }
-    }
-}
-
-/// A simple wrapper struct for marking a struct as a JSON response.
+/// A simple wrapper struct for marking a struct as a JSON response. See
+/// `negotiate::Negotiate` for responding in the format the client accepts.
 pub struct Json<T: Encodable>(pub T);
 
 impl<T: Encodable> iron::modifier::Modifier<Response> for Json<T> {
     #[inline]
     fn modify(self, res: &mut Response) {
         let Json(x) = self;
-        // Make sure the content type is marked as JSON
-        res.headers.set(headers::ContentType("application/json".parse().unwrap()));
-        res.set_mut(json::encode(&x).unwrap());
+        match json::encode(&x) {
+            Ok(body) => {
+                // Make sure the content type is marked as JSON
+                res.headers.set(headers::ContentType("application/json".parse().unwrap()));
+                res.set_mut(body);
+            },
+            Err(err) => {
+                res.set_mut(status::InternalServerError);
+                res.set_mut(format!("Unable to encode the response: {}", err));
+            },
+        }
     }
 }
 
@@ -152,57 +403,81 @@ impl<T, E: err::Error> OnError<T> for Result<T, E> {
     }
 }
 
-/// Maintains a database connection pool during requests instead of having
-/// to open and close the database for every request.
+/// The database used unless configured otherwise.
+pub const DEFAULT_DATABASE: &'static str =
+    // Forward slashes need to be escaped as %2F to be a valid URI.
+    // /var/run/postgresql is the default unix socket that when
+    // connecting on the same host is automatically accepted
+    // even without a password.
+    "postgresql://postgres@%2Fvar%2Frun%2Fpostgresql/backlogrs";
+
+/// The storage shared by every request.
+pub type Db = Arc<Box<Storage + Send + Sync>>;
+
+/// Makes the storage available to every request, which in the case of
+/// postgres maintains a connection pool instead of having to open and close
+/// the database for every request.
 pub struct DbConnection {
-    pool: Arc<r2d2::Pool<PostgresConnectionManager>>
+    storage: Db,
 }
 
 impl DbConnection {
     /// Returns a new `DbConnection` with default config and a connection pool
     /// to postgres@/var/run/postgresql not using any SSL.
     pub fn new() -> DbConnection {
-        let config = Default::default();
-        let manager = PostgresConnectionManager::new(
-            // Forward slashes need to be escaped as %2F to be a valid URI.
-            // /var/run/postgresql is the default unix socket that when
-            // connecting on the same host is automatically accepted
-            // even without a password.
-            "postgresql://postgres@%2Fvar%2Frun%2Fpostgresql/backlogrs",
-            SslMode::None);
-        let error_handler = Box::new(r2d2::NoopErrorHandler);
-        let pool = Arc::new(r2d2::Pool::new(config, manager, error_handler).unwrap());
+        DbConnection::open(DEFAULT_DATABASE).unwrap()
+    }
+
+    /// Returns a `DbConnection` to the storage described by `url`, see
+    /// `storage::open`.
+    pub fn open(url: &str) -> Result<DbConnection, LibError> {
+        Ok(DbConnection::with_boxed_storage(try!(storage::open(url))))
+    }
+
+    /// Returns a `DbConnection` keeping everything in memory, starting out
+    /// empty.
+    pub fn in_memory() -> DbConnection {
+        DbConnection::with_storage(MemoryStorage::new())
+    }
+
+    pub fn with_storage<S: Storage + Send + Sync + 'static>(storage: S) -> DbConnection {
+        DbConnection::with_boxed_storage(Box::new(storage))
+    }
+
+    pub fn with_boxed_storage(storage: Box<Storage + Send + Sync>) -> DbConnection {
         DbConnection {
-            pool: pool
+            storage: Arc::new(storage)
         }
     }
+
+    /// The storage, for using it outside of requests.
+    pub fn storage(&self) -> Db {
+        self.storage.clone()
+    }
 }
 
 impl typemap::Key for DbConnection {
-    type Value = Arc<r2d2::Pool<PostgresConnectionManager>>;
+    type Value = Db;
 }
 
 impl BeforeMiddleware for DbConnection {
     fn before(&self, req: &mut Request) -> IronResult<()> {
-        println!("{:?}", req);
-        req.extensions_mut().insert::<DbConnection>(self.pool.clone());
+        req.extensions_mut().insert::<DbConnection>(self.storage.clone());
         Ok(())
     }
 }
 
 /// Provides an extension method for `Request`s to simplify the process of
-/// getting the database connection from the `BeforeMiddleware` handler.
-pub trait GetDb<'a> {
-    fn db(&'a self) -> r2d2::PooledConnection<'a, PostgresConnectionManager>;
+/// getting the storage from the `BeforeMiddleware` handler.
+pub trait GetDb {
+    fn db(&self) -> Db;
 }
 
-/// Live for at least as long as the borrow on `Request` does.
-/// Whether it lives as long as the `Request` itself is not interesting.
-impl<'a, 'b: 'a> GetDb<'a> for Request<'b> {
+impl<'a> GetDb for Request<'a> {
     #[inline]
-    fn db(&'a self) -> r2d2::PooledConnection<'a, PostgresConnectionManager> {
+    fn db(&self) -> Db {
         // FIXME: Maybe some form of error handling; e.g. returning an IronResult?
-        self.extensions().get::<DbConnection>().and_then(|x| x.get().ok()).unwrap()
+        self.extensions().get::<DbConnection>().unwrap().clone()
     }
 }
 
@@ -220,3 +495,19 @@ impl<'a, U: err::Error> GetFromRouter<U> for Request<'a> {
             }))
     }
 }
+
+/// Provides an extension method for `Request`s to get a parameter from the
+/// query string of the url.
+pub trait GetQuery {
+    fn get_query(&self, name: &str) -> Option<String>;
+}
+
+impl<'a> GetQuery for Request<'a> {
+    fn get_query(&self, name: &str) -> Option<String> {
+        self.url.query.as_ref().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes()).into_iter()
+                .find(|&(ref key, _)| key == name)
+                .map(|(_, value)| value)
+        })
+    }
+}
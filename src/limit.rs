@@ -0,0 +1,127 @@
+//! Limits on request bodies, so that no client can make the server read
+//! more than a route needs.
+//!
+//! Limits are set per route by wrapping the handler:
+//!
+//! ```rust
+//! router.post("/user", BodyLimit::new(1024).max_depth(2).around(post_login));
+//! ```
+use std::io::Read;
+use iron::prelude::*;
+use iron::{headers, status, BeforeMiddleware, Handler};
+use plugin::Extensible;
+use bodyparser;
+use LibError;
+
+/// Rejects requests with bodies larger than `max_bytes` with `413 Request
+/// Entity Too Large`, and with `max_depth` bodies with JSON nested deeper
+/// than that with `400 Bad Request`.
+///
+/// The body is read here, at most `max_bytes` of it, and kept for
+/// `bodyparser` so that handlers don't read it again.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimit {
+    max_bytes: u64,
+    max_depth: Option<usize>,
+}
+
+impl BodyLimit {
+    pub fn new(max_bytes: u64) -> BodyLimit {
+        BodyLimit {
+            max_bytes: max_bytes,
+            max_depth: None,
+        }
+    }
+
+    /// Limits how deeply arrays and objects may be nested, where a flat
+    /// object is 1.
+    pub fn max_depth(mut self, max_depth: usize) -> BodyLimit {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Returns `handler` with the limit applied before it.
+    pub fn around<H: Handler>(self, handler: H) -> Chain {
+        let mut chain = Chain::new(handler);
+        chain.link_before(self);
+        chain
+    }
+
+    fn too_large(&self) -> IronError {
+        IronError::new(
+            LibError::Cause(format!("The body can be at most {} bytes", self.max_bytes)),
+            status::RequestEntityTooLarge)
+    }
+}
+
+impl BeforeMiddleware for BodyLimit {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        // Don't even start reading if the client says it is too much
+        if let Some(&headers::ContentLength(len)) = req.headers.get::<headers::ContentLength>() {
+            if len > self.max_bytes {
+                return Err(self.too_large());
+            }
+        }
+
+        let mut body = vec![];
+        try!(req.body.by_ref().take(self.max_bytes + 1).read_to_end(&mut body)
+             .map_err(|err| IronError::new(err, status::BadRequest)));
+        if body.len() as u64 > self.max_bytes {
+            return Err(self.too_large());
+        }
+        let body = try!(String::from_utf8(body)
+                        .map_err(|err| IronError::new(err, status::BadRequest)));
+
+        if let Some(max_depth) = self.max_depth {
+            try!(check_depth(&body, max_depth));
+        }
+
+        req.extensions_mut().insert::<bodyparser::Raw>(
+            if body.is_empty() { None } else { Some(body) });
+        Ok(())
+    }
+}
+
+/// Fails with `400 Bad Request` if `json` is nested deeper than `max_depth`,
+/// for routes that only know whether their body is JSON once they look at
+/// the request.
+pub fn check_depth(json: &str, max_depth: usize) -> IronResult<()> {
+    if json_depth(json) > max_depth {
+        return Err(IronError::new(
+                LibError::Cause(format!("JSON can be nested at most {} deep", max_depth)),
+                status::BadRequest));
+    }
+    Ok(())
+}
+
+/// How deeply arrays and objects are nested in `json`, without parsing it.
+/// Brackets in strings don't count.
+pub fn json_depth(json: &str) -> usize {
+    let mut depth = 0;
+    let mut max = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in json.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {},
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => {
+                depth += 1;
+                if depth > max {
+                    max = depth;
+                }
+            },
+            ']' | '}' => depth = if depth > 0 { depth - 1 } else { 0 },
+            _ => {},
+        }
+    }
+    max
+}
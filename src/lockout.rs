@@ -0,0 +1,316 @@
+//! Slowing down and locking out attempts to guess passwords.
+//!
+//! Failed attempts to log in are counted in a row both by `Login` and by IP.
+//! After a few free ones every further failure makes the next attempt wait
+//! twice as long as the one before, refusing attempts with `429 Too Many
+//! Requests` and `Retry-After` until then, right password or not. Once a
+//! login has failed `lock_after` times in a row it is locked for `lock_for`
+//! with `423 Locked`, and its owner is mailed a token which unlocks it right
+//! away:
+//!
+//! ```text
+//! POST /api/unlock {"token": "..."}
+//! ```
+//!
+//! IPs are never locked and get more free failures, since many users can
+//! share one. Failures are forgotten after `forget_after` without another,
+//! and those of a login as soon as it is logged in to.
+//!
+//! Failures, refusals, locks and unlocks are all kept as `AuthEvent`s. So is
+//! logging in after failures, but not otherwise since credentials come with
+//! every request. Users can read their own at `GET /api/user/:id/events`.
+use std::cmp;
+use chrono::{DateTime, Duration, UTC};
+use iron::prelude::*;
+use iron::status;
+use auth;
+use export;
+use mail::{templates, Mailer, Message, Template};
+use models::{AuthEvent, User};
+use storage::{AccountToken, Failures, Storage};
+use LibError;
+
+/// Kinds of `AuthEvent`: a wrong password or unknown username.
+pub const FAILURE: &'static str = "failure";
+/// An attempt refused without checking the password, see `Refusal`.
+pub const REFUSED: &'static str = "refused";
+/// A login locked after too many failures.
+pub const LOCKED: &'static str = "locked";
+/// A login unlocked with a token.
+pub const UNLOCKED: &'static str = "unlocked";
+/// Logging in after failures, which forgets them.
+pub const SUCCESS: &'static str = "success";
+
+/// The purpose of the `AccountToken`s mailed to unlock logins.
+pub const UNLOCK: &'static str = "unlock";
+
+/// How many failures are allowed and how long they block attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockoutPolicy {
+    /// Failures in a row of a login before it has to back off.
+    pub free_failures: i32,
+    /// Like `free_failures` but for an IP.
+    pub ip_free_failures: i32,
+    /// How long to back off after the first failure past the free ones,
+    /// doubling with every further failure up to `max_backoff`.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Failures in a row after which a login is locked.
+    pub lock_after: i32,
+    pub lock_for: Duration,
+    /// How long failures are remembered after the last one.
+    pub forget_after: Duration,
+}
+
+impl LockoutPolicy {
+    /// Backing off after 3 failures, from a second up to 5 minutes, and
+    /// locking for an hour after 10. IPs back off after 20.
+    pub fn new() -> LockoutPolicy {
+        LockoutPolicy {
+            free_failures: 3,
+            ip_free_failures: 20,
+            backoff: Duration::seconds(1),
+            max_backoff: Duration::minutes(5),
+            lock_after: 10,
+            lock_for: Duration::hours(1),
+            forget_after: Duration::days(1),
+        }
+    }
+
+    /// How long to back off after `n` failures past the free ones.
+    pub fn backoff_after(&self, n: i32) -> Duration {
+        if n <= 0 {
+            return Duration::zero();
+        }
+        let max = self.max_backoff.num_milliseconds();
+        let mut ms = self.backoff.num_milliseconds();
+        for _ in 1..n {
+            if ms >= max {
+                break;
+            }
+            ms = ms.saturating_mul(2);
+        }
+        Duration::milliseconds(cmp::min(ms, max))
+    }
+}
+
+/// Why an attempt to log in is refused before checking the password.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    /// Too many failures in a row, by login or IP, until then.
+    BackOff(DateTime<UTC>),
+    /// Locked until then, or until unlocked with the token mailed.
+    Locked(DateTime<UTC>),
+}
+
+impl Refusal {
+    pub fn until(&self) -> DateTime<UTC> {
+        match *self {
+            Refusal::BackOff(until) | Refusal::Locked(until) => until,
+        }
+    }
+
+    /// The error to answer with at `now`, telling when to try again.
+    pub fn to_error(&self, now: DateTime<UTC>) -> IronError {
+        // Rounded up, so that trying again then isn't refused again
+        let seconds = cmp::max((self.until() - now).num_milliseconds() + 999, 0) / 1000;
+        let (message, status) = match *self {
+            Refusal::BackOff(_) => (
+                format!("Too many failed attempts to log in, try again in {} seconds", seconds),
+                status::TooManyRequests),
+            Refusal::Locked(_) => (
+                format!("Locked after too many failed attempts to log in, for {} more seconds \
+                         or until unlocked with the token mailed to its owner", seconds),
+                status::Locked),
+        };
+        let mut err = IronError::new(LibError::Cause(message), status);
+        err.response.headers.set_raw("Retry-After", vec![seconds.to_string().into_bytes()]);
+        err
+    }
+}
+
+/// The key the failures of a login are kept under.
+pub fn login_key(id: i32) -> String {
+    format!("login:{}", id)
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+fn blocked(failures: &Failures, now: DateTime<UTC>) -> Option<DateTime<UTC>> {
+    match failures.blocked_until {
+        Some(until) if until > now => Some(until),
+        _ => None,
+    }
+}
+
+/// Keeps an `AuthEvent` of `kind` happening at `now`.
+pub fn audit(db: &Storage, now: DateTime<UTC>, user: Option<&User>, username: &str, ip: &str,
+             kind: &str) -> Result<(), LibError>
+{
+    db.add_auth_event(&AuthEvent {
+        id: None,
+        login_id: user.and_then(|x| x.id),
+        username: username.to_string(),
+        ip: ip.to_string(),
+        kind: kind.to_string(),
+        created_at: export::format_timestamp(&now),
+    }).map(|_| ())
+}
+
+/// Keeps track of failed attempts to log in following a `LockoutPolicy`.
+/// `user` is the login named by the username of an attempt, if any.
+#[derive(Clone)]
+pub struct Lockout {
+    policy: LockoutPolicy,
+}
+
+impl Lockout {
+    pub fn new(policy: LockoutPolicy) -> Lockout {
+        Lockout {
+            policy: policy,
+        }
+    }
+
+    /// Returns why an attempt to log in has to wait, if it does, keeping it
+    /// as an event.
+    pub fn check(&self, db: &Storage, now: DateTime<UTC>, username: &str, user: Option<&User>,
+                 ip: &str) -> Result<Option<Refusal>, LibError>
+    {
+        let mut refusal = match try!(self.current(db, &ip_key(ip), now)) {
+            Some(ref failures) => blocked(failures, now).map(Refusal::BackOff),
+            None => None,
+        };
+        if let Some(id) = user.and_then(|x| x.id) {
+            if let Some(failures) = try!(self.current(db, &login_key(id), now)) {
+                if let Some(until) = blocked(&failures, now) {
+                    refusal = if failures.failures >= self.policy.lock_after {
+                        Some(Refusal::Locked(until))
+                    } else {
+                        match refusal {
+                            Some(previous) if previous.until() > until => Some(previous),
+                            _ => Some(Refusal::BackOff(until)),
+                        }
+                    };
+                }
+            }
+        }
+
+        if refusal.is_some() {
+            try!(audit(db, now, user, username, ip, REFUSED));
+        }
+        Ok(refusal)
+    }
+
+    /// Counts a failed attempt to log in, locking the login and mailing its
+    /// owner a token to unlock it once there have been too many.
+    pub fn failed(&self, db: &Storage, mailer: &Mailer, now: DateTime<UTC>, username: &str,
+                  user: Option<&User>, ip: &str) -> Result<(), LibError>
+    {
+        try!(audit(db, now, user, username, ip, FAILURE));
+        try!(self.count(db, &ip_key(ip), now, self.policy.ip_free_failures, None));
+
+        let (user, id) = match user.and_then(|x| x.id.map(|id| (x, id))) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let failures = try!(self.count(db, &login_key(id), now, self.policy.free_failures,
+                                       Some(self.policy.lock_after)));
+        if failures.failures < self.policy.lock_after {
+            return Ok(());
+        }
+
+        let until = now + self.policy.lock_for;
+        let token = AccountToken {
+            token: try!(auth::random_token()),
+            login_id: id,
+            purpose: UNLOCK.to_string(),
+            expires_at: until,
+        };
+        try!(db.add_token(&token));
+        try!(audit(db, now, Some(user), username, ip, LOCKED));
+        // The lock runs out by itself even if the mail can't be sent.
+        let _ = unlock_message(user, &token.token, until).and_then(|x| mailer.send(&x));
+        Ok(())
+    }
+
+    /// Forgets the failures of a login that was just logged in to.
+    pub fn succeeded(&self, db: &Storage, now: DateTime<UTC>, user: &User, ip: &str)
+        -> Result<(), LibError>
+    {
+        let key = match user.id {
+            Some(id) => login_key(id),
+            None => return Ok(()),
+        };
+        if try!(self.current(db, &key, now)).is_some() {
+            try!(db.clear_failures(&key));
+            try!(audit(db, now, Some(user), &user.username, ip, SUCCESS));
+        }
+        Ok(())
+    }
+
+    /// The failures under `key`, unless they have been forgotten.
+    fn current(&self, db: &Storage, key: &str, now: DateTime<UTC>)
+        -> Result<Option<Failures>, LibError>
+    {
+        Ok(try!(db.failures(key)).and_then(|x| {
+            if x.last_failure + self.policy.forget_after > now { Some(x) } else { None }
+        }))
+    }
+
+    /// Counts another failure under `key`, blocking it when past `free` or
+    /// `lock_after` failures.
+    fn count(&self, db: &Storage, key: &str, now: DateTime<UTC>, free: i32,
+             lock_after: Option<i32>) -> Result<Failures, LibError>
+    {
+        let failures = try!(self.current(db, key, now)).map(|x| x.failures).unwrap_or(0) + 1;
+        let blocked_until = match lock_after {
+            Some(n) if failures >= n => Some(now + self.policy.lock_for),
+            _ if failures > free => Some(now + self.policy.backoff_after(failures - free)),
+            _ => None,
+        };
+        let res = Failures {
+            failures: failures,
+            last_failure: now,
+            blocked_until: blocked_until,
+        };
+        try!(db.set_failures(key, &res));
+        Ok(res)
+    }
+}
+
+/// Forgets the failures of a login, unlocking it if it was locked.
+pub fn forget_failures(db: &Storage, user_id: i32) -> Result<(), LibError> {
+    db.clear_failures(&login_key(user_id))
+}
+
+/// Unlocks the login `token` was mailed for and returns it, unless the
+/// token is unknown or expired. Either way the token can't be used again.
+pub fn unlock(db: &Storage, now: DateTime<UTC>, token: &str, ip: &str)
+    -> Result<Option<User>, LibError>
+{
+    let token = match try!(db.take_token(token, UNLOCK)) {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    if token.expires_at <= now {
+        return Ok(None);
+    }
+    let user = match try!(db.user(token.login_id)) {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    try!(forget_failures(db, token.login_id));
+    try!(audit(db, now, Some(&user), &user.username, ip, UNLOCKED));
+    Ok(Some(user))
+}
+
+fn unlock_message(user: &User, token: &str, until: DateTime<UTC>) -> Result<Message, LibError> {
+    let template = try!(Template::parse(templates::UNLOCK));
+    template.render(&user.email, &[
+        ("username", &user.username[..]),
+        ("token", token),
+        ("until", &until.format("%Y-%m-%d %H:%M UTC").to_string()[..]),
+    ])
+}
@@ -0,0 +1,304 @@
+//! Structured access logs, one JSON object per line.
+//!
+//! `Logger` is linked first as a `BeforeMiddleware` and last as an
+//! `AfterMiddleware`. It gives every request an id, which is returned in the
+//! `X-Request-Id` header, and logs the method, path, status, latency and user
+//! of every request once it is answered:
+//!
+//! ```text
+//! {"latency_ms":1.2,"level":"info","method":"GET","path":"/api/game","request_id":"...",...}
+//! ```
+//!
+//! Credentials are never logged: query parameters like `password`, headers
+//! like `Authorization` and the tokens of share links in paths are redacted.
+use std::ascii::AsciiExt;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use chrono::UTC;
+use iron::prelude::*;
+use iron::{AfterMiddleware, BeforeMiddleware};
+use plugin::Extensible;
+use rustc_serialize::json::{Json, ToJson};
+use time;
+use typemap;
+use auth::CurrentUser;
+use export::format_timestamp;
+use LibError;
+
+const REDACTED: &'static str = "[REDACTED]";
+
+/// Query parameters and headers whose values are never logged.
+const SECRETS: [&'static str; 5] = ["password", "token", "secret", "authorization", "cookie"];
+
+/// Path segments followed by a secret, like the token of a share link.
+const SECRET_PATHS: [&'static str; 2] = ["shared", "shares"];
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRETS.iter().any(|x| name.contains(x))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        })
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<LogLevel, LibError> {
+        match &s.to_ascii_lowercase()[..] {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(LibError::Cause(format!("Unknown log level: {}", s))),
+        }
+    }
+}
+
+/// The id of a request, set by `Logger`.
+pub struct RequestId;
+
+impl typemap::Key for RequestId {
+    type Value = String;
+}
+
+/// When `Logger` first saw a request, in nanoseconds, and its path before
+/// any prefix was stripped.
+struct RequestStart;
+
+impl typemap::Key for RequestStart {
+    type Value = (u64, String);
+}
+
+/// Makes up an id which is unique for as long as the process runs.
+fn new_request_id() -> String {
+    static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+    format!("{:x}-{:x}", time::precise_time_ns(), COUNTER.fetch_add(1, Ordering::SeqCst))
+}
+
+/// Logs every request at a level depending on its status, `info` when it
+/// went fine, `warn` for client errors and `error` for server errors. At the
+/// `debug` level the (redacted) headers are included as well.
+#[derive(Clone)]
+pub struct Logger {
+    level: LogLevel,
+    out: Arc<Mutex<Box<Write + Send>>>,
+}
+
+impl Logger {
+    /// Logs to stdout.
+    pub fn new(level: LogLevel) -> Logger {
+        Logger::with_writer(level, Box::new(io::stdout()))
+    }
+
+    pub fn with_writer(level: LogLevel, out: Box<Write + Send>) -> Logger {
+        Logger {
+            level: level,
+            out: Arc::new(Mutex::new(out)),
+        }
+    }
+
+    /// Logs nothing at all.
+    pub fn off() -> Logger {
+        Logger::with_writer(LogLevel::Off, Box::new(io::sink()))
+    }
+
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level != LogLevel::Off && level <= self.level
+    }
+
+    /// Writes `fields` as a line with the time and `level` added, if the
+    /// level is enabled.
+    pub fn log(&self, level: LogLevel, mut fields: BTreeMap<String, Json>) {
+        if !self.enabled(level) {
+            return;
+        }
+        fields.insert("ts".to_string(), format_timestamp(&UTC::now()).to_json());
+        fields.insert("level".to_string(), level.to_string().to_json());
+        if let Ok(mut out) = self.out.lock() {
+            // Logging must never take a request down with it.
+            let _ = writeln!(out, "{}", Json::Object(fields));
+            let _ = out.flush();
+        }
+    }
+
+    /// Logs `message` about a request, with its id.
+    pub fn event(&self, level: LogLevel, req: &Request, message: &str) {
+        let mut fields = BTreeMap::new();
+        fields.insert("message".to_string(), message.to_json());
+        if let Some(id) = req.extensions().get::<RequestId>() {
+            fields.insert("request_id".to_string(), id.to_json());
+        }
+        self.log(level, fields);
+    }
+
+    fn access(&self, req: &Request, res: &Response) {
+        let status = res.status.map(|x| x.to_u16()).unwrap_or(200);
+        let level = match status {
+            500...599 => LogLevel::Error,
+            400...499 => LogLevel::Warn,
+            _ => LogLevel::Info,
+        };
+        if !self.enabled(level) {
+            return;
+        }
+
+        let mut fields = BTreeMap::new();
+        if let Some(id) = req.extensions().get::<RequestId>() {
+            fields.insert("request_id".to_string(), id.to_json());
+        }
+        let path = match req.extensions().get::<RequestStart>() {
+            Some(&(start, ref path)) => {
+                let latency = (time::precise_time_ns() - start) as f64 / 1e6;
+                fields.insert("latency_ms".to_string(), latency.to_json());
+                path.clone()
+            },
+            None => format!("/{}", req.url.path.connect("/")),
+        };
+        fields.insert("method".to_string(), req.method.to_string().to_json());
+        fields.insert("path".to_string(), redact_path(&path).to_json());
+        if let Some(ref query) = req.url.query {
+            fields.insert("query".to_string(), redact_query(query).to_json());
+        }
+        fields.insert("status".to_string(), status.to_json());
+        fields.insert("remote_addr".to_string(), req.remote_addr.to_string().to_json());
+        if let Some(id) = req.current_user().and_then(|x| x.id) {
+            fields.insert("user_id".to_string(), id.to_json());
+        }
+        if self.enabled(LogLevel::Debug) {
+            let headers = req.headers.iter().map(|x| {
+                let value = if is_secret(x.name()) { REDACTED.to_string() } else { x.value_string() };
+                (x.name().to_string(), value.to_json())
+            }).collect::<BTreeMap<_, _>>();
+            fields.insert("headers".to_string(), Json::Object(headers));
+        }
+        self.log(level, fields);
+    }
+}
+
+/// Replaces the values of secret parameters in a query string.
+pub fn redact_query(query: &str) -> String {
+    query.split('&').map(|pair| {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(_)) if is_secret(key) => format!("{}={}", key, REDACTED),
+            _ => pair.to_string(),
+        }
+    }).collect::<Vec<_>>().connect("&")
+}
+
+/// Replaces the segments of a path that are secrets.
+pub fn redact_path(path: &str) -> String {
+    let mut secret = false;
+    path.split('/').map(|x| {
+        let segment = if secret && !x.is_empty() { REDACTED } else { x };
+        secret = SECRET_PATHS.contains(&x);
+        segment
+    }).collect::<Vec<_>>().connect("/")
+}
+
+impl typemap::Key for Logger {
+    type Value = Logger;
+}
+
+impl BeforeMiddleware for Logger {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        // Keep the id of a proxy in front, as long as it looks harmless.
+        let id = req.headers.get_raw("X-Request-Id")
+            .and_then(|x| x.first())
+            .and_then(|x| String::from_utf8(x.clone()).ok())
+            .and_then(|x| if !x.is_empty() && x.len() <= 64
+                      && x.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+                Some(x)
+            } else {
+                None
+            })
+            .unwrap_or_else(new_request_id);
+        req.extensions_mut().insert::<RequestId>(id);
+        let path = format!("/{}", req.url.path.connect("/"));
+        req.extensions_mut().insert::<RequestStart>((time::precise_time_ns(), path));
+        req.extensions_mut().insert::<Logger>(self.clone());
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for Logger {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        if let Some(id) = req.extensions().get::<RequestId>() {
+            res.headers.set_raw("X-Request-Id", vec![id.clone().into_bytes()]);
+        }
+        self.access(req, &res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        if let Some(id) = req.extensions().get::<RequestId>() {
+            err.response.headers.set_raw("X-Request-Id", vec![id.clone().into_bytes()]);
+        }
+        self.access(req, &err.response);
+        Err(err)
+    }
+}
+
+/// Provides an extension method for `Request`s to log with the `Logger` of
+/// the chain, if there is one.
+pub trait Log {
+    fn log(&self, level: LogLevel, message: &str);
+}
+
+impl<'a> Log for Request<'a> {
+    fn log(&self, level: LogLevel, message: &str) {
+        if let Some(logger) = self.extensions().get::<Logger>() {
+            logger.event(level, self, message);
+        }
+    }
+}
+
+/// A `Write` keeping everything in memory, for looking at logs in tests.
+#[derive(Clone)]
+pub struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl LogBuffer {
+    pub fn new() -> LogBuffer {
+        LogBuffer(Arc::new(Mutex::new(vec![])))
+    }
+
+    /// Every line logged so far.
+    pub fn lines(&self) -> Vec<Json> {
+        let buf = self.0.lock().unwrap();
+        String::from_utf8_lossy(&buf).lines().filter_map(|x| Json::from_str(x).ok()).collect()
+    }
+}
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
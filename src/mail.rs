@@ -0,0 +1,351 @@
+//! Mail to users about their account, behind a trait so that tests and
+//! development setups don't need a mail server.
+//!
+//! Messages are written as templates in `templates/mail`, a subject line
+//! and the body with `{{name}}` placeholders:
+//!
+//! ```rust
+//! let template = try!(Template::parse(templates::VERIFY));
+//! let message = try!(template.render(&user.email, &[
+//!     ("username", &user.username[..]),
+//!     ("token", &token[..]),
+//! ]));
+//! try!(mailer.send(&message));
+//! ```
+//!
+//! Mail is sent by `SmtpMailer`, written to files by `FileMailer` or logged
+//! by `LogMailer`, see `open`.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use chrono::UTC;
+use iron::prelude::*;
+use iron::BeforeMiddleware;
+use plugin::Extensible;
+use rustc_serialize::json::ToJson;
+use time;
+use typemap;
+use logging::{LogLevel, Logger};
+use LibError;
+
+/// The templates that come with backlogrs.
+pub mod templates {
+    /// A login locked after too many failed attempts to log in, with
+    /// `username`, `token` and `until`.
+    pub const UNLOCK: &'static str = include_str!("../templates/mail/unlock.txt");
+    /// A new user verifying their email, with `username`, `token` and
+    /// `until`.
+    pub const VERIFY: &'static str = include_str!("../templates/mail/verify.txt");
+    /// Resetting a forgotten password, with `username`, `token` and `until`.
+    pub const RESET: &'static str = include_str!("../templates/mail/reset.txt");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Message {
+    /// The message as an RFC 5322 mail from `from`, with CRLF line endings.
+    pub fn to_rfc5322(&self, from: &str) -> String {
+        let mut out = String::new();
+        for &(name, value) in [
+            ("From", from),
+            ("To", &self.to[..]),
+            ("Subject", &self.subject[..]),
+        ].iter() {
+            out.push_str(&format!("{}: {}\r\n", name, header_value(value)));
+        }
+        out.push_str(&format!("Date: {}\r\n", UTC::now().format("%a, %d %b %Y %H:%M:%S +0000")));
+        out.push_str("MIME-Version: 1.0\r\n");
+        out.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        out.push_str("\r\n");
+        for line in self.body.lines() {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+        out
+    }
+}
+
+/// Keeps a header on a line of its own, whatever it was given.
+fn header_value(value: &str) -> String {
+    value.replace("\r", "").replace("\n", " ")
+}
+
+/// A message with `{{name}}` placeholders, parsed from a `Subject:` line, an
+/// empty line and the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    subject: String,
+    body: String,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, LibError> {
+        let mut parts = source.splitn(2, "\n\n");
+        let subject = match parts.next().map(|x| x.trim()) {
+            Some(x) if x.starts_with("Subject:") => x["Subject:".len()..].trim().to_string(),
+            _ => return Err(LibError::Cause("A template must start with Subject:".to_string())),
+        };
+        Ok(Template {
+            subject: subject,
+            body: parts.next().unwrap_or("").to_string(),
+        })
+    }
+
+    /// Fills in the placeholders with `vars`, failing for any placeholder
+    /// without a value.
+    pub fn render(&self, to: &str, vars: &[(&str, &str)]) -> Result<Message, LibError> {
+        Ok(Message {
+            to: to.to_string(),
+            subject: try!(fill(&self.subject, vars)),
+            body: try!(fill(&self.body, vars)),
+        })
+    }
+}
+
+fn fill(text: &str, vars: &[(&str, &str)]) -> Result<String, LibError> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = try!(rest[start..].find("}}").map(|x| start + x).ok_or(
+                LibError::Cause("Unclosed {{ in template".to_string())));
+        let name = rest[start + 2..end].trim();
+        match vars.iter().find(|x| x.0 == name) {
+            Some(&(_, value)) => out.push_str(value),
+            None => return Err(LibError::Cause(format!("No value for {{{{{}}}}}", name))),
+        }
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+pub trait Mailer {
+    fn send(&self, message: &Message) -> Result<(), LibError>;
+}
+
+impl<M: Mailer + ?Sized> Mailer for Box<M> {
+    fn send(&self, message: &Message) -> Result<(), LibError> {
+        (**self).send(message)
+    }
+}
+
+/// The mailer shared by every request.
+pub type SharedMailer = Arc<Box<Mailer + Send + Sync>>;
+
+/// Opens the mailer described by `url`, sending from `from`, which is one of
+///
+/// - `smtp://host:port` for an SMTP server, port 25 if left out,
+/// - `file:path/to/dir` for writing every message to a file in a directory,
+/// - `log` for logging every message with `logger`.
+pub fn open(url: &str, from: &str, logger: Logger) -> Result<Box<Mailer + Send + Sync>, LibError> {
+    if url.starts_with("smtp://") {
+        let mut addr = url["smtp://".len()..].trim_right_matches('/').to_string();
+        if !addr.contains(':') {
+            addr.push_str(":25");
+        }
+        Ok(Box::new(SmtpMailer::new(&addr, from)))
+    } else if url.starts_with("file:") {
+        Ok(Box::new(try!(FileMailer::new(&url["file:".len()..], from))))
+    } else if url == "log" {
+        Ok(Box::new(LogMailer::new(logger)))
+    } else {
+        Err(LibError::Cause(format!("Unknown kind of mailer: {}", url)))
+    }
+}
+
+/// Keeps every message instead of sending it, for tests. Clones share the
+/// same messages.
+#[derive(Clone)]
+pub struct MemoryMailer {
+    sent: Arc<Mutex<Vec<Message>>>,
+}
+
+impl MemoryMailer {
+    pub fn new() -> MemoryMailer {
+        MemoryMailer {
+            sent: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Every message sent so far, oldest first.
+    pub fn sent(&self) -> Vec<Message> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, message: &Message) -> Result<(), LibError> {
+        let mut sent = try!(self.sent.lock().map_err(|_| LibError::Cause(
+                    "A previous request panicked while sending mail".to_string())));
+        sent.push(message.clone());
+        Ok(())
+    }
+}
+
+/// Logs every message instead of sending it, for development. Messages may
+/// hold tokens, so this is no mailer for production.
+pub struct LogMailer {
+    logger: Logger,
+}
+
+impl LogMailer {
+    pub fn new(logger: Logger) -> LogMailer {
+        LogMailer {
+            logger: logger,
+        }
+    }
+}
+
+impl Mailer for LogMailer {
+    fn send(&self, message: &Message) -> Result<(), LibError> {
+        let mut fields = BTreeMap::new();
+        fields.insert("message".to_string(), "mail".to_json());
+        fields.insert("to".to_string(), message.to.to_json());
+        fields.insert("subject".to_string(), message.subject.to_json());
+        fields.insert("body".to_string(), message.body.to_json());
+        self.logger.log(LogLevel::Info, fields);
+        Ok(())
+    }
+}
+
+/// Writes every message as an `.eml` file of its own to a directory, for
+/// running without a mail server.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    /// Creates `dir` if needed.
+    pub fn new(dir: &str, from: &str) -> Result<FileMailer, LibError> {
+        try!(fs::create_dir_all(dir).map_err(LibError::other));
+        Ok(FileMailer {
+            dir: PathBuf::from(dir),
+            from: from.to_string(),
+        })
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, message: &Message) -> Result<(), LibError> {
+        static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+        let name = format!("{}-{}.eml", time::precise_time_ns(),
+                           COUNTER.fetch_add(1, Ordering::SeqCst));
+        let mut file = try!(File::create(self.dir.join(name)).map_err(LibError::other));
+        file.write_all(message.to_rfc5322(&self.from).as_bytes()).map_err(LibError::other)
+    }
+}
+
+/// Sends mail through an SMTP server that takes it without logging in, like
+/// a relay on the same host. There is no TLS, so keep the server close.
+pub struct SmtpMailer {
+    addr: String,
+    from: String,
+}
+
+impl SmtpMailer {
+    /// Sends through the server at `addr`, like `localhost:25`.
+    pub fn new(addr: &str, from: &str) -> SmtpMailer {
+        SmtpMailer {
+            addr: addr.to_string(),
+            from: from.to_string(),
+        }
+    }
+}
+
+/// Reads a reply, which may span several lines, failing unless its code is
+/// one of `expected`.
+fn reply<R: BufRead>(reader: &mut R, expected: &[u16]) -> Result<(), LibError> {
+    loop {
+        let mut line = String::new();
+        try!(reader.read_line(&mut line).map_err(LibError::other));
+        let code = line.chars().take(3).collect::<String>().parse::<u16>().ok();
+        let code = try!(code.ok_or(LibError::Cause(format!("Unexpected SMTP reply: {}", line))));
+        // `250-...` is followed by more lines, `250 ...` is the last.
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return if expected.contains(&code) {
+            Ok(())
+        } else {
+            Err(LibError::Cause(format!("SMTP server answered: {}", line.trim())))
+        };
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &Message) -> Result<(), LibError> {
+        let mut stream = try!(TcpStream::connect(&self.addr[..]).map_err(LibError::other));
+        let mut reader = BufReader::new(try!(stream.try_clone().map_err(LibError::other)));
+        try!(reply(&mut reader, &[220]));
+
+        let to = header_value(&message.to);
+        let from = header_value(&self.from);
+        let commands = vec![
+            ("EHLO localhost".to_string(), vec![250]),
+            (format!("MAIL FROM:<{}>", from), vec![250]),
+            (format!("RCPT TO:<{}>", to), vec![250, 251]),
+            ("DATA".to_string(), vec![354]),
+        ];
+        for &(ref command, ref expected) in commands.iter() {
+            try!(write!(stream, "{}\r\n", command).map_err(LibError::other));
+            try!(reply(&mut reader, expected));
+        }
+
+        // Lines starting with a dot get another, so that none ends the data.
+        // The first line is a header, which never does.
+        let data = message.to_rfc5322(&self.from).replace("\r\n.", "\r\n..");
+        try!(write!(stream, "{}.\r\n", data).map_err(LibError::other));
+        try!(reply(&mut reader, &[250]));
+
+        try!(write!(stream, "QUIT\r\n").map_err(LibError::other));
+        reply(&mut reader, &[221])
+    }
+}
+
+/// Makes a mailer available to every request through `GetMailer`.
+pub struct MailerMiddleware {
+    mailer: SharedMailer,
+}
+
+impl MailerMiddleware {
+    pub fn new(mailer: SharedMailer) -> MailerMiddleware {
+        MailerMiddleware {
+            mailer: mailer,
+        }
+    }
+}
+
+impl typemap::Key for MailerMiddleware {
+    type Value = SharedMailer;
+}
+
+impl BeforeMiddleware for MailerMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions_mut().insert::<MailerMiddleware>(self.mailer.clone());
+        Ok(())
+    }
+}
+
+/// Provides an extension method for `Request`s to get the mailer of
+/// `MailerMiddleware`.
+pub trait GetMailer {
+    fn mailer(&self) -> SharedMailer;
+}
+
+impl<'a> GetMailer for Request<'a> {
+    #[inline]
+    fn mailer(&self) -> SharedMailer {
+        self.extensions().get::<MailerMiddleware>().unwrap().clone()
+    }
+}
@@ -0,0 +1,294 @@
+//! Metrics in the Prometheus text format, served at `/metrics`.
+//!
+//! `Metrics` is linked as both a `BeforeMiddleware` and an `AfterMiddleware`
+//! and counts requests and how long they took by route. Routes are only
+//! known for handlers added with `Routes`, anything else is `unmatched`:
+//!
+//! ```rust
+//! router.get_route("/user/:id", get_user_by_id);
+//! ```
+//!
+//! The `Collector` renders those together with the state of the connection
+//! pool and how many users, games and entries there are.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use iron::prelude::*;
+use iron::{headers, status, AfterMiddleware, BeforeMiddleware, Handler};
+use plugin::Extensible;
+use router::Router;
+use time;
+use typemap;
+use storage::Storage;
+use validate::ValidationErrors;
+use {GetDb, LibError};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The route of a request, like `/user/:id`, set by `Route`.
+pub struct RouteName;
+
+impl typemap::Key for RouteName {
+    type Value = &'static str;
+}
+
+/// Tells `Metrics` which route handled a request.
+pub struct Route<H> {
+    glob: &'static str,
+    handler: H,
+}
+
+impl<H: Handler> Route<H> {
+    pub fn new(glob: &'static str, handler: H) -> Route<H> {
+        Route {
+            glob: glob,
+            handler: handler,
+        }
+    }
+}
+
+impl<H: Handler> Handler for Route<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        req.extensions_mut().insert::<RouteName>(self.glob);
+        self.handler.handle(req)
+    }
+}
+
+/// Adds routes to a `Router` wrapped in `Route`, so that their requests are
+/// counted by route.
+pub trait Routes {
+    fn get_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Self;
+    fn post_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Self;
+    fn put_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Self;
+    fn patch_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Self;
+    fn delete_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Self;
+}
+
+impl Routes for Router {
+    fn get_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Router {
+        self.get(glob, Route::new(glob, handler));
+        self
+    }
+
+    fn post_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Router {
+        self.post(glob, Route::new(glob, handler));
+        self
+    }
+
+    fn put_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Router {
+        self.put(glob, Route::new(glob, handler));
+        self
+    }
+
+    fn patch_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Router {
+        self.patch(glob, Route::new(glob, handler));
+        self
+    }
+
+    fn delete_route<H: Handler>(&mut self, glob: &'static str, handler: H) -> &mut Router {
+        self.delete(glob, Route::new(glob, handler));
+        self
+    }
+}
+
+/// When `Metrics` first saw a request, in nanoseconds.
+struct RequestStart;
+
+impl typemap::Key for RequestStart {
+    type Value = u64;
+}
+
+#[derive(Clone, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative, with those above every bound
+    /// last.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len() + 1];
+        }
+        let i = BUCKETS.iter().position(|&x| value <= x).unwrap_or(BUCKETS.len());
+        self.buckets[i] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// By method, route and status.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// By method and route.
+    latencies: BTreeMap<(String, String), Histogram>,
+    /// By kind of error.
+    errors: BTreeMap<&'static str, u64>,
+}
+
+/// Counts requests, their latency and errors. Clones share their counts.
+///
+/// Link it before `DebugIronError`, which turns errors into responses,
+/// since errors can only be counted by kind before that.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            registry: Arc::new(Mutex::new(Default::default())),
+        }
+    }
+
+    /// The handler serving these metrics.
+    pub fn collector(&self) -> Collector {
+        Collector {
+            metrics: self.clone(),
+        }
+    }
+
+    fn record(&self, req: &Request, res: &Response) {
+        let method = req.method.to_string();
+        let route = req.extensions().get::<RouteName>().cloned().unwrap_or("unmatched");
+        let status = res.status.map(|x| x.to_u16()).unwrap_or(200);
+        let latency = req.extensions().get::<RequestStart>()
+            .map(|&start| (time::precise_time_ns() - start) as f64 / 1e9);
+
+        let mut registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(_) => return,
+        };
+        *registry.requests.entry((method.clone(), route.to_string(), status)).or_insert(0) += 1;
+        if let Some(latency) = latency {
+            registry.latencies.entry((method, route.to_string()))
+                .or_insert(Default::default())
+                .observe(latency);
+        }
+    }
+
+    fn record_error(&self, err: &IronError) {
+        let kind = match err.error.downcast::<LibError>() {
+            Some(err) => err.kind(),
+            None if err.error.is::<ValidationErrors>() => "validation",
+            None => "unknown",
+        };
+        if let Ok(mut registry) = self.registry.lock() {
+            *registry.errors.entry(kind).or_insert(0) += 1;
+        }
+    }
+
+    /// Writes the request metrics in the Prometheus text format.
+    fn render(&self, out: &mut String) {
+        let registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(_) => return,
+        };
+
+        header(out, "backlogrs_http_requests_total", "counter",
+               "Requests answered, by method, route and status.");
+        for (&(ref method, ref route, status), count) in registry.requests.iter() {
+            let _ = writeln!(out, "backlogrs_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                             escape(method), escape(route), status, count);
+        }
+
+        header(out, "backlogrs_http_request_duration_seconds", "histogram",
+               "How long requests took to answer, by method and route.");
+        for (&(ref method, ref route), histogram) in registry.latencies.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            let mut cumulative = 0;
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += *count;
+                let le = BUCKETS.get(i).map(|x| x.to_string()).unwrap_or("+Inf".to_string());
+                let _ = writeln!(out, "backlogrs_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                                 labels, le, cumulative);
+            }
+            let _ = writeln!(out, "backlogrs_http_request_duration_seconds_sum{{{}}} {}",
+                             labels, histogram.sum);
+            let _ = writeln!(out, "backlogrs_http_request_duration_seconds_count{{{}}} {}",
+                             labels, histogram.count);
+        }
+
+        header(out, "backlogrs_errors_total", "counter", "Errors, by kind.");
+        for (kind, count) in registry.errors.iter() {
+            let _ = writeln!(out, "backlogrs_errors_total{{kind=\"{}\"}} {}", kind, count);
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+}
+
+impl BeforeMiddleware for Metrics {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions_mut().insert::<RequestStart>(time::precise_time_ns());
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for Metrics {
+    fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
+        self.record(req, &res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
+        self.record_error(&err);
+        self.record(req, &err.response);
+        Err(err)
+    }
+}
+
+/// Serves the metrics of a `Metrics` together with gauges read from the
+/// storage when asked. Needs to be linked after `DbConnection`.
+pub struct Collector {
+    metrics: Metrics,
+}
+
+impl Handler for Collector {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let db = req.db();
+        let counts = try_iron!(db.counts());
+
+        let mut out = String::new();
+        self.metrics.render(&mut out);
+
+        if let Some(pool) = db.pool_state() {
+            for &(name, help, value) in [
+                ("backlogrs_db_pool_size", "The most connections the pool will open.", pool.size),
+                ("backlogrs_db_pool_connections", "Open connections, in use or not.",
+                 pool.connections),
+                ("backlogrs_db_pool_idle_connections", "Open connections not in use.",
+                 pool.idle_connections),
+            ].iter() {
+                header(&mut out, name, "gauge", help);
+                let _ = writeln!(out, "{} {}", name, value);
+            }
+        }
+
+        header(&mut out, "backlogrs_users", "gauge", "Registered users.");
+        let _ = writeln!(out, "backlogrs_users {}", counts.users);
+        header(&mut out, "backlogrs_games", "gauge", "Games.");
+        let _ = writeln!(out, "backlogrs_games {}", counts.games);
+        header(&mut out, "backlogrs_entries", "gauge", "Entries in libraries, by status.");
+        for &(status, count) in counts.entries.iter() {
+            let _ = writeln!(out, "backlogrs_entries{{status=\"{}\"}} {}", status, count);
+        }
+
+        let mut res = Response::with((status::Ok, out));
+        res.headers.set(headers::ContentType("text/plain; version=0.0.4".parse().unwrap()));
+        Ok(res)
+    }
+}
@@ -2,14 +2,16 @@ extern crate "rustc-serialize" as serialize;
 extern crate postgres;
 extern crate time;
 extern crate chrono;
-use {Row, FromSqlRow};
+use LibError;
 use postgres::types::{self, Type};
+use std::error::Error;
+use std::fmt;
 use std::string;
-use std::str::{self, FromStr};
+use std::str::FromStr;
 use std::io::{Read, Write};
 
 #[derive(Debug, Clone)]
-pub struct UtcString(chrono::DateTime<chrono::UTC>);
+pub struct UtcString(pub chrono::DateTime<chrono::UTC>);
 
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
 pub struct Login {
@@ -19,6 +21,22 @@ pub struct Login {
     pub email: String,
 }
 
+from_sql_row!(Login {
+    id,
+    username,
+    password,
+    email,
+});
+
+validate!(Login {
+    // The limits of the columns
+    username: length(min = 1, max = 20),
+    password: length(min = 1, max = 128),
+    email: email,
+});
+
+/// A user as they see themselves. Anyone else only gets to see the
+/// `PublicUser`.
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
 pub struct User {
     pub id: Option<i32>,
@@ -26,15 +44,288 @@ pub struct User {
     pub email: String,
 }
 
+from_sql_row!(User {
+    id,
+    username,
+    email,
+});
+
+impl User {
+    pub fn public(&self) -> PublicUser {
+        PublicUser {
+            id: self.id,
+            username: self.username.clone(),
+        }
+    }
+}
+
+/// What anyone can see of a user.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct PublicUser {
+    pub id: Option<i32>,
+    pub username: String,
+}
+
+from_sql_row!(PublicUser {
+    id,
+    username,
+});
+
+/// Something that happened while logging in, kept for auditing. See
+/// `lockout` and `account` for the kinds there are.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct AuthEvent {
+    pub id: Option<i32>,
+    /// The login logged in to, if the username is one.
+    pub login_id: Option<i32>,
+    pub username: String,
+    pub ip: String,
+    pub kind: String,
+    pub created_at: String,
+}
+
+from_sql_row!(AuthEvent {
+    id,
+    login_id,
+    username,
+    ip,
+    kind,
+    created_at,
+});
+
+/// The token mailed to the owner of a locked login to unlock it.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct Unlock {
+    pub token: String,
+}
+
+validate!(Unlock {
+    token: length(min = 1, max = 128),
+});
+
+/// The token mailed to a new user to verify their email.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct Verify {
+    pub token: String,
+}
+
+validate!(Verify {
+    token: length(min = 1, max = 128),
+});
+
+/// Whether the email of a user has been verified.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct EmailStatus {
+    pub email: String,
+    pub verified: bool,
+}
+
+/// Asks for a token to reset the password of the user with the email.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct ResetRequest {
+    pub email: String,
+}
+
+validate!(ResetRequest {
+    email: email,
+});
+
+/// A new password, with the token mailed for a `ResetRequest`.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct PasswordReset {
+    pub token: String,
+    pub password: String,
+}
+
+validate!(PasswordReset {
+    token: length(min = 1, max = 128),
+    password: length(min = 1, max = 128),
+});
+
+/// Changes to a user, leaving out what stays the same.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct UserUpdate {
+    pub username: Option<String>,
+    pub email: Option<String>,
+}
+
+validate!(UserUpdate {
+    username: length(min = 1, max = 20),
+    email: email,
+});
+
+/// A new password, with the current one to prove it is the user changing it.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct PasswordChange {
+    pub current: String,
+    pub password: String,
+}
+
+validate!(PasswordChange {
+    current: length(min = 1, max = 128),
+    password: length(min = 1, max = 128),
+});
+
+/// Who can see the library of a user.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    /// Anyone, even without logging in, which is the default.
+    Public,
+    /// Only the friends of the user.
+    Friends,
+    Private,
+}
+
+impl FromStr for Visibility {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<Visibility, LibError> {
+        match s {
+            "Public" => Ok(Visibility::Public),
+            "Friends" => Ok(Visibility::Friends),
+            "Private" => Ok(Visibility::Private),
+            _ => Err(LibError::Cause(format!("Unknown visibility: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// The privacy settings of a user.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Privacy {
+    pub library: Visibility,
+}
+
+validate!(Privacy {});
+
+/// Lets the user with the username see a library visible to friends.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct NewFriend {
+    pub username: String,
+}
+
+validate!(NewFriend {
+    username: length(min = 1, max = 20),
+});
+
+/// What anyone can see of a user by their username, with their library if
+/// the one looking can see it.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Profile {
+    pub user: PublicUser,
+    pub library: Option<Vec<Entry>>,
+}
+
+/// A link letting anyone who has the token see the library of a user, or
+/// only the entries with `status` or `tag` if set, until the user deletes it.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct ShareLink {
+    pub token: String,
+    pub login_id: i32,
+    pub status: Option<Status>,
+    pub tag: Option<String>,
+    pub created_at: String,
+}
+
+from_sql_row!(ShareLink {
+    token,
+    login_id,
+    status,
+    tag,
+    created_at,
+});
+
+/// Which entries a new `ShareLink` shows, every entry if neither is set.
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct NewShareLink {
+    pub status: Option<Status>,
+    pub tag: Option<String>,
+}
+
+validate!(NewShareLink {
+    tag: length(min = 1),
+});
+
+/// When a user who asked to be deleted will be, unless they cancel it.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Deletion {
+    pub delete_at: String,
+}
+
+/// How many rows of every table refer to a user, which is what deleting
+/// them removes. Entries and their tags count through the library, and so do
+/// the players they add to the counts of games played together.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq, Default)]
+pub struct UserRows {
+    pub logins: i64,
+    pub library: i64,
+    pub entries: i64,
+    pub entry_tags: i64,
+    pub account_tokens: i64,
+    pub auth_events: i64,
+    pub auth_failures: i64,
+    pub email_verifications: i64,
+    pub pending_deletions: i64,
+    pub privacy_settings: i64,
+    /// Both the friends of the user and the users they are a friend of.
+    pub friends: i64,
+    pub share_links: i64,
+    /// The players they count for in `GamePopularity`, one per game in their
+    /// library.
+    pub game_popularity: i64,
+    /// The players they count for in `GameCooccurrence`, one per pair of
+    /// different games in their library in either order.
+    pub game_cooccurrence: i64,
+}
+
+from_sql_row!(UserRows {
+    logins,
+    library,
+    entries,
+    entry_tags,
+    account_tokens,
+    auth_events,
+    auth_failures,
+    email_verifications,
+    pending_deletions,
+    privacy_settings,
+    friends,
+    share_links,
+    game_popularity,
+    game_cooccurrence,
+});
+
+impl UserRows {
+    pub fn total(&self) -> i64 {
+        self.logins + self.library + self.entries + self.entry_tags + self.account_tokens
+            + self.auth_events + self.auth_failures + self.email_verifications
+            + self.pending_deletions + self.privacy_settings + self.friends + self.share_links
+            + self.game_popularity + self.game_cooccurrence
+    }
+}
+
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
 pub struct Library {
     pub id: Option<i32>,
     pub login_id: i32,
     pub entry_id: i32,
-    pub user: Option<User>,
+    pub user: Option<PublicUser>,
     pub entry: Option<Entry>,
 }
 
+from_sql_row!(Library {
+    id,
+    login_id,
+    entry_id,
+    user: nested(Option<PublicUser>),
+    entry: nested(Option<Entry>),
+});
+
 #[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
 pub struct Entry {
     pub id: Option<i32>,
@@ -45,7 +336,28 @@ pub struct Entry {
     pub game: Option<Game>,
 }
 
-#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy)]
+from_sql_row!(Entry {
+    id,
+    game_id,
+    time_played,
+    last_update: via(Option<UtcString>),
+    status,
+    game: nested(Option<Game>),
+});
+
+validate!(Entry {
+    game_id: required_if(is_new),
+    time_played: range(min = 0),
+});
+
+impl Entry {
+    /// Whether the entry is yet to be added, rather than updated.
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
 pub enum Status {
     Frozen,
     CurrentlyPlaying,
@@ -60,6 +372,29 @@ pub struct Game {
     pub description: String,
 }
 
+from_sql_row!(Game {
+    id,
+    name,
+    description,
+});
+
+validate!(Game {
+    name: length(min = 1),
+});
+
+/// A game recommended from another game based on how many of its players
+/// also have it in their library.
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct SimilarGame {
+    pub game: Game,
+    pub score: f64,
+}
+
+from_sql_row!(SimilarGame {
+    game: nested(Game, prefix = ""),
+    score,
+});
+
 impl serialize::Encodable for UtcString {
     fn encode<S: serialize::Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         s.emit_str(&self.to_string())
@@ -79,6 +414,12 @@ impl string::ToString for UtcString {
     }
 }
 
+impl From<UtcString> for String {
+    fn from(x: UtcString) -> String {
+        x.to_string()
+    }
+}
+
 impl FromStr for UtcString {
     type Err = chrono::format::ParseError;
 
@@ -106,6 +447,42 @@ impl postgres::ToSql for UtcString {
     }
 }
 
+/// A status read from the database that isn't a variant of `Status`.
+#[derive(Debug)]
+pub struct UnknownStatus(pub String);
+
+impl fmt::Display for UnknownStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown status: {}", self.0)
+    }
+}
+
+impl Error for UnknownStatus {
+    fn description(&self) -> &str {
+        "unknown status"
+    }
+}
+
+impl FromStr for Status {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<Status, LibError> {
+        match s {
+            "Frozen" => Ok(Status::Frozen),
+            "CurrentlyPlaying" => Ok(Status::CurrentlyPlaying),
+            "Dropped" => Ok(Status::Dropped),
+            "PlanToPlay" => Ok(Status::PlanToPlay),
+            _ => Err(LibError::Cause(format!("Unknown status: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
 impl postgres::FromSql for Status {
     fn accepts(ty: &Type) -> bool {
         if let &Type::Other(ref o) = ty {
@@ -118,14 +495,10 @@ impl postgres::FromSql for Status {
     fn from_sql<R: Read>(_: &Type, raw: &mut R) -> postgres::Result<Self> {
         let mut buf = vec![];
         try!(raw.read_to_end(&mut buf));
-        let res = match str::from_utf8(&buf) {
-            Ok("Frozen") => Status::Frozen,
-            Ok("CurrentlyPlaying") => Status::CurrentlyPlaying,
-            Ok("Dropped") => Status::Dropped,
-            Ok("PlanToPlay") => Status::PlanToPlay,
-            _ => return Err(postgres::Error::WasNull),
-        };
-        Ok(res)
+        let s = String::from_utf8_lossy(&buf);
+        FromStr::from_str(&s).map_err(|_| {
+            postgres::Error::Conversion(Box::new(UnknownStatus(s.to_string())))
+        })
     }
 }
 
@@ -151,60 +524,3 @@ impl postgres::ToSql for Status {
         Ok(types::IsNull::No)
     }
 }
-
-impl FromSqlRow for User {
-    fn from_sql_row<'stmt>(row: &Row<'stmt>) -> User {
-        User {
-            id: Some(row.get(0)),
-            username: row.get(1),
-            email: row.get(3),
-        }
-    }
-}
-
-impl FromSqlRow for Login {
-    fn from_sql_row<'stmt>(row: &Row<'stmt>) -> Login {
-        Login {
-            id: Some(row.get(0)),
-            username: row.get(1),
-            password: row.get(2),
-            email: row.get(3),
-        }
-    }
-}
-
-impl FromSqlRow for Library {
-    fn from_sql_row<'stmt>(row: &Row<'stmt>) -> Library {
-        Library {
-            id: Some(row.get(0)),
-            login_id: row.get(1),
-            entry_id: row.get(2),
-            user: None,
-            entry: None,
-        }
-    }
-}
-
-impl FromSqlRow for Entry {
-    fn from_sql_row<'stmt>(row: &Row<'stmt>) -> Entry {
-        let us: UtcString = row.get(3);
-        Entry {
-            id: Some(row.get(0)),
-            game_id: Some(row.get(1)),
-            time_played: row.get(2),
-            last_update: Some(us.to_string()),
-            status: Some(row.get(4)),
-            game: None,
-        }
-    }
-}
-
-impl FromSqlRow for Game {
-    fn from_sql_row<'stmt>(row: &Row<'stmt>) -> Game {
-        Game {
-            id: Some(row.get(0)),
-            name: row.get(1),
-            description: row.get(2),
-        }
-    }
-}
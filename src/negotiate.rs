@@ -0,0 +1,209 @@
+//! Responses in whichever format the client accepts.
+//!
+//! `Negotiate::respond` reads the `Accept` header of the request and encodes
+//! the response as JSON, MessagePack, CBOR or, for lists, CSV. JSON is used
+//! when the client doesn't care, and `406 Not Acceptable` is returned when
+//! none of the formats it asks for can be produced:
+//!
+//! ```rust
+//! let games = try_iron!(db.games());
+//! req.respond(status::Ok, &games)
+//! ```
+use std::ascii::AsciiExt;
+use std::cmp::Ordering;
+use std::str;
+use cbor;
+use csv;
+use iron::prelude::*;
+use iron::{headers, status};
+use iron::status::Status;
+use rmp_serialize;
+use rustc_serialize::json::{self, Json};
+use rustc_serialize::Encodable;
+use LibError;
+
+/// A format a response can be encoded in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaType {
+    Json,
+    MsgPack,
+    Cbor,
+    Csv,
+}
+
+/// In order of preference when the client accepts several equally.
+const MEDIA_TYPES: [MediaType; 4] =
+    [MediaType::Json, MediaType::MsgPack, MediaType::Cbor, MediaType::Csv];
+
+impl MediaType {
+    pub fn mime(&self) -> &'static str {
+        match *self {
+            MediaType::Json => "application/json",
+            MediaType::MsgPack => "application/msgpack",
+            MediaType::Cbor => "application/cbor",
+            MediaType::Csv => "text/csv",
+        }
+    }
+
+    /// Whether the media range `range`, like `application/*`, includes this.
+    fn matches(&self, range: &str) -> bool {
+        let mime = self.mime();
+        range == "*/*" || range.eq_ignore_ascii_case(mime)
+            || (range.ends_with("/*") && mime.starts_with(&range[..range.len() - 1]))
+            // Also known as
+            || (*self == MediaType::MsgPack && range.eq_ignore_ascii_case("application/x-msgpack"))
+    }
+
+    /// Encodes `value`, returning `None` if it can't be represented in this
+    /// format, which only happens for CSV of anything but a list.
+    pub fn encode<T: Encodable>(&self, value: &T) -> Result<Option<Vec<u8>>, LibError> {
+        match *self {
+            MediaType::Json => json::encode(value)
+                .map(|x| Some(x.into_bytes()))
+                .map_err(LibError::other),
+            MediaType::MsgPack => {
+                let mut buf = vec![];
+                try!(value.encode(&mut rmp_serialize::Encoder::new(&mut buf))
+                     .map_err(|err| LibError::Cause(format!("{:?}", err))));
+                Ok(Some(buf))
+            },
+            MediaType::Cbor => {
+                let mut encoder = cbor::Encoder::from_memory();
+                try!(encoder.encode(&[value]).map_err(LibError::other));
+                Ok(Some(encoder.as_bytes().to_vec()))
+            },
+            MediaType::Csv => {
+                let json = try!(json::encode(value).map_err(LibError::other));
+                to_csv(&try!(Json::from_str(&json).map_err(LibError::other)))
+            },
+        }
+    }
+}
+
+/// The formats accepted by an `Accept` header, most preferred first. Without
+/// a header anything goes.
+pub fn acceptable(accept: Option<&str>) -> Vec<MediaType> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return MEDIA_TYPES.to_vec(),
+    };
+
+    let mut ranges = accept.split(',').enumerate().filter_map(|(i, x)| {
+        let mut parts = x.split(';').map(|x| x.trim());
+        let range = parts.next().unwrap_or("");
+        let q = parts.filter_map(|x| {
+            let mut kv = x.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("q"), Some(q)) => q.parse::<f32>().ok(),
+                _ => None,
+            }
+        }).next().unwrap_or(1.0);
+        if range.is_empty() { None } else { Some((i, range, q)) }
+    }).collect::<Vec<_>>();
+    // A type refused with q=0 isn't accepted through any wildcard either.
+    let refused = MEDIA_TYPES.iter().cloned().filter(|x| {
+        ranges.iter().any(|&(_, range, q)| q <= 0.0 && !range.ends_with("*") && x.matches(range))
+    }).collect::<Vec<_>>();
+    ranges.retain(|x| x.2 > 0.0);
+    // By quality, keeping the order of the header for equal quality.
+    ranges.sort_by(|a, b| match b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal) {
+        Ordering::Equal => a.0.cmp(&b.0),
+        x => x,
+    });
+
+    let mut res = vec![];
+    for &(_, range, _) in ranges.iter() {
+        for media_type in MEDIA_TYPES.iter() {
+            if media_type.matches(range) && !res.contains(media_type)
+                    && !refused.contains(media_type) {
+                res.push(*media_type);
+            }
+        }
+    }
+    res
+}
+
+/// Writes a list as CSV with a column for every field, where nested objects
+/// get columns prefixed by the name of the field like `game_name`. Lists of
+/// values are joined with `;` and anything else is written as JSON.
+fn to_csv(json: &Json) -> Result<Option<Vec<u8>>, LibError> {
+    let items = match *json {
+        Json::Array(ref items) => items,
+        _ => return Ok(None),
+    };
+
+    let rows = items.iter().map(|item| {
+        let mut row = vec![];
+        flatten("", item, &mut row);
+        row
+    }).collect::<Vec<_>>();
+    let mut columns: Vec<String> = vec![];
+    for row in rows.iter() {
+        for &(ref column, _) in row.iter() {
+            if !columns.contains(column) {
+                columns.push(column.clone());
+            }
+        }
+    }
+
+    let mut writer = csv::Writer::from_memory();
+    if !columns.is_empty() {
+        try!(writer.encode(&columns).map_err(LibError::other));
+    }
+    for row in rows.iter() {
+        let record = columns.iter().map(|column| {
+            row.iter().find(|x| &x.0 == column).map(|x| x.1.clone()).unwrap_or(String::new())
+        }).collect::<Vec<String>>();
+        try!(writer.encode(record).map_err(LibError::other));
+    }
+    Ok(Some(writer.as_bytes().to_vec()))
+}
+
+fn flatten(prefix: &str, json: &Json, row: &mut Vec<(String, String)>) {
+    let column = if prefix.is_empty() { "value".to_string() } else { prefix.to_string() };
+    match *json {
+        Json::Object(ref fields) => for (name, value) in fields.iter() {
+            let name = if prefix.is_empty() { name.clone() } else { format!("{}_{}", prefix, name) };
+            flatten(&name, value, row);
+        },
+        Json::Array(ref values) if values.iter().all(|x| x.as_string().is_some()) => {
+            let values = values.iter().filter_map(|x| x.as_string()).collect::<Vec<_>>();
+            row.push((column, values.connect(";")));
+        },
+        Json::String(ref s) => row.push((column, s.clone())),
+        Json::Null => row.push((column, String::new())),
+        ref json => row.push((column, json.to_string())),
+    }
+}
+
+/// Provides an extension method for `Request`s to respond in the format they
+/// accept.
+pub trait Negotiate {
+    /// Encodes `value` in the most preferred format that can represent it,
+    /// with `406 Not Acceptable` if there is none and `500 Internal Server
+    /// Error` if encoding fails.
+    fn respond<T: Encodable>(&self, status: Status, value: &T) -> IronResult<Response>;
+}
+
+impl<'a> Negotiate for Request<'a> {
+    fn respond<T: Encodable>(&self, status: Status, value: &T) -> IronResult<Response> {
+        let accept = self.headers.get_raw("Accept")
+            .map(|xs| xs.iter().filter_map(|x| str::from_utf8(x).ok())
+                 .collect::<Vec<_>>().connect(","));
+        for media_type in acceptable(accept.as_ref().map(|x| &x[..])).iter() {
+            let body = match media_type.encode(value) {
+                Ok(Some(body)) => body,
+                Ok(None) => continue,
+                Err(err) => return Err(IronError::new(err, status::InternalServerError)),
+            };
+            let mut res = Response::with((status, body));
+            res.headers.set(headers::ContentType(media_type.mime().parse().unwrap()));
+            res.headers.set_raw("Vary", vec![b"Accept".to_vec()]);
+            return Ok(res);
+        }
+        Err(IronError::new(
+                LibError::Cause(format!("Can only respond with {}",
+                    MEDIA_TYPES.iter().map(|x| x.mime()).collect::<Vec<_>>().connect(", "))),
+                status::NotAcceptable))
+    }
+}
@@ -0,0 +1,71 @@
+//! Who can see what of users.
+//!
+//! Others only ever see the `PublicUser` of a user, without their email.
+//! Users choose who can see their library with a `Visibility`: anyone, which
+//! is the default, only their friends, or nobody but themselves. Friends only
+//! go one way, a user adding a friend lets the friend see their library and
+//! not the other way round.
+//!
+//! Whatever they chose, users can share their library, or only the entries
+//! with a status or tag, through a `ShareLink`. Anyone with its token sees
+//! what it shares without logging in, until the user deletes it:
+//!
+//! ```text
+//! POST /api/user/1/shares {"status": "PlanToPlay", "tag": null}
+//! GET /api/shared/<token>
+//! DELETE /api/user/1/shares/<token>
+//! ```
+use export::ExportEntry;
+use models::{PublicUser, Status, User, Visibility};
+use storage::Storage;
+use LibError;
+
+/// What a `ShareLink` shows of a library.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct SharedLibrary {
+    pub user: PublicUser,
+    pub status: Option<Status>,
+    pub tag: Option<String>,
+    /// Oldest first, like `Storage::export`.
+    pub entries: Vec<ExportEntry>,
+}
+
+/// Whether `viewer`, if logged in, can see the library of the user with
+/// `owner_id`.
+pub fn can_see_library(db: &Storage, owner_id: i32, viewer: Option<&User>)
+    -> Result<bool, LibError>
+{
+    let viewer_id = viewer.and_then(|x| x.id);
+    if viewer_id == Some(owner_id) {
+        return Ok(true);
+    }
+    match try!(db.library_visibility(owner_id)) {
+        Visibility::Public => Ok(true),
+        Visibility::Friends => match viewer_id {
+            Some(id) => db.is_friend(owner_id, id),
+            None => Ok(false),
+        },
+        Visibility::Private => Ok(false),
+    }
+}
+
+/// Returns what the link with `token` shares, if there is such a link. Tags
+/// have to match exactly.
+pub fn shared_library(db: &Storage, token: &str) -> Result<Option<SharedLibrary>, LibError> {
+    let link = match try!(db.share_link(token)) {
+        Some(link) => link,
+        None => return Ok(None),
+    };
+    let user = try!(try!(db.user(link.login_id))
+                    .ok_or(LibError::Cause("No such user".to_string())));
+    let entries = try!(db.export(link.login_id)).into_iter()
+        .filter(|x| link.status.map_or(true, |status| x.status == status))
+        .filter(|x| link.tag.as_ref().map_or(true, |tag| x.tags.contains(tag)))
+        .collect();
+    Ok(Some(SharedLibrary {
+        user: user.public(),
+        status: link.status,
+        tag: link.tag,
+        entries: entries,
+    }))
+}
@@ -0,0 +1,479 @@
+//! Rate limiting with token buckets, by IP or by user.
+//!
+//! Every bucket holds up to `capacity` tokens and refills at a steady rate;
+//! a request takes a token and is refused with `429 Too Many Requests` when
+//! there is none left. Responses tell clients where they stand with the
+//! `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers,
+//! and refusals with `Retry-After`:
+//!
+//! ```rust
+//! let signup = RateLimit::new(buckets, "signup").per_ip(Limit::per_hour(10));
+//! router.post("/user", signup.around(post_login));
+//! ```
+//!
+//! Buckets are kept in a `RateLimitStore`, which is in memory for a single
+//! server or in postgres for several sharing the limits.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use iron::prelude::*;
+use iron::{status, AfterMiddleware, BeforeMiddleware, Handler};
+use iron::status::Status;
+use plugin::Extensible;
+use postgres::SslMode;
+use r2d2;
+use r2d2_postgres::PostgresConnectionManager;
+use time;
+use typemap;
+use auth::CurrentUser;
+use LibError;
+
+/// Buckets older than this, in seconds, are full again and can be dropped.
+const PRUNE_AFTER: f64 = 24.0 * 60.0 * 60.0;
+
+/// How often stores drop old buckets, in calls to `take`.
+const PRUNE_EVERY: usize = 1024;
+
+/// A bucket of `capacity` tokens, refilled completely over `period` seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub capacity: u32,
+    pub period: f64,
+}
+
+impl Limit {
+    pub fn new(capacity: u32, period: f64) -> Limit {
+        Limit {
+            capacity: capacity,
+            period: period,
+        }
+    }
+
+    pub fn per_minute(capacity: u32) -> Limit {
+        Limit::new(capacity, 60.0)
+    }
+
+    pub fn per_hour(capacity: u32) -> Limit {
+        Limit::new(capacity, 60.0 * 60.0)
+    }
+
+    /// Tokens per second.
+    fn rate(&self) -> f64 {
+        self.capacity as f64 / self.period
+    }
+}
+
+/// What taking a token from a bucket came to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    /// Whole tokens left.
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until there is a token again, if there is none now.
+    pub retry_after: u64,
+}
+
+/// The state of a bucket at the time it was last used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    /// Seconds since the epoch.
+    pub updated: f64,
+}
+
+impl Bucket {
+    pub fn full(limit: Limit, now: f64) -> Bucket {
+        Bucket {
+            tokens: limit.capacity as f64,
+            updated: now,
+        }
+    }
+
+    /// Refills the bucket up to `now` and takes a token if there is one.
+    pub fn take(&mut self, limit: Limit, now: f64) -> Decision {
+        self.refill(limit, now);
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+        self.decision(limit, allowed)
+    }
+
+    /// Like `take` but leaves the tokens be.
+    pub fn peek(&mut self, limit: Limit, now: f64) -> Decision {
+        self.refill(limit, now);
+        let allowed = self.tokens >= 1.0;
+        self.decision(limit, allowed)
+    }
+
+    fn refill(&mut self, limit: Limit, now: f64) {
+        let elapsed = if now > self.updated { now - self.updated } else { 0.0 };
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(limit.capacity as f64);
+        self.updated = now;
+    }
+
+    fn decision(&self, limit: Limit, allowed: bool) -> Decision {
+        let capacity = limit.capacity as f64;
+        let retry_after = if allowed { 0.0 } else { (1.0 - self.tokens) / limit.rate() };
+        Decision {
+            allowed: allowed,
+            limit: limit.capacity,
+            remaining: self.tokens.floor() as u32,
+            reset: ((capacity - self.tokens) / limit.rate()).ceil() as u64,
+            retry_after: retry_after.ceil() as u64,
+        }
+    }
+}
+
+/// Where buckets are kept. Taking a token must be atomic, so that requests
+/// at the same time can't take the same token.
+pub trait RateLimitStore {
+    /// Takes a token from the bucket `key`, which starts out full, at `now`
+    /// in seconds since the epoch.
+    fn take(&self, key: &str, limit: Limit, now: f64) -> Result<Decision, LibError>;
+
+    /// Whether there is a token in the bucket `key`, without taking it.
+    fn peek(&self, key: &str, limit: Limit, now: f64) -> Result<Decision, LibError>;
+}
+
+/// Shared by every `RateLimit` of a server.
+pub type Buckets = Arc<Box<RateLimitStore + Send + Sync>>;
+
+/// Buckets of a single server.
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    calls: AtomicUsize,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> MemoryRateLimitStore {
+        MemoryRateLimitStore {
+            buckets: Mutex::new(HashMap::new()),
+            calls: ATOMIC_USIZE_INIT,
+        }
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn take(&self, key: &str, limit: Limit, now: f64) -> Result<Decision, LibError> {
+        let mut buckets = try!(self.buckets.lock().map_err(|_| LibError::Cause(
+                    "A previous request panicked while rate limiting".to_string())));
+        if self.calls.fetch_add(1, Ordering::SeqCst) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            let old = buckets.iter()
+                .filter(|&(_, x)| now - x.updated > PRUNE_AFTER)
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in old.iter() {
+                buckets.remove(key);
+            }
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket::full(limit, now));
+        Ok(bucket.take(limit, now))
+    }
+
+    fn peek(&self, key: &str, limit: Limit, now: f64) -> Result<Decision, LibError> {
+        let buckets = try!(self.buckets.lock().map_err(|_| LibError::Cause(
+                    "A previous request panicked while rate limiting".to_string())));
+        let mut bucket = buckets.get(key).cloned().unwrap_or(Bucket::full(limit, now));
+        Ok(bucket.peek(limit, now))
+    }
+}
+
+/// Buckets in the `RateLimitBucket` table, shared by every server using the
+/// same database.
+pub struct PgRateLimitStore {
+    pool: r2d2::Pool<PostgresConnectionManager>,
+    calls: AtomicUsize,
+}
+
+impl PgRateLimitStore {
+    /// Opens a connection pool of its own to the database at `url`.
+    pub fn new(url: &str) -> Result<PgRateLimitStore, LibError> {
+        let manager = PostgresConnectionManager::new(url, SslMode::None);
+        let error_handler = Box::new(r2d2::NoopErrorHandler);
+        let pool = try!(r2d2::Pool::new(Default::default(), manager, error_handler)
+                        .map_err(LibError::other));
+        Ok(PgRateLimitStore {
+            pool: pool,
+            calls: ATOMIC_USIZE_INIT,
+        })
+    }
+}
+
+impl RateLimitStore for PgRateLimitStore {
+    fn take(&self, key: &str, limit: Limit, now: f64) -> Result<Decision, LibError> {
+        let conn = try!(self.pool.get().map_err(LibError::other));
+        if self.calls.fetch_add(1, Ordering::SeqCst) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            try!(conn.execute("DELETE FROM RateLimitBucket WHERE updated_at < $1",
+                              &[&(now - PRUNE_AFTER)]).map_err(LibError::other));
+        }
+
+        let trans = try!(conn.transaction().map_err(LibError::other));
+        let found = {
+            let stmt = try!(trans.prepare(
+                    "SELECT tokens, updated_at FROM RateLimitBucket WHERE key = $1 FOR UPDATE")
+                .map_err(LibError::other));
+            let rows = try!(stmt.query(&[&key]).map_err(LibError::other));
+            let res = rows.iter().next().map(|x| Bucket { tokens: x.get(0), updated: x.get(1) });
+            res
+        };
+        let mut bucket = found.unwrap_or(Bucket::full(limit, now));
+        let decision = bucket.take(limit, now);
+        // Two servers creating the same bucket at once makes one of them
+        // fail here, which `RateLimit` lets through.
+        let sql = if found.is_some() {
+            "UPDATE RateLimitBucket SET tokens = $2, updated_at = $3 WHERE key = $1"
+        } else {
+            "INSERT INTO RateLimitBucket (key, tokens, updated_at) VALUES ($1, $2, $3)"
+        };
+        try!(trans.execute(sql, &[&key, &bucket.tokens, &bucket.updated]).map_err(LibError::other));
+        try!(trans.commit().map_err(LibError::other));
+        Ok(decision)
+    }
+
+    fn peek(&self, key: &str, limit: Limit, now: f64) -> Result<Decision, LibError> {
+        let conn = try!(self.pool.get().map_err(LibError::other));
+        let stmt = try!(conn.prepare("SELECT tokens, updated_at FROM RateLimitBucket WHERE key = $1")
+                        .map_err(LibError::other));
+        let rows = try!(stmt.query(&[&key]).map_err(LibError::other));
+        let mut bucket = rows.iter().next()
+            .map(|x| Bucket { tokens: x.get(0), updated: x.get(1) })
+            .unwrap_or(Bucket::full(limit, now));
+        Ok(bucket.peek(limit, now))
+    }
+}
+
+/// The tightest decision of every `RateLimit` a request went through, which
+/// is what its headers show.
+struct Tightest;
+
+impl typemap::Key for Tightest {
+    type Value = Decision;
+}
+
+fn now() -> f64 {
+    let now = time::get_time();
+    now.sec as f64 + now.nsec as f64 / 1e9
+}
+
+fn set_headers(res: &mut Response, decision: &Decision) {
+    res.headers.set_raw("RateLimit-Limit", vec![decision.limit.to_string().into_bytes()]);
+    res.headers.set_raw("RateLimit-Remaining", vec![decision.remaining.to_string().into_bytes()]);
+    res.headers.set_raw("RateLimit-Reset", vec![decision.reset.to_string().into_bytes()]);
+    if !decision.allowed {
+        res.headers.set_raw("Retry-After", vec![decision.retry_after.to_string().into_bytes()]);
+    }
+}
+
+/// Limits requests by user when they are authenticated and `per_user` is
+/// set, or else by IP when `per_ip` is set. Buckets of different `scope`s are
+/// separate.
+///
+/// Linked as an `AfterMiddleware` as well it adds the headers; `around` does
+/// both. If the store fails, requests are let through rather than refused.
+#[derive(Clone)]
+pub struct RateLimit {
+    buckets: Buckets,
+    scope: &'static str,
+    per_ip: Option<Limit>,
+    per_user: Option<Limit>,
+    counting: Option<Status>,
+    credentials_only: bool,
+}
+
+impl RateLimit {
+    pub fn new(buckets: Buckets, scope: &'static str) -> RateLimit {
+        RateLimit {
+            buckets: buckets,
+            scope: scope,
+            per_ip: None,
+            per_user: None,
+            counting: None,
+            credentials_only: false,
+        }
+    }
+
+    pub fn per_ip(mut self, limit: Limit) -> RateLimit {
+        self.per_ip = Some(limit);
+        self
+    }
+
+    pub fn per_user(mut self, limit: Limit) -> RateLimit {
+        self.per_user = Some(limit);
+        self
+    }
+
+    /// Only takes tokens for responses with `status`, still refusing every
+    /// request once there are none left. With `Unauthorized` this limits
+    /// failed attempts to log in rather than requests with credentials.
+    pub fn only_counting(mut self, status: Status) -> RateLimit {
+        self.counting = Some(status);
+        self
+    }
+
+    /// Leaves requests without an `Authorization` header alone.
+    pub fn credentials_only(mut self) -> RateLimit {
+        self.credentials_only = true;
+        self
+    }
+
+    /// Returns `handler` with the limit applied before it.
+    pub fn around<H: Handler>(self, handler: H) -> Chain {
+        let mut chain = Chain::new(handler);
+        chain.link_before(self.clone());
+        chain.link_after(self);
+        chain
+    }
+
+    fn bucket(&self, req: &Request) -> Option<(String, Limit)> {
+        if self.credentials_only && req.headers.get_raw("Authorization").is_none() {
+            return None;
+        }
+        match (req.current_user().and_then(|x| x.id), self.per_user) {
+            (Some(id), Some(limit)) => Some((format!("{}:user:{}", self.scope, id), limit)),
+            _ => self.per_ip.map(|limit| {
+                (format!("{}:ip:{}", self.scope, req.remote_addr.ip()), limit)
+            }),
+        }
+    }
+
+    /// Takes a token for a response with the status being counted.
+    fn count(&self, req: &mut Request, res: &Response) {
+        if self.counting.is_none() || res.status != self.counting {
+            return;
+        }
+        if let Some((key, limit)) = self.bucket(req) {
+            if let Ok(decision) = self.buckets.take(&key, limit, now()) {
+                keep_tightest(req, decision);
+            }
+        }
+    }
+}
+
+/// Keeps `decision` for the headers if it is tighter than what is kept.
+fn keep_tightest(req: &mut Request, decision: Decision) {
+    let tightest = match req.extensions().get::<Tightest>() {
+        Some(previous) if previous.remaining < decision.remaining && decision.allowed =>
+            *previous,
+        _ => decision,
+    };
+    req.extensions_mut().insert::<Tightest>(tightest);
+}
+
+impl BeforeMiddleware for RateLimit {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let (key, limit) = match self.bucket(req) {
+            Some(bucket) => bucket,
+            None => return Ok(()),
+        };
+        let decision = match self.counting {
+            None => self.buckets.take(&key, limit, now()),
+            Some(_) => self.buckets.peek(&key, limit, now()),
+        };
+        let decision = match decision {
+            Ok(decision) => decision,
+            Err(_) => return Ok(()),
+        };
+
+        if decision.allowed {
+            if self.counting.is_none() {
+                keep_tightest(req, decision);
+            }
+            Ok(())
+        } else {
+            keep_tightest(req, decision);
+            let mut err = IronError::new(
+                LibError::Cause(format!("Too many requests, try again in {} seconds",
+                                        decision.retry_after)),
+                status::TooManyRequests);
+            set_headers(&mut err.response, &decision);
+            Err(err)
+        }
+    }
+}
+
+impl AfterMiddleware for RateLimit {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        self.count(req, &res);
+        if let Some(decision) = req.extensions().get::<Tightest>() {
+            set_headers(&mut res, decision);
+        }
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        self.count(req, &err.response);
+        if let Some(decision) = req.extensions().get::<Tightest>() {
+            set_headers(&mut err.response, decision);
+        }
+        Err(err)
+    }
+}
+
+/// The limits of the API, all kept in the same `buckets`.
+#[derive(Clone)]
+pub struct RateLimits {
+    pub buckets: Buckets,
+    /// Failed attempts to log in by IP.
+    pub login: Limit,
+    /// Accounts created by IP.
+    pub signup: Limit,
+    /// Entries added or updated by user.
+    pub entries: Limit,
+    /// Requests which mail a user, like resetting a password, by IP.
+    pub mail: Limit,
+    /// Any request by user.
+    pub user: Limit,
+    /// Any request by IP, when not authenticated.
+    pub ip: Limit,
+}
+
+impl RateLimits {
+    /// The default limits, generous enough for any client that isn't
+    /// misbehaving.
+    pub fn new(buckets: Buckets) -> RateLimits {
+        RateLimits {
+            buckets: buckets,
+            login: Limit::per_minute(30),
+            signup: Limit::per_hour(10),
+            entries: Limit::per_minute(60),
+            mail: Limit::per_hour(10),
+            user: Limit::per_minute(600),
+            ip: Limit::per_minute(300),
+        }
+    }
+
+    pub fn in_memory() -> RateLimits {
+        RateLimits::new(Arc::new(
+                Box::new(MemoryRateLimitStore::new()) as Box<RateLimitStore + Send + Sync>))
+    }
+
+    /// Every request to the API, linked after `Authenticate`.
+    pub fn api(&self) -> RateLimit {
+        RateLimit::new(self.buckets.clone(), "api").per_user(self.user).per_ip(self.ip)
+    }
+
+    /// Failed attempts to log in, linked both before and after
+    /// `Authenticate` to see them fail.
+    pub fn login(&self) -> RateLimit {
+        RateLimit::new(self.buckets.clone(), "login")
+            .per_ip(self.login)
+            .only_counting(status::Unauthorized)
+            .credentials_only()
+    }
+
+    pub fn signup(&self) -> RateLimit {
+        RateLimit::new(self.buckets.clone(), "signup").per_ip(self.signup)
+    }
+
+    pub fn entries(&self) -> RateLimit {
+        RateLimit::new(self.buckets.clone(), "entries").per_user(self.entries)
+    }
+
+    pub fn mail(&self) -> RateLimit {
+        RateLimit::new(self.buckets.clone(), "mail").per_ip(self.mail)
+    }
+}
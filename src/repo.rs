@@ -0,0 +1,692 @@
+//! Typed access to the database, keeping SQL out of the request handlers.
+//!
+//! Every repository borrows a connection, which may just as well be a
+//! transaction, so that several calls can be made atomically:
+//!
+//! ```rust
+//! let trans = try!(db.transaction());
+//! let game = try!(GameRepo::new(&trans).add(&game));
+//! try!(LibraryRepo::new(&trans).add_entry(user_id, &entry, &[]));
+//! try!(trans.commit());
+//! ```
+use std::collections::HashMap;
+use chrono::{DateTime, UTC};
+use postgres::GenericConnection;
+use postgres::types::ToSql;
+use export::{self, ExportEntry, TIMESTAMP_FORMAT};
+use lockout;
+use models::{AuthEvent, Entry, Game, Library, Login, ShareLink, SimilarGame, Status, User,
+             UserRows, UserUpdate, Visibility};
+use storage::{AccountToken, Failures};
+use {LibError, TryCollectSql, TryFromSqlRow};
+
+/// Runs a query and converts every row of the result.
+fn query<T: TryFromSqlRow>(conn: &GenericConnection, sql: &str, params: &[&ToSql])
+    -> Result<Vec<T>, LibError>
+{
+    let stmt = try!(conn.prepare(sql).map_err(LibError::other));
+    let rows = try!(stmt.query(params).map_err(LibError::other));
+    let res = rows.try_collect_sql();
+    res
+}
+
+fn count(conn: &GenericConnection, sql: &str) -> Result<i64, LibError> {
+    let stmt = try!(conn.prepare(sql).map_err(LibError::other));
+    let rows = try!(stmt.query(&[]).map_err(LibError::other));
+    let res = rows.iter().next().map(|x| x.get_opt(0)).unwrap_or(Ok(0)).map_err(LibError::other);
+    res
+}
+
+fn execute(conn: &GenericConnection, sql: &str, params: &[&ToSql]) -> Result<u64, LibError> {
+    conn.execute(sql, params).map_err(LibError::other)
+}
+
+/// The schema itself rather than what is kept in it.
+pub struct SchemaRepo<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> SchemaRepo<'a> {
+    pub fn new(conn: &'a GenericConnection) -> SchemaRepo<'a> {
+        SchemaRepo { conn: conn }
+    }
+
+    pub fn ping(&self) -> Result<(), LibError> {
+        execute(self.conn, "SELECT 1", &[]).map(|_| ())
+    }
+
+    /// Returns the versions of the migrations applied, oldest first.
+    pub fn migrations(&self) -> Result<Vec<i32>, LibError> {
+        let stmt = try!(self.conn.prepare("SELECT version FROM SchemaMigration ORDER BY version")
+                        .map_err(LibError::other));
+        let rows = try!(stmt.query(&[]).map_err(LibError::other));
+        let res = rows.iter().map(|x| x.get_opt(0)).collect::<Result<Vec<i32>, _>>()
+            .map_err(LibError::other);
+        res
+    }
+}
+
+pub struct GameRepo<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> GameRepo<'a> {
+    pub fn new(conn: &'a GenericConnection) -> GameRepo<'a> {
+        GameRepo { conn: conn }
+    }
+
+    /// Returns every game ordered by name.
+    pub fn all(&self) -> Result<Vec<Game>, LibError> {
+        query(self.conn, "SELECT * FROM Game ORDER BY name", &[])
+    }
+
+    pub fn count(&self) -> Result<i64, LibError> {
+        count(self.conn, "SELECT count(*) FROM Game")
+    }
+
+    pub fn find(&self, id: i32) -> Result<Option<Game>, LibError> {
+        query(self.conn, "SELECT * FROM Game WHERE id = $1", &[&id]).map(|mut x| x.pop())
+    }
+
+    /// Returns the games named `name`, ignoring case.
+    pub fn find_by_name(&self, name: &str) -> Result<Vec<Game>, LibError> {
+        query(self.conn, "SELECT * FROM Game WHERE lower(name) = lower($1) ORDER BY id", &[&name])
+    }
+
+    pub fn add(&self, game: &Game) -> Result<Game, LibError> {
+        let mut res = try!(query(self.conn,
+                "INSERT INTO Game (name, description) VALUES ($1, $2) RETURNING *",
+                &[&game.name, &game.description]));
+        res.pop().ok_or(LibError::Cause("Failed inserting game".to_string()))
+    }
+
+    /// Returns the games most often found in the same libraries as the given
+    /// game, leaving out those already in the library of `user_id`.
+    pub fn similar(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>
+    {
+        query(self.conn,
+              "SELECT g.*, co.players / sqrt(pa.players::float8 * pb.players) AS score \
+                FROM GameCooccurrence co \
+                JOIN GamePopularity pa ON pa.game_id = co.game_id \
+                JOIN GamePopularity pb ON pb.game_id = co.other_id \
+                JOIN Game g ON g.id = co.other_id \
+                WHERE co.game_id = $1 AND NOT EXISTS \
+                    (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id \
+                        WHERE li.login_id = $2 AND e.game_id = co.other_id) \
+                ORDER BY score DESC, g.name LIMIT $3",
+              &[&id, &user_id, &limit])
+    }
+}
+
+pub struct UserRepo<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> UserRepo<'a> {
+    pub fn new(conn: &'a GenericConnection) -> UserRepo<'a> {
+        UserRepo { conn: conn }
+    }
+
+    pub fn all(&self) -> Result<Vec<User>, LibError> {
+        query(self.conn, "SELECT * FROM Login", &[])
+    }
+
+    pub fn count(&self) -> Result<i64, LibError> {
+        count(self.conn, "SELECT count(*) FROM Login")
+    }
+
+    pub fn find(&self, id: i32) -> Result<Option<User>, LibError> {
+        query(self.conn, "SELECT * FROM Login WHERE id = $1", &[&id]).map(|mut x| x.pop())
+    }
+
+    pub fn find_by_name(&self, username: &str) -> Result<Option<User>, LibError> {
+        query(self.conn, "SELECT * FROM Login WHERE username = $1", &[&username])
+            .map(|mut x| x.pop())
+    }
+
+    /// Returns the user with `email`, ignoring case.
+    pub fn find_by_email(&self, email: &str) -> Result<Option<User>, LibError> {
+        query(self.conn, "SELECT * FROM Login WHERE email = $1::text::citext", &[&email])
+            .map(|mut x| x.pop())
+    }
+
+    /// Creates a new user, failing if the username or email is taken.
+    pub fn add(&self, login: &Login) -> Result<User, LibError> {
+        let mut res = try!(query(self.conn,
+                "INSERT INTO Login (username, password, email) \
+                    VALUES ($1, $2, $3) RETURNING id, username, email",
+                &[&login.username, &login.password, &login.email]));
+        res.pop().ok_or(LibError::Cause("Failed inserting user".to_string()))
+    }
+
+    /// Returns the user with the given credentials, if any.
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError> {
+        query(self.conn,
+              "SELECT id, username, email FROM Login WHERE username = $1 AND password = $2",
+              &[&username, &password]).map(|mut x| x.pop())
+    }
+
+    /// Changes the username or email of a user if set, failing if either is
+    /// taken.
+    pub fn update(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+        query(self.conn,
+              "UPDATE Login SET username = COALESCE($2, username), \
+                email = COALESCE($3::text::citext, email) \
+                WHERE id = $1 RETURNING id, username, email",
+              &[&id, &update.username, &update.email]).map(|mut x| x.pop())
+    }
+
+    /// Deletes a user with their library and everything else of theirs.
+    /// Their library is emptied first, which takes their games out of the
+    /// games played together while the entries are still there to say which
+    /// games they were. Then their entries are deleted, which cascades to
+    /// their tags, and the login, which cascades to everything else.
+    pub fn delete(&self, id: i32) -> Result<bool, LibError> {
+        let trans = try!(self.conn.transaction().map_err(LibError::other));
+        let entries = {
+            let stmt = try!(trans.prepare("SELECT entry_id FROM Library WHERE login_id = $1")
+                            .map_err(LibError::other));
+            let rows = try!(stmt.query(&[&id]).map_err(LibError::other));
+            let res = rows.iter().map(|x| x.get_opt(0)).collect::<Result<Vec<i32>, _>>()
+                .map_err(LibError::other);
+            try!(res)
+        };
+        try!(execute(&trans, "DELETE FROM Library WHERE login_id = $1", &[&id]));
+        for entry_id in entries.iter() {
+            try!(execute(&trans, "DELETE FROM Entry WHERE id = $1", &[entry_id]));
+        }
+        let deleted = try!(execute(&trans, "DELETE FROM Login WHERE id = $1", &[&id]));
+        try!(trans.commit().map_err(LibError::other));
+        Ok(deleted > 0)
+    }
+
+    /// Counts the rows of every table that refer to a user.
+    pub fn rows(&self, id: i32) -> Result<UserRows, LibError> {
+        let entries = "SELECT entry_id FROM Library WHERE login_id = $1";
+        let games = "SELECT DISTINCT e.game_id FROM Library li \
+            JOIN Entry e ON e.id = li.entry_id WHERE li.login_id = $1";
+        query(self.conn, &format!(
+                "SELECT (SELECT count(*) FROM Login WHERE id = $1) AS logins, \
+                    (SELECT count(*) FROM Library WHERE login_id = $1) AS library, \
+                    (SELECT count(*) FROM Entry WHERE id IN ({0})) AS entries, \
+                    (SELECT count(*) FROM EntryTag WHERE entry_id IN ({0})) AS entry_tags, \
+                    (SELECT count(*) FROM AccountToken WHERE login_id = $1) AS account_tokens, \
+                    (SELECT count(*) FROM AuthEvent WHERE login_id = $1) AS auth_events, \
+                    (SELECT count(*) FROM AuthFailure WHERE key = $2) AS auth_failures, \
+                    (SELECT count(*) FROM EmailVerification WHERE login_id = $1) \
+                        AS email_verifications, \
+                    (SELECT count(*) FROM PendingDeletion WHERE login_id = $1) \
+                        AS pending_deletions, \
+                    (SELECT count(*) FROM Privacy WHERE login_id = $1) AS privacy_settings, \
+                    (SELECT count(*) FROM Friend WHERE login_id = $1 OR friend_id = $1) \
+                        AS friends, \
+                    (SELECT count(*) FROM ShareLink WHERE login_id = $1) AS share_links, \
+                    (SELECT count(*) FROM ({1}) a) AS game_popularity, \
+                    (SELECT count(*) FROM ({1}) a JOIN ({1}) b ON a.game_id <> b.game_id) \
+                        AS game_cooccurrence", entries, games),
+              &[&id, &lockout::login_key(id)]).map(|mut x| x.pop().unwrap_or(UserRows::default()))
+    }
+
+    /// Changes the password of a user, returning whether there was such a
+    /// user.
+    pub fn set_password(&self, id: i32, password: &str) -> Result<bool, LibError> {
+        execute(self.conn, "UPDATE Login SET password = $2 WHERE id = $1", &[&id, &password])
+            .map(|x| x > 0)
+    }
+}
+
+/// Who can see the libraries of users, and the links sharing them.
+pub struct PrivacyRepo<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> PrivacyRepo<'a> {
+    pub fn new(conn: &'a GenericConnection) -> PrivacyRepo<'a> {
+        PrivacyRepo { conn: conn }
+    }
+
+    pub fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError> {
+        let stmt = try!(self.conn.prepare("SELECT library FROM Privacy WHERE login_id = $1")
+                        .map_err(LibError::other));
+        let rows = try!(stmt.query(&[&user_id]).map_err(LibError::other));
+        let res = match rows.iter().next() {
+            Some(row) => {
+                let library: String = try!(row.get_opt(0).map_err(LibError::other));
+                library.parse()
+            },
+            None => Ok(Visibility::Public),
+        };
+        res
+    }
+
+    pub fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>
+    {
+        let visibility = visibility.to_string();
+        let params: &[&ToSql] = &[&user_id, &visibility];
+        let updated = try!(execute(self.conn,
+                "UPDATE Privacy SET library = $2 WHERE login_id = $1", params));
+        if updated == 0 {
+            try!(execute(self.conn,
+                    "INSERT INTO Privacy (login_id, library) VALUES ($1, $2)", params));
+        }
+        Ok(())
+    }
+
+    /// Returns the friends of a user by username.
+    pub fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError> {
+        query(self.conn,
+              "SELECT lo.* FROM Friend f JOIN Login lo ON lo.id = f.friend_id \
+                WHERE f.login_id = $1 ORDER BY lo.username",
+              &[&user_id])
+    }
+
+    pub fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError> {
+        execute(self.conn,
+                "INSERT INTO Friend (login_id, friend_id) SELECT $1, $2 \
+                    WHERE NOT EXISTS (SELECT 1 FROM Friend WHERE login_id = $1 AND friend_id = $2)",
+                &[&user_id, &friend_id]).map(|_| ())
+    }
+
+    pub fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        execute(self.conn, "DELETE FROM Friend WHERE login_id = $1 AND friend_id = $2",
+                &[&user_id, &friend_id]).map(|x| x > 0)
+    }
+
+    pub fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        let stmt = try!(self.conn.prepare(
+                "SELECT 1 FROM Friend WHERE login_id = $1 AND friend_id = $2")
+            .map_err(LibError::other));
+        let rows = try!(stmt.query(&[&user_id, &friend_id]).map_err(LibError::other));
+        let res = rows.iter().next().is_some();
+        Ok(res)
+    }
+
+    pub fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError> {
+        let mut res = try!(query(self.conn, &format!(
+                "INSERT INTO ShareLink (token, login_id, status, tag, created_at) \
+                    VALUES ($1, $2, $3, $4, $5::text::timestamptz) \
+                    RETURNING token, login_id, status, tag, \
+                    to_char(created_at AT TIME ZONE 'UTC', '{}') AS created_at",
+                TIMESTAMP_FORMAT),
+                &[&link.token, &link.login_id, &link.status, &link.tag, &link.created_at]));
+        res.pop().ok_or(LibError::Cause("Failed inserting share link".to_string()))
+    }
+
+    /// Returns the links sharing the library of a user, oldest first.
+    pub fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError> {
+        query(self.conn, &format!(
+                "SELECT token, login_id, status, tag, \
+                    to_char(created_at AT TIME ZONE 'UTC', '{}') AS created_at \
+                    FROM ShareLink WHERE login_id = $1 ORDER BY created_at, token",
+                TIMESTAMP_FORMAT),
+              &[&user_id])
+    }
+
+    pub fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError> {
+        query(self.conn, &format!(
+                "SELECT token, login_id, status, tag, \
+                    to_char(created_at AT TIME ZONE 'UTC', '{}') AS created_at \
+                    FROM ShareLink WHERE token = $1", TIMESTAMP_FORMAT),
+              &[&token]).map(|mut x| x.pop())
+    }
+
+    pub fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError> {
+        execute(self.conn, "DELETE FROM ShareLink WHERE login_id = $1 AND token = $2",
+                &[&user_id, &token]).map(|x| x > 0)
+    }
+}
+
+/// Failed attempts to log in, the tokens mailed to users, verified emails,
+/// pending deletions and the audit trail of logging in.
+pub struct AuthRepo<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> AuthRepo<'a> {
+    pub fn new(conn: &'a GenericConnection) -> AuthRepo<'a> {
+        AuthRepo { conn: conn }
+    }
+
+    pub fn failures(&self, key: &str) -> Result<Option<Failures>, LibError> {
+        let stmt = try!(self.conn.prepare(&format!(
+                "SELECT failures, to_char(last_failure AT TIME ZONE 'UTC', '{0}'), \
+                    to_char(blocked_until AT TIME ZONE 'UTC', '{0}') \
+                    FROM AuthFailure WHERE key = $1", TIMESTAMP_FORMAT))
+            .map_err(LibError::other));
+        let rows = try!(stmt.query(&[&key]).map_err(LibError::other));
+        let res = match rows.iter().next() {
+            Some(row) => {
+                let last_failure: String = try!(row.get_opt(1).map_err(LibError::other));
+                let blocked_until: Option<String> = try!(row.get_opt(2).map_err(LibError::other));
+                Ok(Some(Failures {
+                    failures: try!(row.get_opt(0).map_err(LibError::other)),
+                    last_failure: try!(export::parse_timestamp(&last_failure)),
+                    blocked_until: match blocked_until {
+                        Some(x) => Some(try!(export::parse_timestamp(&x))),
+                        None => None,
+                    },
+                }))
+            },
+            None => Ok(None),
+        };
+        res
+    }
+
+    pub fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError> {
+        let last_failure = export::format_timestamp(&failures.last_failure);
+        let blocked_until = failures.blocked_until.map(|x| export::format_timestamp(&x));
+        let params: &[&ToSql] = &[&key, &failures.failures, &last_failure, &blocked_until];
+        let updated = try!(execute(self.conn,
+                "UPDATE AuthFailure SET failures = $2, last_failure = $3::text::timestamptz, \
+                    blocked_until = $4::text::timestamptz WHERE key = $1", params));
+        if updated == 0 {
+            try!(execute(self.conn,
+                    "INSERT INTO AuthFailure (key, failures, last_failure, blocked_until) \
+                        VALUES ($1, $2, $3::text::timestamptz, $4::text::timestamptz)", params));
+        }
+        Ok(())
+    }
+
+    pub fn clear_failures(&self, key: &str) -> Result<(), LibError> {
+        execute(self.conn, "DELETE FROM AuthFailure WHERE key = $1", &[&key]).map(|_| ())
+    }
+
+    pub fn add_token(&self, token: &AccountToken) -> Result<(), LibError> {
+        execute(self.conn,
+                "INSERT INTO AccountToken (token, login_id, purpose, expires_at) \
+                    VALUES ($1, $2, $3, $4::text::timestamptz)",
+                &[&token.token, &token.login_id, &token.purpose,
+                  &export::format_timestamp(&token.expires_at)]).map(|_| ())
+    }
+
+    /// Removes the token for `purpose` and returns it, expired or not.
+    pub fn take_token(&self, token: &str, purpose: &str)
+        -> Result<Option<AccountToken>, LibError>
+    {
+        let stmt = try!(self.conn.prepare(&format!(
+                "DELETE FROM AccountToken WHERE token = $1 AND purpose = $2 \
+                    RETURNING login_id, to_char(expires_at AT TIME ZONE 'UTC', '{}')",
+                TIMESTAMP_FORMAT)).map_err(LibError::other));
+        let rows = try!(stmt.query(&[&token, &purpose]).map_err(LibError::other));
+        let res = match rows.iter().next() {
+            Some(row) => {
+                let expires_at: String = try!(row.get_opt(1).map_err(LibError::other));
+                Ok(Some(AccountToken {
+                    token: token.to_string(),
+                    login_id: try!(row.get_opt(0).map_err(LibError::other)),
+                    purpose: purpose.to_string(),
+                    expires_at: try!(export::parse_timestamp(&expires_at)),
+                }))
+            },
+            None => Ok(None),
+        };
+        res
+    }
+
+    pub fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError> {
+        execute(self.conn, "DELETE FROM AccountToken WHERE login_id = $1 AND purpose = $2",
+                &[&user_id, &purpose]).map(|_| ())
+    }
+
+    /// Keeps that a user verified `email`, replacing whatever they verified
+    /// before.
+    pub fn verify_email(&self, user_id: i32, email: &str, at: &DateTime<UTC>)
+        -> Result<(), LibError>
+    {
+        let at = export::format_timestamp(at);
+        let params: &[&ToSql] = &[&user_id, &email, &at];
+        let updated = try!(execute(self.conn,
+                "UPDATE EmailVerification SET email = $2::text::citext, \
+                    verified_at = $3::text::timestamptz WHERE login_id = $1", params));
+        if updated == 0 {
+            try!(execute(self.conn,
+                    "INSERT INTO EmailVerification (login_id, email, verified_at) \
+                        VALUES ($1, $2::text::citext, $3::text::timestamptz)", params));
+        }
+        Ok(())
+    }
+
+    pub fn schedule_deletion(&self, user_id: i32, at: &DateTime<UTC>) -> Result<(), LibError> {
+        let at = export::format_timestamp(at);
+        let params: &[&ToSql] = &[&user_id, &at];
+        let updated = try!(execute(self.conn,
+                "UPDATE PendingDeletion SET delete_at = $2::text::timestamptz \
+                    WHERE login_id = $1", params));
+        if updated == 0 {
+            try!(execute(self.conn,
+                    "INSERT INTO PendingDeletion (login_id, delete_at) \
+                        VALUES ($1, $2::text::timestamptz)", params));
+        }
+        Ok(())
+    }
+
+    pub fn deletion(&self, user_id: i32) -> Result<Option<DateTime<UTC>>, LibError> {
+        let stmt = try!(self.conn.prepare(&format!(
+                "SELECT to_char(delete_at AT TIME ZONE 'UTC', '{}') FROM PendingDeletion \
+                    WHERE login_id = $1", TIMESTAMP_FORMAT)).map_err(LibError::other));
+        let rows = try!(stmt.query(&[&user_id]).map_err(LibError::other));
+        let res = match rows.iter().next() {
+            Some(row) => {
+                let at: String = try!(row.get_opt(0).map_err(LibError::other));
+                Ok(Some(try!(export::parse_timestamp(&at))))
+            },
+            None => Ok(None),
+        };
+        res
+    }
+
+    pub fn cancel_deletion(&self, user_id: i32) -> Result<bool, LibError> {
+        execute(self.conn, "DELETE FROM PendingDeletion WHERE login_id = $1", &[&user_id])
+            .map(|x| x > 0)
+    }
+
+    /// Returns the users to be deleted by `now`.
+    pub fn due_deletions(&self, now: &DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+        let stmt = try!(self.conn.prepare(
+                "SELECT login_id FROM PendingDeletion \
+                    WHERE delete_at <= $1::text::timestamptz ORDER BY login_id")
+            .map_err(LibError::other));
+        let rows = try!(stmt.query(&[&export::format_timestamp(now)]).map_err(LibError::other));
+        let res = rows.iter().map(|x| x.get_opt(0)).collect::<Result<Vec<i32>, _>>()
+            .map_err(LibError::other);
+        res
+    }
+
+    /// Returns every token of a user that hasn't been used, expired or not.
+    pub fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError> {
+        let stmt = try!(self.conn.prepare(&format!(
+                "SELECT token, purpose, to_char(expires_at AT TIME ZONE 'UTC', '{}') \
+                    FROM AccountToken WHERE login_id = $1 ORDER BY expires_at",
+                TIMESTAMP_FORMAT)).map_err(LibError::other));
+        let rows = try!(stmt.query(&[&user_id]).map_err(LibError::other));
+        let mut res = vec![];
+        for row in rows.iter() {
+            let expires_at: String = try!(row.get_opt(2).map_err(LibError::other));
+            res.push(AccountToken {
+                token: try!(row.get_opt(0).map_err(LibError::other)),
+                login_id: user_id,
+                purpose: try!(row.get_opt(1).map_err(LibError::other)),
+                expires_at: try!(export::parse_timestamp(&expires_at)),
+            });
+        }
+        Ok(res)
+    }
+
+    /// Returns whether the current email of a user has been verified.
+    pub fn email_verified(&self, user_id: i32) -> Result<bool, LibError> {
+        let stmt = try!(self.conn.prepare(
+                "SELECT 1 FROM EmailVerification v JOIN Login l ON l.id = v.login_id \
+                    WHERE v.login_id = $1 AND v.email = l.email").map_err(LibError::other));
+        let rows = try!(stmt.query(&[&user_id]).map_err(LibError::other));
+        let res = rows.iter().next().is_some();
+        Ok(res)
+    }
+
+    pub fn add_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError> {
+        let mut res = try!(query(self.conn, &format!(
+                "INSERT INTO AuthEvent (login_id, username, ip, kind, created_at) \
+                    VALUES ($1, $2, $3, $4, $5::text::timestamptz) \
+                    RETURNING id, login_id, username, ip, kind, \
+                    to_char(created_at AT TIME ZONE 'UTC', '{}') AS created_at",
+                TIMESTAMP_FORMAT),
+                &[&event.login_id, &event.username, &event.ip, &event.kind, &event.created_at]));
+        res.pop().ok_or(LibError::Cause("Failed inserting authentication event".to_string()))
+    }
+
+    /// Returns the events of logging in to a user, newest first.
+    pub fn events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError> {
+        query(self.conn, &format!(
+                "SELECT id, login_id, username, ip, kind, \
+                    to_char(created_at AT TIME ZONE 'UTC', '{}') AS created_at \
+                    FROM AuthEvent WHERE login_id = $1 ORDER BY id DESC", TIMESTAMP_FORMAT),
+              &[&user_id])
+    }
+}
+
+/// Nested models to include with `Library` rows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Expand {
+    pub game: bool,
+    pub user: bool,
+}
+
+pub struct LibraryRepo<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> LibraryRepo<'a> {
+    pub fn new(conn: &'a GenericConnection) -> LibraryRepo<'a> {
+        LibraryRepo { conn: conn }
+    }
+
+    /// Returns every possible status of an entry.
+    pub fn statuses(&self) -> Result<Vec<Status>, LibError> {
+        let stmt = try!(self.conn.prepare("SELECT unnest(enum_range(NULL::Status))")
+                        .map_err(LibError::other));
+        let rows = try!(stmt.query(&[]).map_err(LibError::other));
+        let res = rows.iter().map(|x| x.get_opt(0)).collect::<Result<Vec<Status>, _>>()
+            .map_err(LibError::other);
+        res
+    }
+
+    /// Returns the number of entries with every status, including those
+    /// with none.
+    pub fn count_by_status(&self) -> Result<Vec<(Status, i64)>, LibError> {
+        let stmt = try!(self.conn.prepare(
+                "SELECT s.status, count(e.id) FROM unnest(enum_range(NULL::Status)) s (status) \
+                    LEFT JOIN Entry e ON e.status = s.status GROUP BY s.status ORDER BY s.status")
+            .map_err(LibError::other));
+        let rows = try!(stmt.query(&[]).map_err(LibError::other));
+        let res = rows.iter().map(|x| {
+            let status = try!(x.get_opt(0).map_err(LibError::other));
+            let count = try!(x.get_opt(1).map_err(LibError::other));
+            Ok((status, count))
+        }).collect();
+        res
+    }
+
+    /// Returns the library of a user with the nested models asked for by
+    /// `expand` joined in the same query. The entry is always included.
+    pub fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError> {
+        self.find_library(user_id, None, expand)
+    }
+
+    /// Like `library` but only for a single entry.
+    pub fn find(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>
+    {
+        self.find_library(user_id, Some(entry_id), expand).map(|mut x| x.pop())
+    }
+
+    fn find_library(&self, user_id: i32, entry_id: Option<i32>, expand: Expand)
+        -> Result<Vec<Library>, LibError>
+    {
+        query(self.conn, &format!(
+                "SELECT li.*, e.game_id AS entry_game_id, e.time_played AS entry_time_played, \
+                    e.last_update AS entry_last_update, e.status AS entry_status{}{} \
+                    FROM Login lo JOIN Library li ON lo.id = li.login_id \
+                    JOIN Entry e ON e.id = li.entry_id {} \
+                    WHERE lo.id = $1 AND ($2::int IS NULL OR e.id = $2) ORDER BY e.id",
+                if expand.user {
+                    ", lo.id AS user_id, lo.username AS user_username"
+                } else { "" },
+                if expand.game {
+                    ", g.name AS entry_game_name, g.description AS entry_game_description"
+                } else { "" },
+                if expand.game { "JOIN Game g ON g.id = e.game_id" } else { "" }),
+              &[&user_id, &entry_id])
+    }
+
+    pub fn find_entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError> {
+        query(self.conn,
+              "SELECT e.* FROM Login lo JOIN Library li ON lo.id = li.login_id \
+                JOIN Entry e ON e.id = li.entry_id WHERE e.id = $1 AND lo.id = $2",
+              &[&entry_id, &user_id]).map(|mut x| x.pop())
+    }
+
+    /// Adds a new entry to the library of a user. The game, status and time
+    /// played must be set; the last update is kept if set, which is only
+    /// meant for restoring backups.
+    pub fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String])
+        -> Result<Entry, LibError>
+    {
+        let status = try!(entry.status.ok_or(LibError::Cause("Missing status".to_string())));
+        let trans = try!(self.conn.transaction().map_err(LibError::other));
+        // First create entry and then map that into a library
+        let new_entry = try!(try!(query::<Entry>(&trans,
+                "INSERT INTO Entry (game_id, time_played, status, last_update) \
+                    VALUES ($1, $2, $3, COALESCE($4::text::timestamptz, CURRENT_TIMESTAMP)) \
+                    RETURNING *",
+                &[&entry.game_id, &entry.time_played, &status, &entry.last_update]))
+            .pop().ok_or(LibError::Cause("Failed inserting new entry".to_string())));
+        let entry_id = new_entry.id.unwrap();
+
+        try!(execute(&trans, "INSERT INTO Library (entry_id, login_id) VALUES ($1, $2)",
+                     &[&entry_id, &user_id]));
+        for tag in tags.iter() {
+            try!(execute(&trans, "INSERT INTO EntryTag (entry_id, tag) VALUES ($1, $2)",
+                         &[&entry_id, tag]));
+        }
+
+        try!(trans.commit().map_err(LibError::other));
+        Ok(new_entry)
+    }
+
+    /// Updates the status and time played of an entry in the library of a
+    /// user, returning whether there was such an entry.
+    pub fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError> {
+        let status = try!(entry.status.ok_or(LibError::Cause("Missing status".to_string())));
+        execute(self.conn,
+                "UPDATE Entry e SET status = $3, time_played = $4 WHERE e.id = $1 AND EXISTS \
+                    (SELECT * FROM Library li WHERE li.entry_id = $1 AND li.login_id = $2)",
+                &[&entry.id, &user_id, &status, &entry.time_played]).map(|x| x > 0)
+    }
+
+    /// Returns every entry in the library of a user, oldest first, with their
+    /// game and tags.
+    pub fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError> {
+        let stmt = try!(self.conn.prepare(
+                "SELECT et.entry_id, et.tag FROM Library li \
+                    JOIN EntryTag et ON et.entry_id = li.entry_id \
+                    WHERE li.login_id = $1 ORDER BY et.tag").map_err(LibError::other));
+        let mut tags = HashMap::new();
+        for row in try!(stmt.query(&[&user_id]).map_err(LibError::other)).iter() {
+            tags.entry(row.get::<_, i32>(0)).or_insert(vec![]).push(row.get::<_, String>(1));
+        }
+
+        let mut entries = try!(query::<ExportEntry>(self.conn, &format!(
+                "SELECT e.id, e.time_played, e.status, \
+                    to_char(e.last_update AT TIME ZONE 'UTC', '{}') AS last_update, \
+                    g.id AS game_id, g.name AS game_name, g.description AS game_description \
+                    FROM Library li JOIN Entry e ON e.id = li.entry_id \
+                    JOIN Game g ON g.id = e.game_id \
+                    WHERE li.login_id = $1 ORDER BY e.id", TIMESTAMP_FORMAT),
+                &[&user_id]));
+        for entry in entries.iter_mut() {
+            entry.tags = tags.remove(&entry.id).unwrap_or(vec![]);
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,709 @@
+use std::ascii::AsciiExt;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use chrono::{DateTime, Timelike, UTC};
+use models::{AuthEvent, Entry, Game, Library, Login, ShareLink, SimilarGame, Status, User,
+             UserRows, UserUpdate, UtcString, Visibility};
+use export::{self, ExportEntry};
+use lockout;
+use repo::Expand;
+use storage::{self, AccountToken, Counts, Failures, Storage};
+use LibError;
+
+/// Storage that lives and dies with the process, for tests and demos.
+///
+/// It keeps the semantics of the database schema: usernames and emails are
+/// unique (emails ignoring case), entries must refer to existing games and
+/// users, the last update of an entry is set whenever it changes and
+/// timestamps are kept with microseconds like in postgres.
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Default)]
+struct State {
+    logins: Vec<Login>,
+    games: Vec<Game>,
+    entries: Vec<EntryRow>,
+    library: Vec<LibraryRow>,
+    tags: Vec<(i32, String)>,
+    failures: HashMap<String, Failures>,
+    tokens: Vec<AccountToken>,
+    auth_events: Vec<AuthEvent>,
+    /// The email verified by every login and when.
+    verified: HashMap<i32, (String, DateTime<UTC>)>,
+    /// When every user to be deleted will be.
+    deletions: HashMap<i32, DateTime<UTC>>,
+    /// Who can see the library of every login that chose.
+    privacy: HashMap<i32, Visibility>,
+    /// Every login and a friend of theirs.
+    friends: Vec<(i32, i32)>,
+    share_links: Vec<ShareLink>,
+    last_id: Sequences,
+}
+
+/// The last id handed out for every table, like the serial sequences in
+/// postgres they are never reused.
+#[derive(Clone, Copy, Default)]
+struct Sequences {
+    login: i32,
+    game: i32,
+    entry: i32,
+    library: i32,
+    auth_event: i32,
+}
+
+#[derive(Clone)]
+struct EntryRow {
+    id: i32,
+    game_id: i32,
+    time_played: f32,
+    last_update: DateTime<UTC>,
+    status: Status,
+}
+
+#[derive(Clone)]
+struct LibraryRow {
+    id: i32,
+    entry_id: i32,
+    login_id: i32,
+}
+
+/// The current time with the precision postgres has.
+fn now() -> DateTime<UTC> {
+    let now = UTC::now();
+    now.with_nanosecond(now.nanosecond() / 1000 * 1000).unwrap_or(now)
+}
+
+fn next(id: &mut i32) -> i32 {
+    *id += 1;
+    *id
+}
+
+fn to_user(login: &Login) -> User {
+    User {
+        id: login.id,
+        username: login.username.clone(),
+        email: login.email.clone(),
+    }
+}
+
+fn to_entry(row: &EntryRow) -> Entry {
+    Entry {
+        id: Some(row.id),
+        game_id: Some(row.game_id),
+        time_played: Some(row.time_played),
+        last_update: Some(UtcString(row.last_update).to_string()),
+        status: Some(row.status),
+        game: None,
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::with_state(Default::default())
+    }
+
+    /// Storage with the same user and games as `db.sql`, for trying out the
+    /// API without a database.
+    pub fn demo() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        storage::seed(&storage).unwrap();
+        storage
+    }
+
+    fn with_state(state: State) -> MemoryStorage {
+        MemoryStorage {
+            state: Mutex::new(state)
+        }
+    }
+
+    fn state(&self) -> Result<MutexGuard<State>, LibError> {
+        self.state.lock().map_err(|_| LibError::Cause(
+                "A previous request panicked while using the storage".to_string()))
+    }
+}
+
+impl State {
+    fn library_rows(&self, user_id: i32) -> Vec<(&LibraryRow, &EntryRow)> {
+        let mut rows = self.library.iter()
+            .filter(|x| x.login_id == user_id)
+            .filter_map(|li| self.entries.iter().find(|e| e.id == li.entry_id).map(|e| (li, e)))
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| a.1.id.cmp(&b.1.id));
+        rows
+    }
+
+    fn to_library(&self, li: &LibraryRow, e: &EntryRow, expand: Expand) -> Library {
+        let mut entry = to_entry(e);
+        if expand.game {
+            entry.game = self.games.iter().find(|g| g.id == Some(e.game_id)).cloned();
+        }
+        Library {
+            id: Some(li.id),
+            login_id: li.login_id,
+            entry_id: li.entry_id,
+            user: if expand.user {
+                self.logins.iter().find(|x| x.id == Some(li.login_id)).map(|x| to_user(x).public())
+            } else {
+                None
+            },
+            entry: Some(entry),
+        }
+    }
+
+    fn games_of(&self, user_id: i32) -> HashSet<i32> {
+        self.library_rows(user_id).iter().map(|&(_, e)| e.game_id).collect()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn games(&self) -> Result<Vec<Game>, LibError> {
+        let mut games = try!(self.state()).games.clone();
+        games.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(games)
+    }
+
+    fn game(&self, id: i32) -> Result<Option<Game>, LibError> {
+        Ok(try!(self.state()).games.iter().find(|x| x.id == Some(id)).cloned())
+    }
+
+    fn games_by_name(&self, name: &str) -> Result<Vec<Game>, LibError> {
+        let name = name.to_ascii_lowercase();
+        Ok(try!(self.state()).games.iter()
+           .filter(|x| x.name.to_ascii_lowercase() == name)
+           .cloned().collect())
+    }
+
+    fn add_game(&self, game: &Game) -> Result<Game, LibError> {
+        let mut state = try!(self.state());
+        let game = Game {
+            id: Some(next(&mut state.last_id.game)),
+            name: game.name.clone(),
+            description: game.description.clone(),
+        };
+        state.games.push(game.clone());
+        Ok(game)
+    }
+
+    fn similar_games(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>
+    {
+        let state = try!(self.state());
+        let owned = user_id.map(|x| state.games_of(x)).unwrap_or(HashSet::new());
+
+        // Count players the same way the cooccurrence triggers do, where a
+        // game in a library more than once only counts once. Counting from
+        // the libraries as they are leaves out whatever has been deleted.
+        let mut players = HashMap::new();
+        let mut together = HashMap::new();
+        for login in state.logins.iter() {
+            let games = state.games_of(login.id.unwrap());
+            for &game in games.iter() {
+                *players.entry(game).or_insert(0) += 1;
+            }
+            if games.contains(&id) {
+                for &other in games.iter().filter(|&&x| x != id) {
+                    *together.entry(other).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut res = together.iter()
+            .filter(|&(other, _)| !owned.contains(other))
+            .filter_map(|(other, &n)| state.games.iter().find(|g| g.id == Some(*other)).map(|g| {
+                let pa = *players.get(&id).unwrap_or(&1) as f64;
+                let pb = *players.get(other).unwrap_or(&1) as f64;
+                SimilarGame {
+                    game: g.clone(),
+                    score: n as f64 / (pa * pb).sqrt(),
+                }
+            }))
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| match b.score.partial_cmp(&a.score).unwrap() {
+            Ordering::Equal => a.game.name.cmp(&b.game.name),
+            x => x,
+        });
+        res.truncate(limit as usize);
+        Ok(res)
+    }
+
+    fn users(&self) -> Result<Vec<User>, LibError> {
+        Ok(try!(self.state()).logins.iter().map(to_user).collect())
+    }
+
+    fn user(&self, id: i32) -> Result<Option<User>, LibError> {
+        Ok(try!(self.state()).logins.iter().find(|x| x.id == Some(id)).map(to_user))
+    }
+
+    fn add_user(&self, login: &Login) -> Result<User, LibError> {
+        let mut state = try!(self.state());
+        if login.username.chars().count() > 20 {
+            return Err(LibError::Cause("Username can be at most 20 characters".to_string()));
+        }
+        if login.password.chars().count() > 128 {
+            return Err(LibError::Cause("Password can be at most 128 characters".to_string()));
+        }
+        if state.logins.iter().any(|x| x.username == login.username) {
+            return Err(LibError::Cause(format!("Username {} already exists", login.username)));
+        }
+        let email = login.email.to_ascii_lowercase();
+        if state.logins.iter().any(|x| x.email.to_ascii_lowercase() == email) {
+            return Err(LibError::Cause(format!("Email {} already exists", login.email)));
+        }
+
+        let login = Login {
+            id: Some(next(&mut state.last_id.login)),
+            username: login.username.clone(),
+            password: login.password.clone(),
+            email: login.email.clone(),
+        };
+        state.logins.push(login.clone());
+        Ok(to_user(&login))
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError> {
+        Ok(try!(self.state()).logins.iter()
+           .find(|x| x.username == username && x.password == password)
+           .map(to_user))
+    }
+
+    fn update_user(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+        let mut state = try!(self.state());
+        if let Some(ref username) = update.username {
+            if username.chars().count() > 20 {
+                return Err(LibError::Cause("Username can be at most 20 characters".to_string()));
+            }
+            if state.logins.iter().any(|x| x.id != Some(id) && x.username == *username) {
+                return Err(LibError::Cause(format!("Username {} already exists", username)));
+            }
+        }
+        if let Some(ref email) = update.email {
+            let lower = email.to_ascii_lowercase();
+            let taken = state.logins.iter()
+                .any(|x| x.id != Some(id) && x.email.to_ascii_lowercase() == lower);
+            if taken {
+                return Err(LibError::Cause(format!("Email {} already exists", email)));
+            }
+        }
+
+        let login = match state.logins.iter_mut().find(|x| x.id == Some(id)) {
+            Some(login) => login,
+            None => return Ok(None),
+        };
+        if let Some(ref username) = update.username {
+            login.username = username.clone();
+        }
+        if let Some(ref email) = update.email {
+            login.email = email.clone();
+        }
+        Ok(Some(to_user(login)))
+    }
+
+    fn delete_user(&self, id: i32) -> Result<bool, LibError> {
+        let mut state = try!(self.state());
+        if !state.logins.iter().any(|x| x.id == Some(id)) {
+            return Ok(false);
+        }
+        let entries = state.library.iter()
+            .filter(|x| x.login_id == id)
+            .map(|x| x.entry_id)
+            .collect::<HashSet<_>>();
+        state.tags.retain(|x| !entries.contains(&x.0));
+        state.library.retain(|x| x.login_id != id);
+        state.entries.retain(|x| !entries.contains(&x.id));
+        state.tokens.retain(|x| x.login_id != id);
+        state.auth_events.retain(|x| x.login_id != Some(id));
+        state.verified.remove(&id);
+        state.deletions.remove(&id);
+        state.privacy.remove(&id);
+        state.friends.retain(|x| x.0 != id && x.1 != id);
+        state.share_links.retain(|x| x.login_id != id);
+        state.logins.retain(|x| x.id != Some(id));
+        Ok(true)
+    }
+
+    fn schedule_deletion(&self, id: i32, at: DateTime<UTC>) -> Result<(), LibError> {
+        let mut state = try!(self.state());
+        if !state.logins.iter().any(|x| x.id == Some(id)) {
+            return Err(LibError::Cause(format!("No user with id {}", id)));
+        }
+        state.deletions.insert(id, at);
+        Ok(())
+    }
+
+    fn deletion(&self, id: i32) -> Result<Option<DateTime<UTC>>, LibError> {
+        Ok(try!(self.state()).deletions.get(&id).cloned())
+    }
+
+    fn cancel_deletion(&self, id: i32) -> Result<bool, LibError> {
+        Ok(try!(self.state()).deletions.remove(&id).is_some())
+    }
+
+    fn due_deletions(&self, now: DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+        let mut res = try!(self.state()).deletions.iter()
+            .filter(|&(_, at)| *at <= now)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        res.sort();
+        Ok(res)
+    }
+
+    fn user_by_name(&self, username: &str) -> Result<Option<User>, LibError> {
+        Ok(try!(self.state()).logins.iter().find(|x| x.username == username).map(to_user))
+    }
+
+    fn failures(&self, key: &str) -> Result<Option<Failures>, LibError> {
+        Ok(try!(self.state()).failures.get(key).cloned())
+    }
+
+    fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError> {
+        try!(self.state()).failures.insert(key.to_string(), failures.clone());
+        Ok(())
+    }
+
+    fn clear_failures(&self, key: &str) -> Result<(), LibError> {
+        try!(self.state()).failures.remove(key);
+        Ok(())
+    }
+
+    fn add_token(&self, token: &AccountToken) -> Result<(), LibError> {
+        let mut state = try!(self.state());
+        if !state.logins.iter().any(|x| x.id == Some(token.login_id)) {
+            return Err(LibError::Cause(format!("No user with id {}", token.login_id)));
+        }
+        if state.tokens.iter().any(|x| x.token == token.token) {
+            return Err(LibError::Cause("Token already exists".to_string()));
+        }
+        state.tokens.push(token.clone());
+        Ok(())
+    }
+
+    fn take_token(&self, token: &str, purpose: &str) -> Result<Option<AccountToken>, LibError> {
+        let mut state = try!(self.state());
+        let i = state.tokens.iter().position(|x| x.token == token && x.purpose == purpose);
+        Ok(i.map(|i| state.tokens.remove(i)))
+    }
+
+    fn add_auth_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError> {
+        let mut state = try!(self.state());
+        if let Some(id) = event.login_id {
+            if !state.logins.iter().any(|x| x.id == Some(id)) {
+                return Err(LibError::Cause(format!("No user with id {}", id)));
+            }
+        }
+        let mut event = event.clone();
+        event.id = Some(next(&mut state.last_id.auth_event));
+        state.auth_events.push(event.clone());
+        Ok(event)
+    }
+
+    fn auth_events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError> {
+        Ok(try!(self.state()).auth_events.iter().rev()
+           .filter(|x| x.login_id == Some(user_id))
+           .cloned().collect())
+    }
+
+    fn user_by_email(&self, email: &str) -> Result<Option<User>, LibError> {
+        let email = email.to_ascii_lowercase();
+        Ok(try!(self.state()).logins.iter()
+           .find(|x| x.email.to_ascii_lowercase() == email)
+           .map(to_user))
+    }
+
+    fn set_password(&self, user_id: i32, password: &str) -> Result<bool, LibError> {
+        if password.chars().count() > 128 {
+            return Err(LibError::Cause("Password can be at most 128 characters".to_string()));
+        }
+        let mut state = try!(self.state());
+        match state.logins.iter_mut().find(|x| x.id == Some(user_id)) {
+            Some(login) => {
+                login.password = password.to_string();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError> {
+        try!(self.state()).tokens.retain(|x| !(x.login_id == user_id && x.purpose == purpose));
+        Ok(())
+    }
+
+    fn verify_email(&self, user_id: i32, email: &str, at: DateTime<UTC>) -> Result<(), LibError> {
+        let mut state = try!(self.state());
+        if !state.logins.iter().any(|x| x.id == Some(user_id)) {
+            return Err(LibError::Cause(format!("No user with id {}", user_id)));
+        }
+        state.verified.insert(user_id, (email.to_string(), at));
+        Ok(())
+    }
+
+    fn email_verified(&self, user_id: i32) -> Result<bool, LibError> {
+        let state = try!(self.state());
+        let login = state.logins.iter().find(|x| x.id == Some(user_id));
+        Ok(match (login, state.verified.get(&user_id)) {
+            (Some(login), Some(&(ref email, _))) => {
+                email.to_ascii_lowercase() == login.email.to_ascii_lowercase()
+            }
+            _ => false,
+        })
+    }
+
+    fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError> {
+        Ok(try!(self.state()).tokens.iter().filter(|x| x.login_id == user_id).cloned().collect())
+    }
+
+    fn user_rows(&self, user_id: i32) -> Result<UserRows, LibError> {
+        let state = try!(self.state());
+        let entries = state.library.iter()
+            .filter(|x| x.login_id == user_id)
+            .map(|x| x.entry_id)
+            .collect::<HashSet<_>>();
+        let games = state.games_of(user_id).len() as i64;
+        Ok(UserRows {
+            logins: state.logins.iter().filter(|x| x.id == Some(user_id)).count() as i64,
+            library: state.library.iter().filter(|x| x.login_id == user_id).count() as i64,
+            entries: state.entries.iter().filter(|x| entries.contains(&x.id)).count() as i64,
+            entry_tags: state.tags.iter().filter(|x| entries.contains(&x.0)).count() as i64,
+            account_tokens: state.tokens.iter().filter(|x| x.login_id == user_id).count() as i64,
+            auth_events: state.auth_events.iter()
+                .filter(|x| x.login_id == Some(user_id)).count() as i64,
+            auth_failures: state.failures.contains_key(&lockout::login_key(user_id)) as i64,
+            email_verifications: state.verified.contains_key(&user_id) as i64,
+            pending_deletions: state.deletions.contains_key(&user_id) as i64,
+            privacy_settings: state.privacy.contains_key(&user_id) as i64,
+            friends: state.friends.iter()
+                .filter(|x| x.0 == user_id || x.1 == user_id).count() as i64,
+            share_links: state.share_links.iter().filter(|x| x.login_id == user_id).count() as i64,
+            game_popularity: games,
+            game_cooccurrence: games * (games - 1),
+        })
+    }
+
+    fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError> {
+        Ok(try!(self.state()).privacy.get(&user_id).cloned().unwrap_or(Visibility::Public))
+    }
+
+    fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>
+    {
+        let mut state = try!(self.state());
+        if !state.logins.iter().any(|x| x.id == Some(user_id)) {
+            return Err(LibError::Cause(format!("No user with id {}", user_id)));
+        }
+        state.privacy.insert(user_id, visibility);
+        Ok(())
+    }
+
+    fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError> {
+        let state = try!(self.state());
+        let mut res = state.logins.iter()
+            .filter(|x| state.friends.iter().any(|f| f.0 == user_id && Some(f.1) == x.id))
+            .map(to_user)
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(res)
+    }
+
+    fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError> {
+        let mut state = try!(self.state());
+        for &id in [user_id, friend_id].iter() {
+            if !state.logins.iter().any(|x| x.id == Some(id)) {
+                return Err(LibError::Cause(format!("No user with id {}", id)));
+            }
+        }
+        if !state.friends.contains(&(user_id, friend_id)) {
+            state.friends.push((user_id, friend_id));
+        }
+        Ok(())
+    }
+
+    fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        let mut state = try!(self.state());
+        let before = state.friends.len();
+        state.friends.retain(|x| *x != (user_id, friend_id));
+        Ok(state.friends.len() < before)
+    }
+
+    fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        Ok(try!(self.state()).friends.contains(&(user_id, friend_id)))
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError> {
+        let mut state = try!(self.state());
+        if !state.logins.iter().any(|x| x.id == Some(link.login_id)) {
+            return Err(LibError::Cause(format!("No user with id {}", link.login_id)));
+        }
+        if state.share_links.iter().any(|x| x.token == link.token) {
+            return Err(LibError::Cause("The share link token is taken".to_string()));
+        }
+        state.share_links.push(link.clone());
+        Ok(link.clone())
+    }
+
+    fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError> {
+        Ok(try!(self.state()).share_links.iter()
+           .filter(|x| x.login_id == user_id)
+           .cloned()
+           .collect())
+    }
+
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError> {
+        Ok(try!(self.state()).share_links.iter().find(|x| x.token == token).cloned())
+    }
+
+    fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError> {
+        let mut state = try!(self.state());
+        let before = state.share_links.len();
+        state.share_links.retain(|x| !(x.login_id == user_id && x.token == token));
+        Ok(state.share_links.len() < before)
+    }
+
+    fn statuses(&self) -> Result<Vec<Status>, LibError> {
+        Ok(vec![Status::Frozen, Status::CurrentlyPlaying, Status::Dropped, Status::PlanToPlay])
+    }
+
+    fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError> {
+        let state = try!(self.state());
+        let res = state.library_rows(user_id).into_iter()
+            .map(|(li, e)| state.to_library(li, e, expand))
+            .collect();
+        Ok(res)
+    }
+
+    fn library_entry(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>
+    {
+        let state = try!(self.state());
+        let res = state.library_rows(user_id).into_iter()
+            .find(|&(_, e)| e.id == entry_id)
+            .map(|(li, e)| state.to_library(li, e, expand));
+        Ok(res)
+    }
+
+    fn entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError> {
+        let state = try!(self.state());
+        let res = state.library_rows(user_id).into_iter()
+            .find(|&(_, e)| e.id == entry_id)
+            .map(|(_, e)| to_entry(e));
+        Ok(res)
+    }
+
+    fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String]) -> Result<Entry, LibError> {
+        let status = try!(entry.status.ok_or(LibError::Cause("Missing status".to_string())));
+        let game_id = try!(entry.game_id.ok_or(LibError::Cause("Missing game".to_string())));
+        let time_played = try!(entry.time_played
+                               .ok_or(LibError::Cause("Missing time played".to_string())));
+        let last_update = match entry.last_update {
+            Some(ref x) => try!(UtcString::from_str(x).map_err(LibError::other)).0,
+            None => now(),
+        };
+
+        let mut state = try!(self.state());
+        if !state.games.iter().any(|x| x.id == Some(game_id)) {
+            return Err(LibError::Cause(format!("No game with id {}", game_id)));
+        }
+        if !state.logins.iter().any(|x| x.id == Some(user_id)) {
+            return Err(LibError::Cause(format!("No user with id {}", user_id)));
+        }
+        let unique = tags.iter().collect::<HashSet<_>>();
+        if unique.len() != tags.len() {
+            return Err(LibError::Cause("An entry can't have the same tag twice".to_string()));
+        }
+
+        let row = EntryRow {
+            id: next(&mut state.last_id.entry),
+            game_id: game_id,
+            time_played: time_played,
+            last_update: last_update,
+            status: status,
+        };
+        let library = LibraryRow {
+            id: next(&mut state.last_id.library),
+            entry_id: row.id,
+            login_id: user_id,
+        };
+        for tag in tags.iter() {
+            state.tags.push((row.id, tag.clone()));
+        }
+        state.library.push(library);
+        state.entries.push(row.clone());
+        Ok(to_entry(&row))
+    }
+
+    fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError> {
+        let status = try!(entry.status.ok_or(LibError::Cause("Missing status".to_string())));
+        let time_played = try!(entry.time_played
+                               .ok_or(LibError::Cause("Missing time played".to_string())));
+        let mut state = try!(self.state());
+        let owned = state.library.iter()
+            .any(|x| Some(x.entry_id) == entry.id && x.login_id == user_id);
+        if !owned {
+            return Ok(false);
+        }
+
+        let row = state.entries.iter_mut().find(|x| Some(x.id) == entry.id).unwrap();
+        row.status = status;
+        row.time_played = time_played;
+        row.last_update = now();
+        Ok(true)
+    }
+
+    fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError> {
+        let state = try!(self.state());
+        let res = state.library_rows(user_id).into_iter().filter_map(|(_, e)| {
+            state.games.iter().find(|g| g.id == Some(e.game_id)).map(|g| {
+                let mut tags = state.tags.iter()
+                    .filter(|x| x.0 == e.id)
+                    .map(|x| x.1.clone())
+                    .collect::<Vec<_>>();
+                tags.sort();
+                ExportEntry {
+                    id: e.id,
+                    game: g.clone(),
+                    time_played: e.time_played,
+                    status: e.status,
+                    last_update: export::format_timestamp(&e.last_update),
+                    tags: tags,
+                }
+            })
+        }).collect();
+        Ok(res)
+    }
+
+    fn counts(&self) -> Result<Counts, LibError> {
+        let statuses = try!(self.statuses());
+        let state = try!(self.state());
+        Ok(Counts {
+            users: state.logins.len() as i64,
+            games: state.games.len() as i64,
+            entries: statuses.into_iter()
+                .map(|s| (s, state.entries.iter().filter(|e| e.status == s).count() as i64))
+                .collect(),
+        })
+    }
+
+    fn ping(&self) -> Result<(), LibError> {
+        self.state().map(|_| ())
+    }
+
+    /// Always the latest schema.
+    fn migrations(&self) -> Result<Vec<i32>, LibError> {
+        Ok((1..storage::SCHEMA_VERSION + 1).collect())
+    }
+
+    /// Runs `f` on a copy of everything, which replaces the original only if
+    /// `f` succeeds. Other calls wait until the transaction is done.
+    fn transaction(&self, f: &mut FnMut(&Storage) -> Result<(), LibError>)
+        -> Result<(), LibError>
+    {
+        let mut state = try!(self.state());
+        let copy = MemoryStorage::with_state(state.clone());
+        try!(f(&copy));
+        *state = try!(copy.state()).clone();
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Where the models are kept, behind a trait so that the API doesn't care
+//! whether that is postgres or memory.
+//!
+//! `PgStorage` is the real thing, built on the repositories in `repo`.
+//! `MemoryStorage` keeps everything in memory with the same semantics as
+//! the database schema, which makes it possible to exercise the whole API in
+//! tests or run a demo without a database. With the `sqlite` feature there
+//! is also `SqliteStorage` for installs without a database server.
+use chrono::{DateTime, UTC};
+use models::{AuthEvent, Entry, Game, Library, Login, ShareLink, SimilarGame, Status, User,
+             UserRows, UserUpdate, Visibility};
+use export::ExportEntry;
+use repo::Expand;
+use LibError;
+
+pub use self::memory::MemoryStorage;
+pub use self::pg::{PgConnection, PgStorage};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+
+mod memory;
+mod pg;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// The version of the schema in `db.sql` and `sqlite.sql` this build expects.
+/// Every change to the schema is a migration which bumps it, inserting its
+/// version into `SchemaMigration`. Migrations for existing postgres databases
+/// are kept in `migrations/`.
+pub const SCHEMA_VERSION: i32 = 11;
+
+/// Opens the storage described by `url`, which is one of
+///
+/// - `postgresql://...` or `postgres://...` for postgres,
+/// - `sqlite:path/to/file.db` for SQLite, if built with the `sqlite` feature,
+/// - `memory:` for an empty in-memory storage or `memory:demo` for one with
+///   the data of `db.sql`.
+pub fn open(url: &str) -> Result<Box<Storage + Send + Sync>, LibError> {
+    if url.starts_with("postgresql://") || url.starts_with("postgres://") {
+        Ok(Box::new(try!(PgStorage::new(url))))
+    } else if url.starts_with("sqlite:") {
+        open_sqlite(&url["sqlite:".len()..])
+    } else if url == "memory:" {
+        Ok(Box::new(MemoryStorage::new()))
+    } else if url == "memory:demo" {
+        Ok(Box::new(MemoryStorage::demo()))
+    } else {
+        Err(LibError::Cause(format!("Unknown kind of database: {}", url)))
+    }
+}
+
+/// Adds the same user and games as `db.sql` to `storage`, with shortened
+/// descriptions.
+pub fn seed(storage: &Storage) -> Result<(), LibError> {
+    try!(storage.add_user(&Login {
+        id: None,
+        username: "user".to_string(),
+        password: "hunter2".to_string(),
+        email: "user@example.com".to_string(),
+    }));
+    for &(name, description) in [
+        ("Diablo III", "Two decades have passed since the demonic denizens, Diablo, \
+            Mephisto, and Baal, wandered the world of Sanctuary."),
+        ("Darksiders", "Deceived by the forces of evil into prematurely bringing about \
+            the end of the world, War – the first Horseman of the Apocalypse – stands \
+            accused of breaking the sacred law."),
+        ("Bioshock Infinite", "BioShock Infinite is a first-person shooter made by \
+            Irrational Games, the studio behind the original BioShock."),
+        ("Skyrim", "You should have acted. They're already here. The Elder Scrolls told \
+            of their return."),
+        ("Mass Effect 3", "Plunges you into an all-out galactic war to take Earth back \
+            from a nearly unstoppable foe."),
+        ("The Witcher 3", "The war with Nilfgaard obliterated the old order. The North is \
+            engulfed in chaos."),
+        ("Darksiders II", "Awakened by the End of Days, Death, the most feared of the \
+            legendary Four Horsemen, embarks upon a quest to restore mankind."),
+        ("The Witcher 2", "The second instalment in the RPG saga about the Witcher, \
+            Geralt of Rivia."),
+        ("The Witcher", "Welcome to a world that knows no mercy - none is received, and \
+            none is given."),
+    ].iter() {
+        try!(storage.add_game(&Game {
+            id: None,
+            name: name.to_string(),
+            description: description.to_string(),
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(path: &str) -> Result<Box<Storage + Send + Sync>, LibError> {
+    Ok(Box::new(try!(SqliteStorage::new(path))))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite(_: &str) -> Result<Box<Storage + Send + Sync>, LibError> {
+    Err(LibError::Cause("backlogrs was built without the sqlite feature".to_string()))
+}
+
+/// How much there is of everything, for metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counts {
+    pub users: i64,
+    pub games: i64,
+    /// The number of entries with every status, including those with none.
+    pub entries: Vec<(Status, i64)>,
+}
+
+/// How busy the connection pool of a storage is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolState {
+    /// The most connections the pool will open.
+    pub size: u32,
+    /// The connections that are open, in use or not.
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+/// Failed attempts to log in in a row, kept by login or by IP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failures {
+    pub failures: i32,
+    pub last_failure: DateTime<UTC>,
+    /// No attempt is let through before this.
+    pub blocked_until: Option<DateTime<UTC>>,
+}
+
+/// A secret mailed to a user to prove they read the email of a login.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountToken {
+    pub token: String,
+    pub login_id: i32,
+    /// What the token can be used for, like `unlock`.
+    pub purpose: String,
+    pub expires_at: DateTime<UTC>,
+}
+
+pub trait Storage {
+    /// Returns every game ordered by name.
+    fn games(&self) -> Result<Vec<Game>, LibError>;
+
+    fn game(&self, id: i32) -> Result<Option<Game>, LibError>;
+
+    /// Returns the games named `name`, ignoring case.
+    fn games_by_name(&self, name: &str) -> Result<Vec<Game>, LibError>;
+
+    fn add_game(&self, game: &Game) -> Result<Game, LibError>;
+
+    /// Returns the games most often found in the same libraries as the given
+    /// game, leaving out those already in the library of `user_id`.
+    fn similar_games(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>;
+
+    fn users(&self) -> Result<Vec<User>, LibError>;
+
+    fn user(&self, id: i32) -> Result<Option<User>, LibError>;
+
+    /// Creates a new user, failing if the username or email is taken.
+    fn add_user(&self, login: &Login) -> Result<User, LibError>;
+
+    /// Returns the user with the given credentials, if any.
+    fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError>;
+
+    /// Changes the username or email of a user if set, failing if either is
+    /// taken. Returns the user as changed, if there is such a user.
+    fn update_user(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError>;
+
+    /// Deletes a user with their library and everything else of theirs,
+    /// returning whether there was such a user.
+    fn delete_user(&self, id: i32) -> Result<bool, LibError>;
+
+    /// Deletes a user at `at` rather than right away, see `due_deletions`.
+    fn schedule_deletion(&self, id: i32, at: DateTime<UTC>) -> Result<(), LibError>;
+
+    /// Returns when a user is to be deleted, if they are.
+    fn deletion(&self, id: i32) -> Result<Option<DateTime<UTC>>, LibError>;
+
+    /// Keeps a user from being deleted, returning whether they were to be.
+    fn cancel_deletion(&self, id: i32) -> Result<bool, LibError>;
+
+    /// Returns the users to be deleted by `now`.
+    fn due_deletions(&self, now: DateTime<UTC>) -> Result<Vec<i32>, LibError>;
+
+    fn user_by_name(&self, username: &str) -> Result<Option<User>, LibError>;
+
+    /// Returns the failed attempts to log in kept under `key`, like
+    /// `login:1` or `ip:127.0.0.1`.
+    fn failures(&self, key: &str) -> Result<Option<Failures>, LibError>;
+
+    fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError>;
+
+    fn clear_failures(&self, key: &str) -> Result<(), LibError>;
+
+    fn add_token(&self, token: &AccountToken) -> Result<(), LibError>;
+
+    /// Removes the token for `purpose` and returns it, expired or not, so
+    /// that it can only be used once.
+    fn take_token(&self, token: &str, purpose: &str) -> Result<Option<AccountToken>, LibError>;
+
+    /// Keeps an event, which gets an id. The time it happened must be set.
+    fn add_auth_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError>;
+
+    /// Returns the events of logging in to a user, newest first.
+    fn auth_events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError>;
+
+    /// Returns the user with `email`, ignoring case.
+    fn user_by_email(&self, email: &str) -> Result<Option<User>, LibError>;
+
+    /// Changes the password of a user, returning whether there was such a
+    /// user.
+    fn set_password(&self, user_id: i32, password: &str) -> Result<bool, LibError>;
+
+    /// Removes every token of a user for `purpose`.
+    fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError>;
+
+    /// Keeps that a user verified `email` at `at`, replacing whatever they
+    /// verified before.
+    fn verify_email(&self, user_id: i32, email: &str, at: DateTime<UTC>) -> Result<(), LibError>;
+
+    /// Returns whether the current email of a user has been verified, which
+    /// it no longer is once changed.
+    fn email_verified(&self, user_id: i32) -> Result<bool, LibError>;
+
+    /// Returns every token of a user that hasn't been used, expired or not.
+    fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError>;
+
+    /// Counts the rows of every table that refer to a user, including their
+    /// failed attempts to log in, and the players they count for in the
+    /// games played together.
+    fn user_rows(&self, user_id: i32) -> Result<UserRows, LibError>;
+
+    /// Returns who can see the library of a user, which is anyone unless
+    /// they chose otherwise.
+    fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError>;
+
+    fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>;
+
+    /// Returns the friends of a user by username.
+    fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError>;
+
+    /// Makes `friend_id` a friend of a user, unless they are already.
+    fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError>;
+
+    /// Returns whether `friend_id` was a friend of a user.
+    fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError>;
+
+    fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError>;
+
+    /// Keeps a link sharing the library of its user. The token must be
+    /// unique and the time it was created set.
+    fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError>;
+
+    /// Returns the links sharing the library of a user, oldest first.
+    fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError>;
+
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError>;
+
+    /// Deletes a link of a user so that it stops working, returning whether
+    /// there was such a link.
+    fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError>;
+
+    /// Returns every possible status of an entry.
+    fn statuses(&self) -> Result<Vec<Status>, LibError>;
+
+    /// Returns the library of a user with the nested models asked for by
+    /// `expand`. The entry is always included.
+    fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError>;
+
+    /// Like `library` but only for a single entry.
+    fn library_entry(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>;
+
+    fn entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError>;
+
+    /// Adds a new entry to the library of a user. The game, status and time
+    /// played must be set; the last update is kept if set, which is only
+    /// meant for restoring backups.
+    fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String]) -> Result<Entry, LibError>;
+
+    /// Updates the status and time played of an entry in the library of a
+    /// user, returning whether there was such an entry. The last update is
+    /// always set to now.
+    fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError>;
+
+    /// Returns every entry in the library of a user, oldest first, with their
+    /// game and tags.
+    fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError>;
+
+    fn counts(&self) -> Result<Counts, LibError>;
+
+    /// Checks that the storage can be reached, which for a database means
+    /// getting a connection and running a query.
+    fn ping(&self) -> Result<(), LibError>;
+
+    /// Returns the versions of the migrations applied to the schema, oldest
+    /// first.
+    fn migrations(&self) -> Result<Vec<i32>, LibError>;
+
+    /// Returns the state of the connection pool, if the storage has one.
+    fn pool_state(&self) -> Option<PoolState> {
+        None
+    }
+
+    /// Runs `f` atomically: if it fails nothing it did through the storage it
+    /// was given is kept.
+    fn transaction(&self, f: &mut FnMut(&Storage) -> Result<(), LibError>)
+        -> Result<(), LibError>;
+}
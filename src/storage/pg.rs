@@ -0,0 +1,495 @@
+use std::default::Default;
+use chrono::{DateTime, UTC};
+use postgres::{GenericConnection, SslMode};
+use r2d2;
+use r2d2_postgres::PostgresConnectionManager;
+use models::{AuthEvent, Entry, Game, Library, Login, ShareLink, SimilarGame, Status, User,
+             UserRows, UserUpdate, Visibility};
+use export::ExportEntry;
+use repo::{AuthRepo, Expand, GameRepo, LibraryRepo, PrivacyRepo, SchemaRepo, UserRepo};
+use storage::{AccountToken, Counts, Failures, PoolState, Storage};
+use LibError;
+
+/// Storage in postgres through a connection pool. Every call takes a
+/// connection from the pool for as long as it runs.
+pub struct PgStorage {
+    pool: r2d2::Pool<PostgresConnectionManager>,
+    size: u32,
+}
+
+impl PgStorage {
+    /// Opens a connection pool with default config to the database at `url`,
+    /// not using any SSL.
+    pub fn new(url: &str) -> Result<PgStorage, LibError> {
+        let config: r2d2::Config = Default::default();
+        let size = config.pool_size();
+        let manager = PostgresConnectionManager::new(url, SslMode::None);
+        let error_handler = Box::new(r2d2::NoopErrorHandler);
+        let pool = try!(r2d2::Pool::new(config, manager, error_handler).map_err(LibError::other));
+        Ok(PgStorage {
+            pool: pool,
+            size: size,
+        })
+    }
+
+    fn with<T, F>(&self, f: F) -> Result<T, LibError>
+        where F: FnOnce(&PgConnection) -> Result<T, LibError>
+    {
+        let conn = try!(self.pool.get().map_err(LibError::other));
+        f(&PgConnection::new(&*conn))
+    }
+}
+
+impl Storage for PgStorage {
+    fn games(&self) -> Result<Vec<Game>, LibError> {
+        self.with(|x| x.games())
+    }
+
+    fn game(&self, id: i32) -> Result<Option<Game>, LibError> {
+        self.with(|x| x.game(id))
+    }
+
+    fn games_by_name(&self, name: &str) -> Result<Vec<Game>, LibError> {
+        self.with(|x| x.games_by_name(name))
+    }
+
+    fn add_game(&self, game: &Game) -> Result<Game, LibError> {
+        self.with(|x| x.add_game(game))
+    }
+
+    fn similar_games(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>
+    {
+        self.with(|x| x.similar_games(id, user_id, limit))
+    }
+
+    fn users(&self) -> Result<Vec<User>, LibError> {
+        self.with(|x| x.users())
+    }
+
+    fn user(&self, id: i32) -> Result<Option<User>, LibError> {
+        self.with(|x| x.user(id))
+    }
+
+    fn add_user(&self, login: &Login) -> Result<User, LibError> {
+        self.with(|x| x.add_user(login))
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError> {
+        self.with(|x| x.authenticate(username, password))
+    }
+
+    fn update_user(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+        self.with(|x| x.update_user(id, update))
+    }
+
+    fn delete_user(&self, id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.delete_user(id))
+    }
+
+    fn schedule_deletion(&self, id: i32, at: DateTime<UTC>) -> Result<(), LibError> {
+        self.with(|x| x.schedule_deletion(id, at))
+    }
+
+    fn deletion(&self, id: i32) -> Result<Option<DateTime<UTC>>, LibError> {
+        self.with(|x| x.deletion(id))
+    }
+
+    fn cancel_deletion(&self, id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.cancel_deletion(id))
+    }
+
+    fn due_deletions(&self, now: DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+        self.with(|x| x.due_deletions(now))
+    }
+
+    fn user_by_name(&self, username: &str) -> Result<Option<User>, LibError> {
+        self.with(|x| x.user_by_name(username))
+    }
+
+    fn failures(&self, key: &str) -> Result<Option<Failures>, LibError> {
+        self.with(|x| x.failures(key))
+    }
+
+    fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError> {
+        self.with(|x| x.set_failures(key, failures))
+    }
+
+    fn clear_failures(&self, key: &str) -> Result<(), LibError> {
+        self.with(|x| x.clear_failures(key))
+    }
+
+    fn add_token(&self, token: &AccountToken) -> Result<(), LibError> {
+        self.with(|x| x.add_token(token))
+    }
+
+    fn take_token(&self, token: &str, purpose: &str) -> Result<Option<AccountToken>, LibError> {
+        self.with(|x| x.take_token(token, purpose))
+    }
+
+    fn add_auth_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError> {
+        self.with(|x| x.add_auth_event(event))
+    }
+
+    fn auth_events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError> {
+        self.with(|x| x.auth_events(user_id))
+    }
+
+    fn user_by_email(&self, email: &str) -> Result<Option<User>, LibError> {
+        self.with(|x| x.user_by_email(email))
+    }
+
+    fn set_password(&self, user_id: i32, password: &str) -> Result<bool, LibError> {
+        self.with(|x| x.set_password(user_id, password))
+    }
+
+    fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError> {
+        self.with(|x| x.delete_tokens(user_id, purpose))
+    }
+
+    fn verify_email(&self, user_id: i32, email: &str, at: DateTime<UTC>) -> Result<(), LibError> {
+        self.with(|x| x.verify_email(user_id, email, at))
+    }
+
+    fn email_verified(&self, user_id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.email_verified(user_id))
+    }
+
+    fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError> {
+        self.with(|x| x.tokens(user_id))
+    }
+
+    fn user_rows(&self, user_id: i32) -> Result<UserRows, LibError> {
+        self.with(|x| x.user_rows(user_id))
+    }
+
+    fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError> {
+        self.with(|x| x.library_visibility(user_id))
+    }
+
+    fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>
+    {
+        self.with(|x| x.set_library_visibility(user_id, visibility))
+    }
+
+    fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError> {
+        self.with(|x| x.friends(user_id))
+    }
+
+    fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError> {
+        self.with(|x| x.add_friend(user_id, friend_id))
+    }
+
+    fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.remove_friend(user_id, friend_id))
+    }
+
+    fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.is_friend(user_id, friend_id))
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError> {
+        self.with(|x| x.add_share_link(link))
+    }
+
+    fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError> {
+        self.with(|x| x.share_links(user_id))
+    }
+
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError> {
+        self.with(|x| x.share_link(token))
+    }
+
+    fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError> {
+        self.with(|x| x.delete_share_link(user_id, token))
+    }
+
+    fn statuses(&self) -> Result<Vec<Status>, LibError> {
+        self.with(|x| x.statuses())
+    }
+
+    fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError> {
+        self.with(|x| x.library(user_id, expand))
+    }
+
+    fn library_entry(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>
+    {
+        self.with(|x| x.library_entry(user_id, entry_id, expand))
+    }
+
+    fn entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError> {
+        self.with(|x| x.entry(user_id, entry_id))
+    }
+
+    fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String]) -> Result<Entry, LibError> {
+        self.with(|x| x.add_entry(user_id, entry, tags))
+    }
+
+    fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError> {
+        self.with(|x| x.update_entry(user_id, entry))
+    }
+
+    fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError> {
+        self.with(|x| x.export(user_id))
+    }
+
+    fn counts(&self) -> Result<Counts, LibError> {
+        self.with(|x| x.counts())
+    }
+
+    fn ping(&self) -> Result<(), LibError> {
+        self.with(|x| x.ping())
+    }
+
+    fn migrations(&self) -> Result<Vec<i32>, LibError> {
+        self.with(|x| x.migrations())
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        let state = self.pool.state();
+        Some(PoolState {
+            size: self.size,
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+        })
+    }
+
+    fn transaction(&self, f: &mut FnMut(&Storage) -> Result<(), LibError>)
+        -> Result<(), LibError>
+    {
+        self.with(|x| x.transaction(f))
+    }
+}
+
+/// Storage on a single connection, which may just as well be a transaction.
+pub struct PgConnection<'a> {
+    conn: &'a GenericConnection,
+}
+
+impl<'a> PgConnection<'a> {
+    pub fn new(conn: &'a GenericConnection) -> PgConnection<'a> {
+        PgConnection { conn: conn }
+    }
+}
+
+impl<'a> Storage for PgConnection<'a> {
+    fn games(&self) -> Result<Vec<Game>, LibError> {
+        GameRepo::new(self.conn).all()
+    }
+
+    fn game(&self, id: i32) -> Result<Option<Game>, LibError> {
+        GameRepo::new(self.conn).find(id)
+    }
+
+    fn games_by_name(&self, name: &str) -> Result<Vec<Game>, LibError> {
+        GameRepo::new(self.conn).find_by_name(name)
+    }
+
+    fn add_game(&self, game: &Game) -> Result<Game, LibError> {
+        GameRepo::new(self.conn).add(game)
+    }
+
+    fn similar_games(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>
+    {
+        GameRepo::new(self.conn).similar(id, user_id, limit)
+    }
+
+    fn users(&self) -> Result<Vec<User>, LibError> {
+        UserRepo::new(self.conn).all()
+    }
+
+    fn user(&self, id: i32) -> Result<Option<User>, LibError> {
+        UserRepo::new(self.conn).find(id)
+    }
+
+    fn add_user(&self, login: &Login) -> Result<User, LibError> {
+        UserRepo::new(self.conn).add(login)
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError> {
+        UserRepo::new(self.conn).authenticate(username, password)
+    }
+
+    fn update_user(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+        UserRepo::new(self.conn).update(id, update)
+    }
+
+    fn delete_user(&self, id: i32) -> Result<bool, LibError> {
+        UserRepo::new(self.conn).delete(id)
+    }
+
+    fn schedule_deletion(&self, id: i32, at: DateTime<UTC>) -> Result<(), LibError> {
+        AuthRepo::new(self.conn).schedule_deletion(id, &at)
+    }
+
+    fn deletion(&self, id: i32) -> Result<Option<DateTime<UTC>>, LibError> {
+        AuthRepo::new(self.conn).deletion(id)
+    }
+
+    fn cancel_deletion(&self, id: i32) -> Result<bool, LibError> {
+        AuthRepo::new(self.conn).cancel_deletion(id)
+    }
+
+    fn due_deletions(&self, now: DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+        AuthRepo::new(self.conn).due_deletions(&now)
+    }
+
+    fn user_by_name(&self, username: &str) -> Result<Option<User>, LibError> {
+        UserRepo::new(self.conn).find_by_name(username)
+    }
+
+    fn failures(&self, key: &str) -> Result<Option<Failures>, LibError> {
+        AuthRepo::new(self.conn).failures(key)
+    }
+
+    fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError> {
+        AuthRepo::new(self.conn).set_failures(key, failures)
+    }
+
+    fn clear_failures(&self, key: &str) -> Result<(), LibError> {
+        AuthRepo::new(self.conn).clear_failures(key)
+    }
+
+    fn add_token(&self, token: &AccountToken) -> Result<(), LibError> {
+        AuthRepo::new(self.conn).add_token(token)
+    }
+
+    fn take_token(&self, token: &str, purpose: &str) -> Result<Option<AccountToken>, LibError> {
+        AuthRepo::new(self.conn).take_token(token, purpose)
+    }
+
+    fn add_auth_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError> {
+        AuthRepo::new(self.conn).add_event(event)
+    }
+
+    fn auth_events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError> {
+        AuthRepo::new(self.conn).events(user_id)
+    }
+
+    fn user_by_email(&self, email: &str) -> Result<Option<User>, LibError> {
+        UserRepo::new(self.conn).find_by_email(email)
+    }
+
+    fn set_password(&self, user_id: i32, password: &str) -> Result<bool, LibError> {
+        UserRepo::new(self.conn).set_password(user_id, password)
+    }
+
+    fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError> {
+        AuthRepo::new(self.conn).delete_tokens(user_id, purpose)
+    }
+
+    fn verify_email(&self, user_id: i32, email: &str, at: DateTime<UTC>) -> Result<(), LibError> {
+        AuthRepo::new(self.conn).verify_email(user_id, email, &at)
+    }
+
+    fn email_verified(&self, user_id: i32) -> Result<bool, LibError> {
+        AuthRepo::new(self.conn).email_verified(user_id)
+    }
+
+    fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError> {
+        AuthRepo::new(self.conn).tokens(user_id)
+    }
+
+    fn user_rows(&self, user_id: i32) -> Result<UserRows, LibError> {
+        UserRepo::new(self.conn).rows(user_id)
+    }
+
+    fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError> {
+        PrivacyRepo::new(self.conn).library_visibility(user_id)
+    }
+
+    fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>
+    {
+        PrivacyRepo::new(self.conn).set_library_visibility(user_id, visibility)
+    }
+
+    fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError> {
+        PrivacyRepo::new(self.conn).friends(user_id)
+    }
+
+    fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError> {
+        PrivacyRepo::new(self.conn).add_friend(user_id, friend_id)
+    }
+
+    fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        PrivacyRepo::new(self.conn).remove_friend(user_id, friend_id)
+    }
+
+    fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        PrivacyRepo::new(self.conn).is_friend(user_id, friend_id)
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError> {
+        PrivacyRepo::new(self.conn).add_share_link(link)
+    }
+
+    fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError> {
+        PrivacyRepo::new(self.conn).share_links(user_id)
+    }
+
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError> {
+        PrivacyRepo::new(self.conn).share_link(token)
+    }
+
+    fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError> {
+        PrivacyRepo::new(self.conn).delete_share_link(user_id, token)
+    }
+
+    fn statuses(&self) -> Result<Vec<Status>, LibError> {
+        LibraryRepo::new(self.conn).statuses()
+    }
+
+    fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError> {
+        LibraryRepo::new(self.conn).library(user_id, expand)
+    }
+
+    fn library_entry(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>
+    {
+        LibraryRepo::new(self.conn).find(user_id, entry_id, expand)
+    }
+
+    fn entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError> {
+        LibraryRepo::new(self.conn).find_entry(user_id, entry_id)
+    }
+
+    fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String]) -> Result<Entry, LibError> {
+        LibraryRepo::new(self.conn).add_entry(user_id, entry, tags)
+    }
+
+    fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError> {
+        LibraryRepo::new(self.conn).update_entry(user_id, entry)
+    }
+
+    fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError> {
+        LibraryRepo::new(self.conn).export(user_id)
+    }
+
+    fn counts(&self) -> Result<Counts, LibError> {
+        Ok(Counts {
+            users: try!(UserRepo::new(self.conn).count()),
+            games: try!(GameRepo::new(self.conn).count()),
+            entries: try!(LibraryRepo::new(self.conn).count_by_status()),
+        })
+    }
+
+    fn ping(&self) -> Result<(), LibError> {
+        SchemaRepo::new(self.conn).ping()
+    }
+
+    fn migrations(&self) -> Result<Vec<i32>, LibError> {
+        SchemaRepo::new(self.conn).migrations()
+    }
+
+    fn transaction(&self, f: &mut FnMut(&Storage) -> Result<(), LibError>)
+        -> Result<(), LibError>
+    {
+        let trans = try!(self.conn.transaction().map_err(LibError::other));
+        try!(f(&PgConnection::new(&trans)));
+        trans.commit().map_err(LibError::other)
+    }
+}
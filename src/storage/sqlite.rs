@@ -0,0 +1,905 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+use chrono::{DateTime, UTC};
+use rusqlite::{SqliteConnection, SqliteError, SqliteRow};
+use rusqlite::types::ToSql;
+use models::{AuthEvent, Entry, Game, Library, Login, ShareLink, SimilarGame, Status, User,
+             UserRows, UserUpdate, UtcString, Visibility};
+use export::{self, ExportEntry};
+use lockout;
+use repo::Expand;
+use storage::{AccountToken, Counts, Failures, Storage};
+use LibError;
+
+/// The schema, created when opening a database unless it already exists.
+const SCHEMA: &'static str = include_str!("../../sqlite.sql");
+
+/// Storage in a single SQLite file, following `sqlite.sql`. Every call opens
+/// the file for as long as it runs.
+pub struct SqliteStorage {
+    path: String,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn new(path: &str) -> Result<SqliteStorage, LibError> {
+        let storage = SqliteStorage {
+            path: path.to_string()
+        };
+        let conn = try!(storage.open());
+        try!(conn.execute_batch(SCHEMA).map_err(error));
+        Ok(storage)
+    }
+
+    fn open(&self) -> Result<SqliteConnection, LibError> {
+        let conn = try!(SqliteConnection::open(&self.path).map_err(error));
+        // Foreign keys are off by default in every new connection.
+        try!(conn.execute_batch("PRAGMA foreign_keys = ON").map_err(error));
+        Ok(conn)
+    }
+
+    fn with<T, F>(&self, f: F) -> Result<T, LibError>
+        where F: FnOnce(&Conn) -> Result<T, LibError>
+    {
+        let conn = try!(self.open());
+        let res = f(&Conn { conn: &conn });
+        res
+    }
+}
+
+fn error(err: SqliteError) -> LibError {
+    LibError::Cause(err.message)
+}
+
+fn query<T, F>(conn: &SqliteConnection, sql: &str, params: &[&ToSql], f: F)
+    -> Result<Vec<T>, LibError>
+    where F: Fn(&SqliteRow) -> Result<T, LibError>
+{
+    let mut stmt = try!(conn.prepare(sql).map_err(error));
+    let mut res = vec![];
+    {
+        let rows = try!(stmt.query(params).map_err(error));
+        for row in rows {
+            let row = try!(row.map_err(error));
+            res.push(try!(f(&row)));
+        }
+    }
+    Ok(res)
+}
+
+fn execute(conn: &SqliteConnection, sql: &str, params: &[&ToSql]) -> Result<i32, LibError> {
+    conn.execute(sql, params).map_err(error)
+}
+
+/// Runs `f` in a savepoint, which unlike a transaction can be nested.
+fn savepoint<T, F>(conn: &SqliteConnection, f: F) -> Result<T, LibError>
+    where F: FnOnce() -> Result<T, LibError>
+{
+    try!(conn.execute_batch("SAVEPOINT backlogrs").map_err(error));
+    match f() {
+        Ok(x) => {
+            try!(conn.execute_batch("RELEASE backlogrs").map_err(error));
+            Ok(x)
+        },
+        Err(err) => {
+            // The original error says more than any error rolling back.
+            let _ = conn.execute_batch("ROLLBACK TO backlogrs; RELEASE backlogrs");
+            Err(err)
+        },
+    }
+}
+
+fn to_game(row: &SqliteRow, i: i32) -> Game {
+    Game {
+        id: Some(row.get(i)),
+        name: row.get(i + 1),
+        description: row.get(i + 2),
+    }
+}
+
+fn to_user(row: &SqliteRow, i: i32) -> User {
+    User {
+        id: Some(row.get(i)),
+        username: row.get(i + 1),
+        email: row.get(i + 2),
+    }
+}
+
+/// Reads the columns `id, game_id, time_played, last_update, status` of
+/// Entry starting at `i`.
+fn to_entry(row: &SqliteRow, i: i32) -> Result<Entry, LibError> {
+    let last_update = try!(UtcString::from_str(&row.get::<String>(i + 3))
+                           .map_err(LibError::other));
+    Ok(Entry {
+        id: Some(row.get(i)),
+        game_id: Some(row.get(i + 1)),
+        time_played: Some(row.get::<f64>(i + 2) as f32),
+        last_update: Some(last_update.to_string()),
+        status: Some(try!(row.get::<String>(i + 4).parse())),
+        game: None,
+    })
+}
+
+/// Reads the columns `token, login_id, status, tag, created_at` of
+/// ShareLink.
+fn to_share_link(row: &SqliteRow) -> Result<ShareLink, LibError> {
+    let status = match row.get::<Option<String>>(2) {
+        Some(status) => Some(try!(status.parse())),
+        None => None,
+    };
+    Ok(ShareLink {
+        token: row.get(0),
+        login_id: row.get(1),
+        status: status,
+        tag: row.get(3),
+        created_at: row.get(4),
+    })
+}
+
+fn to_auth_event(row: &SqliteRow) -> AuthEvent {
+    AuthEvent {
+        id: Some(row.get(0)),
+        login_id: row.get(1),
+        username: row.get(2),
+        ip: row.get(3),
+        kind: row.get(4),
+        created_at: row.get(5),
+    }
+}
+
+const LIBRARY: &'static str =
+    "SELECT li.id, li.login_id, li.entry_id, \
+        e.id, e.game_id, e.time_played, e.last_update, e.status, \
+        lo.id, lo.username, lo.email, g.id, g.name, g.description \
+        FROM Login lo JOIN Library li ON lo.id = li.login_id \
+        JOIN Entry e ON e.id = li.entry_id JOIN Game g ON g.id = e.game_id";
+
+fn to_library(row: &SqliteRow, expand: Expand) -> Result<Library, LibError> {
+    let mut entry = try!(to_entry(row, 3));
+    if expand.game {
+        entry.game = Some(to_game(row, 11));
+    }
+    Ok(Library {
+        id: Some(row.get(0)),
+        login_id: row.get(1),
+        entry_id: row.get(2),
+        user: if expand.user { Some(to_user(row, 8).public()) } else { None },
+        entry: Some(entry),
+    })
+}
+
+/// Storage on a single open connection.
+struct Conn<'a> {
+    conn: &'a SqliteConnection,
+}
+
+impl Storage for SqliteStorage {
+    fn games(&self) -> Result<Vec<Game>, LibError> {
+        self.with(|x| x.games())
+    }
+
+    fn game(&self, id: i32) -> Result<Option<Game>, LibError> {
+        self.with(|x| x.game(id))
+    }
+
+    fn games_by_name(&self, name: &str) -> Result<Vec<Game>, LibError> {
+        self.with(|x| x.games_by_name(name))
+    }
+
+    fn add_game(&self, game: &Game) -> Result<Game, LibError> {
+        self.with(|x| x.add_game(game))
+    }
+
+    fn similar_games(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>
+    {
+        self.with(|x| x.similar_games(id, user_id, limit))
+    }
+
+    fn users(&self) -> Result<Vec<User>, LibError> {
+        self.with(|x| x.users())
+    }
+
+    fn user(&self, id: i32) -> Result<Option<User>, LibError> {
+        self.with(|x| x.user(id))
+    }
+
+    fn add_user(&self, login: &Login) -> Result<User, LibError> {
+        self.with(|x| x.add_user(login))
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError> {
+        self.with(|x| x.authenticate(username, password))
+    }
+
+    fn update_user(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+        self.with(|x| x.update_user(id, update))
+    }
+
+    fn delete_user(&self, id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.delete_user(id))
+    }
+
+    fn schedule_deletion(&self, id: i32, at: DateTime<UTC>) -> Result<(), LibError> {
+        self.with(|x| x.schedule_deletion(id, at))
+    }
+
+    fn deletion(&self, id: i32) -> Result<Option<DateTime<UTC>>, LibError> {
+        self.with(|x| x.deletion(id))
+    }
+
+    fn cancel_deletion(&self, id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.cancel_deletion(id))
+    }
+
+    fn due_deletions(&self, now: DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+        self.with(|x| x.due_deletions(now))
+    }
+
+    fn user_by_name(&self, username: &str) -> Result<Option<User>, LibError> {
+        self.with(|x| x.user_by_name(username))
+    }
+
+    fn failures(&self, key: &str) -> Result<Option<Failures>, LibError> {
+        self.with(|x| x.failures(key))
+    }
+
+    fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError> {
+        self.with(|x| x.set_failures(key, failures))
+    }
+
+    fn clear_failures(&self, key: &str) -> Result<(), LibError> {
+        self.with(|x| x.clear_failures(key))
+    }
+
+    fn add_token(&self, token: &AccountToken) -> Result<(), LibError> {
+        self.with(|x| x.add_token(token))
+    }
+
+    fn take_token(&self, token: &str, purpose: &str) -> Result<Option<AccountToken>, LibError> {
+        self.with(|x| x.take_token(token, purpose))
+    }
+
+    fn add_auth_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError> {
+        self.with(|x| x.add_auth_event(event))
+    }
+
+    fn auth_events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError> {
+        self.with(|x| x.auth_events(user_id))
+    }
+
+    fn user_by_email(&self, email: &str) -> Result<Option<User>, LibError> {
+        self.with(|x| x.user_by_email(email))
+    }
+
+    fn set_password(&self, user_id: i32, password: &str) -> Result<bool, LibError> {
+        self.with(|x| x.set_password(user_id, password))
+    }
+
+    fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError> {
+        self.with(|x| x.delete_tokens(user_id, purpose))
+    }
+
+    fn verify_email(&self, user_id: i32, email: &str, at: DateTime<UTC>) -> Result<(), LibError> {
+        self.with(|x| x.verify_email(user_id, email, at))
+    }
+
+    fn email_verified(&self, user_id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.email_verified(user_id))
+    }
+
+    fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError> {
+        self.with(|x| x.tokens(user_id))
+    }
+
+    fn user_rows(&self, user_id: i32) -> Result<UserRows, LibError> {
+        self.with(|x| x.user_rows(user_id))
+    }
+
+    fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError> {
+        self.with(|x| x.library_visibility(user_id))
+    }
+
+    fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>
+    {
+        self.with(|x| x.set_library_visibility(user_id, visibility))
+    }
+
+    fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError> {
+        self.with(|x| x.friends(user_id))
+    }
+
+    fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError> {
+        self.with(|x| x.add_friend(user_id, friend_id))
+    }
+
+    fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.remove_friend(user_id, friend_id))
+    }
+
+    fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        self.with(|x| x.is_friend(user_id, friend_id))
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError> {
+        self.with(|x| x.add_share_link(link))
+    }
+
+    fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError> {
+        self.with(|x| x.share_links(user_id))
+    }
+
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError> {
+        self.with(|x| x.share_link(token))
+    }
+
+    fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError> {
+        self.with(|x| x.delete_share_link(user_id, token))
+    }
+
+    fn statuses(&self) -> Result<Vec<Status>, LibError> {
+        self.with(|x| x.statuses())
+    }
+
+    fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError> {
+        self.with(|x| x.library(user_id, expand))
+    }
+
+    fn library_entry(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>
+    {
+        self.with(|x| x.library_entry(user_id, entry_id, expand))
+    }
+
+    fn entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError> {
+        self.with(|x| x.entry(user_id, entry_id))
+    }
+
+    fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String]) -> Result<Entry, LibError> {
+        self.with(|x| x.add_entry(user_id, entry, tags))
+    }
+
+    fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError> {
+        self.with(|x| x.update_entry(user_id, entry))
+    }
+
+    fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError> {
+        self.with(|x| x.export(user_id))
+    }
+
+    fn counts(&self) -> Result<Counts, LibError> {
+        self.with(|x| x.counts())
+    }
+
+    fn ping(&self) -> Result<(), LibError> {
+        self.with(|x| x.ping())
+    }
+
+    fn migrations(&self) -> Result<Vec<i32>, LibError> {
+        self.with(|x| x.migrations())
+    }
+
+    fn transaction(&self, f: &mut FnMut(&Storage) -> Result<(), LibError>)
+        -> Result<(), LibError>
+    {
+        self.with(|x| x.transaction(f))
+    }
+}
+
+impl<'a> Storage for Conn<'a> {
+    fn games(&self) -> Result<Vec<Game>, LibError> {
+        query(self.conn, "SELECT id, name, description FROM Game ORDER BY name", &[],
+              |row| Ok(to_game(row, 0)))
+    }
+
+    fn game(&self, id: i32) -> Result<Option<Game>, LibError> {
+        query(self.conn, "SELECT id, name, description FROM Game WHERE id = ?", &[&id],
+              |row| Ok(to_game(row, 0))).map(|mut x| x.pop())
+    }
+
+    fn games_by_name(&self, name: &str) -> Result<Vec<Game>, LibError> {
+        query(self.conn,
+              "SELECT id, name, description FROM Game WHERE lower(name) = lower(?) ORDER BY id",
+              &[&name], |row| Ok(to_game(row, 0)))
+    }
+
+    fn add_game(&self, game: &Game) -> Result<Game, LibError> {
+        try!(execute(self.conn, "INSERT INTO Game (name, description) VALUES (?, ?)",
+                     &[&game.name, &game.description]));
+        Ok(Game {
+            id: Some(self.conn.last_insert_rowid() as i32),
+            name: game.name.clone(),
+            description: game.description.clone(),
+        })
+    }
+
+    fn similar_games(&self, id: i32, user_id: Option<i32>, limit: i64)
+        -> Result<Vec<SimilarGame>, LibError>
+    {
+        // SQLite has no sqrt, so the score is calculated here instead.
+        let mut res = try!(query(self.conn,
+              "SELECT g.id, g.name, g.description, co.players, pa.players, pb.players \
+                FROM GameCooccurrence co \
+                JOIN GamePopularity pa ON pa.game_id = co.game_id \
+                JOIN GamePopularity pb ON pb.game_id = co.other_id \
+                JOIN Game g ON g.id = co.other_id \
+                WHERE co.game_id = ? AND NOT EXISTS \
+                    (SELECT * FROM Library li JOIN Entry e ON e.id = li.entry_id \
+                        WHERE li.login_id = ? AND e.game_id = co.other_id)",
+              &[&id, &user_id.unwrap_or(-1)],
+              |row| Ok(SimilarGame {
+                  game: to_game(row, 0),
+                  score: row.get::<i32>(3) as f64
+                      / (row.get::<i32>(4) as f64 * row.get::<i32>(5) as f64).sqrt(),
+              })));
+        res.sort_by(|a, b| match b.score.partial_cmp(&a.score).unwrap() {
+            Ordering::Equal => a.game.name.cmp(&b.game.name),
+            x => x,
+        });
+        res.truncate(limit as usize);
+        Ok(res)
+    }
+
+    fn users(&self) -> Result<Vec<User>, LibError> {
+        query(self.conn, "SELECT id, username, email FROM Login", &[],
+              |row| Ok(to_user(row, 0)))
+    }
+
+    fn user(&self, id: i32) -> Result<Option<User>, LibError> {
+        query(self.conn, "SELECT id, username, email FROM Login WHERE id = ?", &[&id],
+              |row| Ok(to_user(row, 0))).map(|mut x| x.pop())
+    }
+
+    fn add_user(&self, login: &Login) -> Result<User, LibError> {
+        try!(execute(self.conn, "INSERT INTO Login (username, password, email) VALUES (?, ?, ?)",
+                     &[&login.username, &login.password, &login.email]));
+        Ok(User {
+            id: Some(self.conn.last_insert_rowid() as i32),
+            username: login.username.clone(),
+            email: login.email.clone(),
+        })
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>, LibError> {
+        query(self.conn,
+              "SELECT id, username, email FROM Login WHERE username = ? AND password = ?",
+              &[&username, &password], |row| Ok(to_user(row, 0))).map(|mut x| x.pop())
+    }
+
+    fn update_user(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, LibError> {
+        savepoint(self.conn, || {
+            if let Some(ref username) = update.username {
+                try!(execute(self.conn, "UPDATE Login SET username = ? WHERE id = ?",
+                             &[username, &id]));
+            }
+            if let Some(ref email) = update.email {
+                try!(execute(self.conn, "UPDATE Login SET email = ? WHERE id = ?",
+                             &[email, &id]));
+            }
+            self.user(id)
+        })
+    }
+
+    /// Foreign keys don't cascade in SQLite, so everything referring to the
+    /// user is deleted table by table. The library goes before the entries,
+    /// which its delete trigger needs to take the games out of the games
+    /// played together.
+    fn delete_user(&self, id: i32) -> Result<bool, LibError> {
+        savepoint(self.conn, || {
+            let entries = try!(query(self.conn, "SELECT entry_id FROM Library WHERE login_id = ?",
+                                     &[&id], |row| Ok(row.get::<i32>(0))));
+            try!(execute(self.conn, "DELETE FROM Library WHERE login_id = ?", &[&id]));
+            for entry_id in entries.iter() {
+                try!(execute(self.conn, "DELETE FROM EntryTag WHERE entry_id = ?", &[entry_id]));
+                try!(execute(self.conn, "DELETE FROM Entry WHERE id = ?", &[entry_id]));
+            }
+            for table in ["AccountToken", "AuthEvent", "EmailVerification",
+                          "PendingDeletion", "Privacy", "ShareLink"].iter() {
+                try!(execute(self.conn, &format!("DELETE FROM {} WHERE login_id = ?", table),
+                             &[&id]));
+            }
+            try!(execute(self.conn, "DELETE FROM Friend WHERE login_id = ?1 OR friend_id = ?1",
+                         &[&id]));
+            execute(self.conn, "DELETE FROM Login WHERE id = ?", &[&id]).map(|x| x > 0)
+        })
+    }
+
+    fn schedule_deletion(&self, id: i32, at: DateTime<UTC>) -> Result<(), LibError> {
+        execute(self.conn, "INSERT OR REPLACE INTO PendingDeletion (login_id, delete_at) \
+                    VALUES (?, ?)",
+                &[&id, &export::format_timestamp(&at)]).map(|_| ())
+    }
+
+    fn deletion(&self, id: i32) -> Result<Option<DateTime<UTC>>, LibError> {
+        query(self.conn, "SELECT delete_at FROM PendingDeletion WHERE login_id = ?", &[&id],
+              |row| export::parse_timestamp(&row.get::<String>(0))).map(|mut x| x.pop())
+    }
+
+    fn cancel_deletion(&self, id: i32) -> Result<bool, LibError> {
+        execute(self.conn, "DELETE FROM PendingDeletion WHERE login_id = ?", &[&id])
+            .map(|x| x > 0)
+    }
+
+    /// The timestamps are all in the same format, which sorts like the time.
+    fn due_deletions(&self, now: DateTime<UTC>) -> Result<Vec<i32>, LibError> {
+        query(self.conn,
+              "SELECT login_id FROM PendingDeletion WHERE delete_at <= ? ORDER BY login_id",
+              &[&export::format_timestamp(&now)], |row| Ok(row.get(0)))
+    }
+
+    fn user_by_name(&self, username: &str) -> Result<Option<User>, LibError> {
+        query(self.conn, "SELECT id, username, email FROM Login WHERE username = ?",
+              &[&username], |row| Ok(to_user(row, 0))).map(|mut x| x.pop())
+    }
+
+    fn failures(&self, key: &str) -> Result<Option<Failures>, LibError> {
+        query(self.conn,
+              "SELECT failures, last_failure, blocked_until FROM AuthFailure WHERE key = ?",
+              &[&key], |row| Ok(Failures {
+                  failures: row.get(0),
+                  last_failure: try!(export::parse_timestamp(&row.get::<String>(1))),
+                  blocked_until: match row.get::<Option<String>>(2) {
+                      Some(x) => Some(try!(export::parse_timestamp(&x))),
+                      None => None,
+                  },
+              })).map(|mut x| x.pop())
+    }
+
+    fn set_failures(&self, key: &str, failures: &Failures) -> Result<(), LibError> {
+        let last_failure = export::format_timestamp(&failures.last_failure);
+        match failures.blocked_until {
+            Some(ref x) => execute(self.conn,
+                "INSERT OR REPLACE INTO AuthFailure (key, failures, last_failure, blocked_until) \
+                    VALUES (?, ?, ?, ?)",
+                &[&key, &failures.failures, &last_failure, &export::format_timestamp(x)]),
+            None => execute(self.conn,
+                "INSERT OR REPLACE INTO AuthFailure (key, failures, last_failure) VALUES (?, ?, ?)",
+                &[&key, &failures.failures, &last_failure]),
+        }.map(|_| ())
+    }
+
+    fn clear_failures(&self, key: &str) -> Result<(), LibError> {
+        execute(self.conn, "DELETE FROM AuthFailure WHERE key = ?", &[&key]).map(|_| ())
+    }
+
+    fn add_token(&self, token: &AccountToken) -> Result<(), LibError> {
+        execute(self.conn,
+                "INSERT INTO AccountToken (token, login_id, purpose, expires_at) \
+                    VALUES (?, ?, ?, ?)",
+                &[&token.token, &token.login_id, &token.purpose,
+                  &export::format_timestamp(&token.expires_at)]).map(|_| ())
+    }
+
+    fn take_token(&self, token: &str, purpose: &str) -> Result<Option<AccountToken>, LibError> {
+        savepoint(self.conn, || {
+            let res = try!(query(self.conn,
+                  "SELECT login_id, expires_at FROM AccountToken WHERE token = ? AND purpose = ?",
+                  &[&token, &purpose], |row| Ok(AccountToken {
+                      token: token.to_string(),
+                      login_id: row.get(0),
+                      purpose: purpose.to_string(),
+                      expires_at: try!(export::parse_timestamp(&row.get::<String>(1))),
+                  }))).pop();
+            try!(execute(self.conn, "DELETE FROM AccountToken WHERE token = ? AND purpose = ?",
+                         &[&token, &purpose]));
+            Ok(res)
+        })
+    }
+
+    fn add_auth_event(&self, event: &AuthEvent) -> Result<AuthEvent, LibError> {
+        // Like postgres, keep the time in the same format however it was given.
+        let created_at = export::format_timestamp(
+            &try!(export::parse_timestamp(&event.created_at)));
+        try!(match event.login_id {
+            Some(id) => execute(self.conn,
+                "INSERT INTO AuthEvent (username, ip, kind, created_at, login_id) \
+                    VALUES (?, ?, ?, ?, ?)",
+                &[&event.username, &event.ip, &event.kind, &created_at, &id]),
+            None => execute(self.conn,
+                "INSERT INTO AuthEvent (username, ip, kind, created_at) VALUES (?, ?, ?, ?)",
+                &[&event.username, &event.ip, &event.kind, &created_at]),
+        });
+        let mut res = try!(query(self.conn,
+              "SELECT id, login_id, username, ip, kind, created_at FROM AuthEvent WHERE id = ?",
+              &[&(self.conn.last_insert_rowid() as i32)], |row| Ok(to_auth_event(row))));
+        res.pop().ok_or(LibError::Cause("Failed inserting authentication event".to_string()))
+    }
+
+    fn auth_events(&self, user_id: i32) -> Result<Vec<AuthEvent>, LibError> {
+        query(self.conn,
+              "SELECT id, login_id, username, ip, kind, created_at FROM AuthEvent \
+                WHERE login_id = ? ORDER BY id DESC",
+              &[&user_id], |row| Ok(to_auth_event(row)))
+    }
+
+    fn user_by_email(&self, email: &str) -> Result<Option<User>, LibError> {
+        // The email column ignores case.
+        query(self.conn, "SELECT id, username, email FROM Login WHERE email = ?",
+              &[&email], |row| Ok(to_user(row, 0))).map(|mut x| x.pop())
+    }
+
+    fn set_password(&self, user_id: i32, password: &str) -> Result<bool, LibError> {
+        execute(self.conn, "UPDATE Login SET password = ? WHERE id = ?", &[&password, &user_id])
+            .map(|x| x > 0)
+    }
+
+    fn delete_tokens(&self, user_id: i32, purpose: &str) -> Result<(), LibError> {
+        execute(self.conn, "DELETE FROM AccountToken WHERE login_id = ? AND purpose = ?",
+                &[&user_id, &purpose]).map(|_| ())
+    }
+
+    fn verify_email(&self, user_id: i32, email: &str, at: DateTime<UTC>) -> Result<(), LibError> {
+        execute(self.conn,
+                "INSERT OR REPLACE INTO EmailVerification (login_id, email, verified_at) \
+                    VALUES (?, ?, ?)",
+                &[&user_id, &email, &export::format_timestamp(&at)]).map(|_| ())
+    }
+
+    fn email_verified(&self, user_id: i32) -> Result<bool, LibError> {
+        query(self.conn,
+              "SELECT 1 FROM EmailVerification v JOIN Login l ON l.id = v.login_id \
+                WHERE v.login_id = ? AND v.email = l.email",
+              &[&user_id], |_| Ok(())).map(|x| !x.is_empty())
+    }
+
+    fn tokens(&self, user_id: i32) -> Result<Vec<AccountToken>, LibError> {
+        query(self.conn,
+              "SELECT token, purpose, expires_at FROM AccountToken WHERE login_id = ? \
+                ORDER BY expires_at",
+              &[&user_id], |row| Ok(AccountToken {
+                  token: row.get(0),
+                  login_id: user_id,
+                  purpose: row.get(1),
+                  expires_at: try!(export::parse_timestamp(&row.get::<String>(2))),
+              }))
+    }
+
+    fn user_rows(&self, user_id: i32) -> Result<UserRows, LibError> {
+        let entries = "SELECT entry_id FROM Library WHERE login_id = ?1";
+        let games = "SELECT DISTINCT e.game_id FROM Library li \
+            JOIN Entry e ON e.id = li.entry_id WHERE li.login_id = ?1";
+        let sql = format!(
+            "SELECT (SELECT count(*) FROM Login WHERE id = ?1), \
+                (SELECT count(*) FROM Library WHERE login_id = ?1), \
+                (SELECT count(*) FROM Entry WHERE id IN ({0})), \
+                (SELECT count(*) FROM EntryTag WHERE entry_id IN ({0})), \
+                (SELECT count(*) FROM AccountToken WHERE login_id = ?1), \
+                (SELECT count(*) FROM AuthEvent WHERE login_id = ?1), \
+                (SELECT count(*) FROM AuthFailure WHERE key = ?2), \
+                (SELECT count(*) FROM EmailVerification WHERE login_id = ?1), \
+                (SELECT count(*) FROM PendingDeletion WHERE login_id = ?1), \
+                (SELECT count(*) FROM Privacy WHERE login_id = ?1), \
+                (SELECT count(*) FROM Friend WHERE login_id = ?1 OR friend_id = ?1), \
+                (SELECT count(*) FROM ShareLink WHERE login_id = ?1), \
+                (SELECT count(*) FROM ({1})), \
+                (SELECT count(*) FROM ({1}) a JOIN ({1}) b ON a.game_id <> b.game_id)",
+            entries, games);
+        let key = lockout::login_key(user_id);
+        query(self.conn, &sql, &[&user_id, &key], |row| Ok(UserRows {
+            logins: row.get(0),
+            library: row.get(1),
+            entries: row.get(2),
+            entry_tags: row.get(3),
+            account_tokens: row.get(4),
+            auth_events: row.get(5),
+            auth_failures: row.get(6),
+            email_verifications: row.get(7),
+            pending_deletions: row.get(8),
+            privacy_settings: row.get(9),
+            friends: row.get(10),
+            share_links: row.get(11),
+            game_popularity: row.get(12),
+            game_cooccurrence: row.get(13),
+        })).map(|mut x| x.pop().unwrap_or(UserRows::default()))
+    }
+
+    fn library_visibility(&self, user_id: i32) -> Result<Visibility, LibError> {
+        let library = try!(query(self.conn, "SELECT library FROM Privacy WHERE login_id = ?",
+                                 &[&user_id], |row| Ok(row.get::<String>(0))));
+        match library.into_iter().next() {
+            Some(library) => library.parse(),
+            None => Ok(Visibility::Public),
+        }
+    }
+
+    fn set_library_visibility(&self, user_id: i32, visibility: Visibility)
+        -> Result<(), LibError>
+    {
+        execute(self.conn, "INSERT OR REPLACE INTO Privacy (login_id, library) VALUES (?, ?)",
+                &[&user_id, &visibility.to_string()]).map(|_| ())
+    }
+
+    fn friends(&self, user_id: i32) -> Result<Vec<User>, LibError> {
+        query(self.conn,
+              "SELECT lo.id, lo.username, lo.email FROM Friend f \
+                JOIN Login lo ON lo.id = f.friend_id WHERE f.login_id = ? ORDER BY lo.username",
+              &[&user_id], |row| Ok(to_user(row, 0)))
+    }
+
+    fn add_friend(&self, user_id: i32, friend_id: i32) -> Result<(), LibError> {
+        execute(self.conn, "INSERT OR IGNORE INTO Friend (login_id, friend_id) VALUES (?, ?)",
+                &[&user_id, &friend_id]).map(|_| ())
+    }
+
+    fn remove_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        execute(self.conn, "DELETE FROM Friend WHERE login_id = ? AND friend_id = ?",
+                &[&user_id, &friend_id]).map(|x| x > 0)
+    }
+
+    fn is_friend(&self, user_id: i32, friend_id: i32) -> Result<bool, LibError> {
+        query(self.conn, "SELECT 1 FROM Friend WHERE login_id = ? AND friend_id = ?",
+              &[&user_id, &friend_id], |_| Ok(())).map(|x| !x.is_empty())
+    }
+
+    fn add_share_link(&self, link: &ShareLink) -> Result<ShareLink, LibError> {
+        // Like postgres, keep the time in the same format however it was given.
+        let created_at = export::format_timestamp(
+            &try!(export::parse_timestamp(&link.created_at)));
+        savepoint(self.conn, || {
+            try!(execute(self.conn,
+                    "INSERT INTO ShareLink (token, login_id, created_at) VALUES (?, ?, ?)",
+                    &[&link.token, &link.login_id, &created_at]));
+            if let Some(status) = link.status {
+                try!(execute(self.conn, "UPDATE ShareLink SET status = ? WHERE token = ?",
+                             &[&status.to_string(), &link.token]));
+            }
+            if let Some(ref tag) = link.tag {
+                try!(execute(self.conn, "UPDATE ShareLink SET tag = ? WHERE token = ?",
+                             &[tag, &link.token]));
+            }
+            try!(self.share_link(&link.token))
+                .ok_or(LibError::Cause("Failed inserting share link".to_string()))
+        })
+    }
+
+    fn share_links(&self, user_id: i32) -> Result<Vec<ShareLink>, LibError> {
+        query(self.conn,
+              "SELECT token, login_id, status, tag, created_at FROM ShareLink \
+                WHERE login_id = ? ORDER BY created_at, token",
+              &[&user_id], |row| to_share_link(row))
+    }
+
+    fn share_link(&self, token: &str) -> Result<Option<ShareLink>, LibError> {
+        query(self.conn,
+              "SELECT token, login_id, status, tag, created_at FROM ShareLink WHERE token = ?",
+              &[&token], |row| to_share_link(row)).map(|mut x| x.pop())
+    }
+
+    fn delete_share_link(&self, user_id: i32, token: &str) -> Result<bool, LibError> {
+        execute(self.conn, "DELETE FROM ShareLink WHERE login_id = ? AND token = ?",
+                &[&user_id, &token]).map(|x| x > 0)
+    }
+
+    fn statuses(&self) -> Result<Vec<Status>, LibError> {
+        // The same order as the CHECK constraint on Entry.
+        Ok(vec![Status::Frozen, Status::CurrentlyPlaying, Status::Dropped, Status::PlanToPlay])
+    }
+
+    fn library(&self, user_id: i32, expand: Expand) -> Result<Vec<Library>, LibError> {
+        query(self.conn, &format!("{} WHERE lo.id = ? ORDER BY e.id", LIBRARY), &[&user_id],
+              |row| to_library(row, expand))
+    }
+
+    fn library_entry(&self, user_id: i32, entry_id: i32, expand: Expand)
+        -> Result<Option<Library>, LibError>
+    {
+        query(self.conn, &format!("{} WHERE lo.id = ? AND e.id = ?", LIBRARY),
+              &[&user_id, &entry_id], |row| to_library(row, expand)).map(|mut x| x.pop())
+    }
+
+    fn entry(&self, user_id: i32, entry_id: i32) -> Result<Option<Entry>, LibError> {
+        query(self.conn,
+              "SELECT e.id, e.game_id, e.time_played, e.last_update, e.status \
+                FROM Library li JOIN Entry e ON e.id = li.entry_id \
+                WHERE e.id = ? AND li.login_id = ?",
+              &[&entry_id, &user_id], |row| to_entry(row, 0)).map(|mut x| x.pop())
+    }
+
+    fn add_entry(&self, user_id: i32, entry: &Entry, tags: &[String]) -> Result<Entry, LibError> {
+        let status = try!(entry.status.ok_or(LibError::Cause("Missing status".to_string())));
+        let game_id = try!(entry.game_id.ok_or(LibError::Cause("Missing game".to_string())));
+        let time_played = try!(entry.time_played
+                               .ok_or(LibError::Cause("Missing time played".to_string())));
+        savepoint(self.conn, || {
+            let status = status.to_string();
+            let time_played = time_played as f64;
+            match entry.last_update {
+                Some(ref x) => {
+                    let last_update = try!(UtcString::from_str(x).map_err(LibError::other));
+                    try!(execute(self.conn,
+                                 "INSERT INTO Entry (game_id, time_played, status, last_update) \
+                                    VALUES (?, ?, ?, ?)",
+                                 &[&game_id, &time_played, &status,
+                                   &export::format_timestamp(&last_update.0)]));
+                },
+                None => {
+                    try!(execute(self.conn,
+                                 "INSERT INTO Entry (game_id, time_played, status) \
+                                    VALUES (?, ?, ?)",
+                                 &[&game_id, &time_played, &status]));
+                },
+            }
+            let entry_id = self.conn.last_insert_rowid() as i32;
+
+            try!(execute(self.conn, "INSERT INTO Library (entry_id, login_id) VALUES (?, ?)",
+                         &[&entry_id, &user_id]));
+            for tag in tags.iter() {
+                try!(execute(self.conn, "INSERT INTO EntryTag (entry_id, tag) VALUES (?, ?)",
+                             &[&entry_id, tag]));
+            }
+
+            let mut res = try!(query(self.conn,
+                  "SELECT id, game_id, time_played, last_update, status FROM Entry WHERE id = ?",
+                  &[&entry_id], |row| to_entry(row, 0)));
+            res.pop().ok_or(LibError::Cause("Failed inserting new entry".to_string()))
+        })
+    }
+
+    fn update_entry(&self, user_id: i32, entry: &Entry) -> Result<bool, LibError> {
+        let status = try!(entry.status.ok_or(LibError::Cause("Missing status".to_string())));
+        let time_played = try!(entry.time_played
+                               .ok_or(LibError::Cause("Missing time played".to_string())));
+        let entry_id = try!(entry.id.ok_or(LibError::Cause("Missing entry".to_string())));
+        execute(self.conn,
+                "UPDATE Entry SET status = ?, time_played = ? WHERE id = ? AND EXISTS \
+                    (SELECT * FROM Library li WHERE li.entry_id = Entry.id AND li.login_id = ?)",
+                &[&status.to_string(), &(time_played as f64), &entry_id, &user_id])
+            .map(|x| x > 0)
+    }
+
+    fn export(&self, user_id: i32) -> Result<Vec<ExportEntry>, LibError> {
+        let tags = try!(query(self.conn,
+              "SELECT et.entry_id, et.tag FROM Library li \
+                JOIN EntryTag et ON et.entry_id = li.entry_id \
+                WHERE li.login_id = ? ORDER BY et.tag",
+              &[&user_id], |row| Ok((row.get::<i32>(0), row.get::<String>(1)))));
+
+        query(self.conn,
+              "SELECT e.id, e.time_played, e.status, e.last_update, g.id, g.name, g.description \
+                FROM Library li JOIN Entry e ON e.id = li.entry_id \
+                JOIN Game g ON g.id = e.game_id \
+                WHERE li.login_id = ? ORDER BY e.id",
+              &[&user_id], |row| {
+                  let id = row.get(0);
+                  Ok(ExportEntry {
+                      id: id,
+                      game: to_game(row, 4),
+                      time_played: row.get::<f64>(1) as f32,
+                      status: try!(row.get::<String>(2).parse()),
+                      last_update: row.get(3),
+                      tags: tags.iter().filter(|x| x.0 == id).map(|x| x.1.clone()).collect(),
+                  })
+              })
+    }
+
+    fn counts(&self) -> Result<Counts, LibError> {
+        let count = |sql: &str| query(self.conn, sql, &[], |row| Ok(row.get::<i64>(0)))
+            .map(|x| x.into_iter().next().unwrap_or(0));
+        let by_status = try!(query(self.conn, "SELECT status, count(*) FROM Entry GROUP BY status",
+                                   &[], |row| Ok((row.get::<String>(0), row.get::<i64>(1)))));
+        Ok(Counts {
+            users: try!(count("SELECT count(*) FROM Login")),
+            games: try!(count("SELECT count(*) FROM Game")),
+            entries: try!(self.statuses()).into_iter().map(|s| {
+                let n = by_status.iter().find(|x| x.0 == s.to_string()).map(|x| x.1);
+                (s, n.unwrap_or(0))
+            }).collect(),
+        })
+    }
+
+    fn ping(&self) -> Result<(), LibError> {
+        query(self.conn, "SELECT 1", &[], |_| Ok(())).map(|_| ())
+    }
+
+    fn migrations(&self) -> Result<Vec<i32>, LibError> {
+        query(self.conn, "SELECT version FROM SchemaMigration ORDER BY version", &[],
+              |row| Ok(row.get(0)))
+    }
+
+    fn transaction(&self, f: &mut FnMut(&Storage) -> Result<(), LibError>)
+        -> Result<(), LibError>
+    {
+        savepoint(self.conn, || f(self))
+    }
+}
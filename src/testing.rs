@@ -0,0 +1,260 @@
+//! Support for testing the API in-process, without binding a socket.
+//!
+//! A `TestServer` builds the same `Chain` as the binary on top of any
+//! storage and answers requests written as plain HTTP:
+//!
+//! ```rust
+//! let server = TestServer::new();
+//! let user = server.post("/api/user", r#"{"username": "a", ...}"#)
+//!     .assert_status(status::Ok)
+//!     .json::<User>();
+//! ```
+use std::ascii::AsciiExt;
+use std::env;
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use hyper::net::NetworkStream;
+use hyper::server::request::Request as HttpRequest;
+use iron::prelude::*;
+use iron::{headers, Handler};
+use iron::status::Status;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::{json, Decodable};
+use time;
+use handlers::{self, Config};
+use logging::Logger;
+use models::{Entry, Game, User};
+use storage::{self, Storage};
+use {Db, DbConnection};
+
+/// Every request seems to come from and go to this address.
+const ADDR: &'static str = "127.0.0.1:3000";
+
+/// A stream reading a request written up front, ignoring anything written.
+struct MockStream {
+    input: Cursor<Vec<u8>>,
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for MockStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(ADDR.parse().unwrap())
+    }
+}
+
+/// Returns `prefix` followed by a number that differs between calls and test
+/// runs, for names that must be unique in a database shared by tests. Keep
+/// `prefix` short; usernames can be at most 20 characters.
+pub fn unique(prefix: &str) -> String {
+    static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{}{}{}", prefix, time::precise_time_ns() % 1000000, n)
+}
+
+/// Asks to sign up `username`, with the password `secret`.
+pub fn try_signup(server: &TestServer, username: &str) -> TestResponse {
+    server.post("/api/user", &format!(
+            r#"{{"id": null, "username": "{0}", "password": "secret",
+                 "email": "{0}@example.com"}}"#, username))
+}
+
+/// Signs up `username`, with the password `secret`.
+pub fn signup(server: &TestServer, username: &str) -> User {
+    try_signup(server, username)
+        .assert_status(Status::Ok)
+        .json::<User>()
+}
+
+/// Adds the `game`th of the games the server lists to the library of the
+/// user with `id`, logging in with `auth`.
+pub fn add_entry(server: &TestServer, id: i32, auth: (&str, &str), game: usize) -> Entry {
+    let games = server.get("/api/game").assert_status(Status::Ok).json::<Vec<Game>>();
+    server.request(TestRequest::post(&format!("/api/user/{}/library", id))
+                   .basic_auth(auth.0, auth.1)
+                   .body(&format!(
+            r#"{{"id": null, "game_id": {}, "time_played": null, "last_update": null,
+                "status": null, "game": null}}"#, games[game].id.unwrap())))
+        .assert_status(Status::Ok)
+        .json::<Entry>()
+}
+
+/// The whole API running on a storage of its own.
+pub struct TestServer {
+    chain: Chain,
+    db: Db,
+}
+
+impl TestServer {
+    /// Serves the storage given by `BACKLOGRS_TEST_DATABASE`, see
+    /// `storage::open`, or else a new in-memory storage. Either is seeded with
+    /// the data of `db.sql` unless it has games already, so a scratch database
+    /// only needs its schema.
+    pub fn new() -> TestServer {
+        let url = env::var("BACKLOGRS_TEST_DATABASE").unwrap_or("memory:".to_string());
+        let storage = storage::open(&url).unwrap();
+        if storage.games().unwrap().is_empty() {
+            storage::seed(&**storage).unwrap();
+        }
+        TestServer::with_storage(storage)
+    }
+
+    /// Serves `storage` with the defaults of `Config`.
+    pub fn with_storage(storage: Box<Storage + Send + Sync>) -> TestServer {
+        TestServer::with_config(storage, Config::new())
+    }
+
+    pub fn with_logger(storage: Box<Storage + Send + Sync>, logger: Logger) -> TestServer {
+        TestServer::with_config(storage, Config::new().logger(logger))
+    }
+
+    pub fn with_config(storage: Box<Storage + Send + Sync>, config: Config) -> TestServer {
+        let db = DbConnection::with_boxed_storage(storage);
+        TestServer {
+            db: db.storage(),
+            chain: handlers::chain(db, config),
+        }
+    }
+
+    /// The storage being served, for what happens outside of requests.
+    pub fn db(&self) -> Db {
+        self.db.clone()
+    }
+
+    pub fn get(&self, path: &str) -> TestResponse {
+        self.request(TestRequest::get(path))
+    }
+
+    pub fn post(&self, path: &str, body: &str) -> TestResponse {
+        self.request(TestRequest::post(path).body(body))
+    }
+
+    pub fn request(&self, req: TestRequest) -> TestResponse {
+        let mut raw = format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n",
+                              req.method, req.path, ADDR, req.body.len());
+        for &(ref name, ref value) in req.headers.iter() {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str("\r\n");
+        raw.push_str(&req.body);
+
+        let stream = MockStream { input: Cursor::new(raw.into_bytes()) };
+        let mut reader = BufReader::new(Box::new(stream) as Box<NetworkStream + Send>);
+        let addr = ADDR.parse().unwrap();
+        let http = HttpRequest::new(&mut reader, addr).unwrap();
+        let mut req = Request::from_http(http, addr).unwrap();
+
+        let res = match self.chain.handle(&mut req) {
+            Ok(res) => res,
+            Err(err) => err.response,
+        };
+        let mut body = String::new();
+        if let Some(mut reader) = res.body {
+            reader.read_to_string(&mut body).unwrap();
+        }
+        TestResponse {
+            status: res.status.unwrap_or(Status::Ok),
+            headers: res.headers,
+            body: body,
+        }
+    }
+}
+
+/// A request to send to a `TestServer`.
+pub struct TestRequest {
+    method: &'static str,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl TestRequest {
+    pub fn new(method: &'static str, path: &str) -> TestRequest {
+        TestRequest {
+            method: method,
+            path: path.to_string(),
+            headers: vec![],
+            body: String::new(),
+        }
+    }
+
+    pub fn get(path: &str) -> TestRequest {
+        TestRequest::new("GET", path)
+    }
+
+    pub fn post(path: &str) -> TestRequest {
+        TestRequest::new("POST", path)
+    }
+
+    pub fn patch(path: &str) -> TestRequest {
+        TestRequest::new("PATCH", path)
+    }
+
+    pub fn delete(path: &str) -> TestRequest {
+        TestRequest::new("DELETE", path)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> TestRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Authenticates as `username` with HTTP basic auth.
+    pub fn basic_auth(self, username: &str, password: &str) -> TestRequest {
+        let credentials = format!("{}:{}", username, password).as_bytes().to_base64(STANDARD);
+        self.header("Authorization", &format!("Basic {}", credentials))
+    }
+
+    pub fn body(mut self, body: &str) -> TestRequest {
+        self.body = body.to_string();
+        self
+    }
+}
+
+/// What a `TestServer` answered, with assertions that print the body when
+/// they fail since that is where any error ends up.
+pub struct TestResponse {
+    pub status: Status,
+    pub headers: headers::Headers,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn assert_status(&self, status: Status) -> &TestResponse {
+        assert!(self.status == status, "expected {} but got {}: {}",
+                status, self.status, self.body);
+        self
+    }
+
+    /// Asserts that the header `name` is `value`, as it would be written.
+    pub fn assert_header(&self, name: &str, value: &str) -> &TestResponse {
+        let actual = self.headers.iter().find(|x| x.name().eq_ignore_ascii_case(name))
+            .map(|x| x.value_string());
+        assert!(actual.as_ref().map(|x| &x[..]) == Some(value),
+                "expected header {} to be {} but it was {:?}", name, value, actual);
+        self
+    }
+
+    /// Decodes the body as JSON, panicking if it isn't a `T`.
+    pub fn json<T: Decodable>(&self) -> T {
+        match json::decode(&self.body) {
+            Ok(x) => x,
+            Err(err) => panic!("unable to decode {}: {}", self.body, err),
+        }
+    }
+}
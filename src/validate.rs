@@ -0,0 +1,267 @@
+//! Validation of models sent by clients, before they reach the database.
+//!
+//! Rules are declared for the fields of a model with `validate!`:
+//!
+//! ```rust
+//! validate!(Login {
+//!     username: length(min = 1, max = 20),
+//!     email: email,
+//! });
+//! ```
+//!
+//! and `GetValid::get_valid` decodes a body and validates it in one go,
+//! answering `422 Unprocessable Entity` with every error by field.
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use bodyparser;
+use iron::prelude::*;
+use iron::status;
+use rustc_serialize::Decodable;
+use {Json, LibError, OnError};
+
+/// Implements `Validate` for a struct from rules on its fields, which for an
+/// `Option` only apply when it is `Some`:
+///
+/// - `length(min = 1, max = 20)` counts the characters of a string.
+/// - `range(min = 0, max = 100)` bounds a number.
+/// - `email` requires a string to look like an email address.
+/// - `required` requires an `Option` to be `Some`.
+/// - `required_if(method)` requires an `Option` to be `Some` whenever the
+///   method of the model with that name returns true.
+///
+/// Every rule is followed by a comma, and a field with several rules is
+/// listed once for each. Errors are in the order of the rules.
+#[macro_export]
+macro_rules! validate {
+    ($model:ident {}) => {
+        impl $crate::validate::Validate for $model {
+            fn validate(&self) -> Result<(), $crate::validate::ValidationErrors> {
+                Ok(())
+            }
+        }
+    };
+    ($model:ident { $($field:ident: $rule:ident $(($($arg:tt)*))*,)* }) => {
+        impl $crate::validate::Validate for $model {
+            fn validate(&self) -> Result<(), $crate::validate::ValidationErrors> {
+                let mut errors = $crate::validate::ValidationErrors::new();
+                $(validate_field!(errors, self, $field: $rule $(($($arg)*))*);)*
+                errors.into_result()
+            }
+        }
+    };
+}
+
+/// Checks a single rule for `validate!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! validate_field {
+    ($errors:ident, $model:expr, $field:ident: length(min = $min:expr, max = $max:expr)) => {
+        validate_field!(@text $errors, $model, $field, Some($min), Some($max))
+    };
+    ($errors:ident, $model:expr, $field:ident: length(min = $min:expr)) => {
+        validate_field!(@text $errors, $model, $field, Some($min), None)
+    };
+    ($errors:ident, $model:expr, $field:ident: length(max = $max:expr)) => {
+        validate_field!(@text $errors, $model, $field, None, Some($max))
+    };
+    ($errors:ident, $model:expr, $field:ident: range(min = $min:expr, max = $max:expr)) => {
+        validate_field!(@number $errors, $model, $field, Some($min as f64), Some($max as f64))
+    };
+    ($errors:ident, $model:expr, $field:ident: range(min = $min:expr)) => {
+        validate_field!(@number $errors, $model, $field, Some($min as f64), None)
+    };
+    ($errors:ident, $model:expr, $field:ident: range(max = $max:expr)) => {
+        validate_field!(@number $errors, $model, $field, None, Some($max as f64))
+    };
+    ($errors:ident, $model:expr, $field:ident: email) => {
+        if let Some(value) = $crate::validate::Text::text(&$model.$field) {
+            $errors.check(stringify!($field), $crate::validate::rules::email(value));
+        }
+    };
+    ($errors:ident, $model:expr, $field:ident: required) => {
+        if $model.$field.is_none() {
+            $errors.add(stringify!($field), "is required");
+        }
+    };
+    ($errors:ident, $model:expr, $field:ident: required_if($method:ident)) => {
+        if $model.$method() && $model.$field.is_none() {
+            $errors.add(stringify!($field), "is required");
+        }
+    };
+
+    (@text $errors:ident, $model:expr, $field:ident, $min:expr, $max:expr) => {
+        if let Some(value) = $crate::validate::Text::text(&$model.$field) {
+            $errors.check(stringify!($field), $crate::validate::rules::length(value, $min, $max));
+        }
+    };
+    (@number $errors:ident, $model:expr, $field:ident, $min:expr, $max:expr) => {
+        if let Some(value) = $crate::validate::Number::number(&$model.$field) {
+            $errors.check(stringify!($field), $crate::validate::rules::range(value, $min, $max));
+        }
+    };
+}
+
+/// Why a field is invalid.
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Every error of a model, in the order of its fields.
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> ValidationErrors {
+        ValidationErrors {
+            errors: vec![],
+        }
+    }
+
+    pub fn add(&mut self, field: &str, message: &str) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Adds the result of a rule, if it failed.
+    pub fn check(&mut self, field: &str, error: Option<String>) {
+        if let Some(message) = error {
+            self.add(field, &message);
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.errors.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors = self.errors.iter()
+            .map(|x| format!("{} {}", x.field, x.message))
+            .collect::<Vec<_>>();
+        f.write_str(&errors.connect(", "))
+    }
+}
+
+impl Error for ValidationErrors {
+    fn description(&self) -> &str {
+        "Invalid fields"
+    }
+}
+
+/// Implement with `validate!`.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// A string a rule can check, which an `Option` only is when it is `Some`.
+pub trait Text {
+    fn text(&self) -> Option<&str>;
+}
+
+impl Text for String {
+    fn text(&self) -> Option<&str> {
+        Some(&self[..])
+    }
+}
+
+impl Text for Option<String> {
+    fn text(&self) -> Option<&str> {
+        self.as_ref().map(|x| &x[..])
+    }
+}
+
+/// A number a rule can check, which an `Option` only is when it is `Some`.
+pub trait Number {
+    fn number(&self) -> Option<f64>;
+}
+
+macro_rules! number {
+    ($($ty:ty),*) => {
+        $(
+            impl Number for $ty {
+                fn number(&self) -> Option<f64> {
+                    Some(*self as f64)
+                }
+            }
+
+            impl Number for Option<$ty> {
+                fn number(&self) -> Option<f64> {
+                    self.map(|x| x as f64)
+                }
+            }
+        )*
+    };
+}
+
+number!(i32, i64, f32, f64);
+
+/// The rules of `validate!`, which return why a value breaks them.
+pub mod rules {
+    pub fn length(value: &str, min: Option<usize>, max: Option<usize>) -> Option<String> {
+        let len = value.chars().count();
+        match (min, max) {
+            (Some(min), Some(max)) if len < min || len > max =>
+                Some(format!("must be between {} and {} characters long", min, max)),
+            (Some(min), None) if len < min =>
+                Some(format!("must be at least {} characters long", min)),
+            (None, Some(max)) if len > max =>
+                Some(format!("must be at most {} characters long", max)),
+            _ => None,
+        }
+    }
+
+    pub fn range(value: f64, min: Option<f64>, max: Option<f64>) -> Option<String> {
+        match (min, max) {
+            (Some(min), Some(max)) if !(value >= min && value <= max) =>
+                Some(format!("must be between {} and {}", min, max)),
+            (Some(min), None) if !(value >= min) => Some(format!("must be at least {}", min)),
+            (None, Some(max)) if !(value <= max) => Some(format!("must be at most {}", max)),
+            _ => None,
+        }
+    }
+
+    /// Only checks the shape of an address, since whether it really exists
+    /// can only be known by sending something to it.
+    pub fn email(value: &str) -> Option<String> {
+        let mut parts = value.split('@');
+        let valid = match (parts.next(), parts.next(), parts.next()) {
+            (Some(local), Some(domain), None) =>
+                !local.is_empty() && !value.chars().any(|x| x.is_whitespace())
+                && domain.split('.').count() > 1 && domain.split('.').all(|x| !x.is_empty()),
+            _ => false,
+        };
+        if valid { None } else { Some("must be an email address".to_string()) }
+    }
+}
+
+/// Provides an extension method for `Request`s to decode a JSON body and
+/// validate it.
+pub trait GetValid {
+    /// Decodes the body as a `T`, with `400 Bad Request` if it is missing or
+    /// isn't a `T` and `422 Unprocessable Entity` listing the errors by field
+    /// if it is invalid.
+    fn get_valid<T: Decodable + Validate + Clone + Any>(&mut self) -> IronResult<T>;
+}
+
+impl<'a> GetValid for Request<'a> {
+    fn get_valid<T: Decodable + Validate + Clone + Any>(&mut self) -> IronResult<T> {
+        let value = try!(try!(self.get::<bodyparser::Struct<T>>().on_err(status::BadRequest))
+                         .ok_or(LibError::Cause("Missing body".to_string()))
+                         .on_err(status::BadRequest));
+        match value.validate() {
+            Ok(()) => Ok(value),
+            Err(errors) => {
+                let body = Json(errors.clone());
+                Err(IronError::new(errors, (status::UnprocessableEntity, body)))
+            },
+        }
+    }
+}
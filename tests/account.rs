@@ -0,0 +1,405 @@
+extern crate backlogrs;
+extern crate chrono;
+
+use chrono::{Duration, TimeZone, UTC};
+use backlogrs::account::{self, PASSWORD_CHANGED, PASSWORD_RESET};
+use backlogrs::clock::{Clock, FakeClock};
+use backlogrs::handlers::Config;
+use backlogrs::lockout::LockoutPolicy;
+use backlogrs::mail::MemoryMailer;
+use backlogrs::export::{Archive, ARCHIVE_FORMAT};
+use backlogrs::models::{AuthEvent, Deletion, EmailStatus, User, UserRows};
+use backlogrs::status;
+use backlogrs::storage::{self, Storage};
+use backlogrs::testing::*;
+
+fn server() -> (TestServer, FakeClock, MemoryMailer) {
+    let clock = FakeClock::new(UTC.ymd(2015, 6, 1).and_hms(12, 0, 0));
+    let mailer = MemoryMailer::new();
+    let config = Config::new().clock(clock.clone()).mailer(mailer.clone());
+    (TestServer::with_config(storage::open("memory:demo").unwrap(), config), clock, mailer)
+}
+
+fn email_status(server: &TestServer, id: i32, username: &str, password: &str) -> EmailStatus {
+    server.request(TestRequest::get(&format!("/api/user/{}/email", id))
+                   .basic_auth(username, password))
+        .assert_status(status::Ok)
+        .json::<EmailStatus>()
+}
+
+fn login(server: &TestServer, username: &str, password: &str) -> TestResponse {
+    server.request(TestRequest::get("/api/game").basic_auth(username, password))
+}
+
+fn request_reset(server: &TestServer, email: &str) -> TestResponse {
+    server.post("/api/password/reset", &format!(r#"{{"email": "{}"}}"#, email))
+}
+
+fn reset(server: &TestServer, token: &str, password: &str) -> TestResponse {
+    server.post("/api/password/reset/confirm",
+                &format!(r#"{{"token": "{}", "password": "{}"}}"#, token, password))
+}
+
+/// The token is on a line of its own, indented.
+fn token_in(body: &str) -> String {
+    body.lines().find(|x| x.starts_with("    ")).expect("no token in the mail").trim().to_string()
+}
+
+#[test]
+fn verifies_email() {
+    let (server, _, mailer) = server();
+    let user = signup(&server, "new");
+    let id = user.id.unwrap();
+    assert!(!email_status(&server, id, "new", "secret").verified);
+
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "new@example.com");
+    assert_eq!(sent[0].subject, "Verify your email");
+    assert!(sent[0].body.contains("Hi new,"));
+    assert!(sent[0].body.contains("valid until 2015-06-08 12:00 UTC"));
+
+    let token = token_in(&sent[0].body);
+    server.post("/api/verify", r#"{"token": "nope"}"#).assert_status(status::BadRequest);
+    let res = server.post("/api/verify", &format!(r#"{{"token": "{}"}}"#, token))
+        .assert_status(status::Ok)
+        .json::<EmailStatus>();
+    assert_eq!(res.email, "new@example.com");
+    assert!(res.verified);
+    assert!(email_status(&server, id, "new", "secret").verified);
+    // Tokens only work once
+    server.post("/api/verify", &format!(r#"{{"token": "{}"}}"#, token))
+        .assert_status(status::BadRequest);
+
+    // Only the user can see whether it is
+    server.get(&format!("/api/user/{}/email", id)).assert_status(status::Unauthorized);
+    server.request(TestRequest::get(&format!("/api/user/{}/email", id))
+                   .basic_auth("user", "hunter2"))
+        .assert_status(status::Forbidden);
+}
+
+#[test]
+fn resends_verification() {
+    let (server, clock, mailer) = server();
+    let id = signup(&server, "new").id.unwrap();
+    let path = format!("/api/user/{}/email/verify", id);
+
+    // Tokens expire after a week
+    clock.advance(Duration::days(7));
+    let token = token_in(&mailer.sent()[0].body);
+    server.post("/api/verify", &format!(r#"{{"token": "{}"}}"#, token))
+        .assert_status(status::BadRequest);
+
+    server.request(TestRequest::post(&path).basic_auth("new", "secret"))
+        .assert_status(status::Accepted);
+    let token = token_in(&mailer.sent()[1].body);
+    server.post("/api/verify", &format!(r#"{{"token": "{}"}}"#, token))
+        .assert_status(status::Ok);
+
+    // Nothing left to verify
+    server.request(TestRequest::post(&path).basic_auth("new", "secret"))
+        .assert_status(status::Ok);
+    assert_eq!(mailer.sent().len(), 2);
+    server.post(&path, "").assert_status(status::Unauthorized);
+}
+
+#[test]
+fn resets_password() {
+    let (server, clock, mailer) = server();
+    request_reset(&server, "USER@example.com").assert_status(status::Accepted);
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "user@example.com");
+    assert_eq!(sent[0].subject, "Reset your password");
+    let token = token_in(&sent[0].body);
+
+    clock.advance(Duration::minutes(5));
+    reset(&server, "nope", "hunter3").assert_status(status::BadRequest);
+    reset(&server, &token, "").assert_status(status::UnprocessableEntity);
+    let user = reset(&server, &token, "hunter3").assert_status(status::Ok).json::<User>();
+    assert_eq!(user.username, "user");
+    login(&server, "user", "hunter2").assert_status(status::Unauthorized);
+    login(&server, "user", "hunter3").assert_status(status::Ok);
+    // Reading the mail verified the email
+    assert!(email_status(&server, 1, "user", "hunter3").verified);
+    // Tokens only work once
+    reset(&server, &token, "hunter4").assert_status(status::BadRequest);
+
+    let events = server.request(TestRequest::get("/api/user/1/events")
+                                .basic_auth("user", "hunter3"))
+        .assert_status(status::Ok)
+        .json::<Vec<AuthEvent>>();
+    assert!(events.iter().any(|x| x.kind == PASSWORD_RESET
+                              && x.created_at == "2015-06-01T12:05:00.000000Z"));
+}
+
+#[test]
+fn reset_tokens() {
+    let (server, clock, mailer) = server();
+    request_reset(&server, "user@example.com").assert_status(status::Accepted);
+    // Tokens expire after an hour
+    clock.advance(Duration::hours(1));
+    request_reset(&server, "user@example.com").assert_status(status::Accepted);
+    request_reset(&server, "user@example.com").assert_status(status::Accepted);
+    let tokens = mailer.sent().iter().map(|x| token_in(&x.body)).collect::<Vec<_>>();
+
+    reset(&server, &tokens[0], "hunter3").assert_status(status::BadRequest);
+    // Using one uses up all
+    reset(&server, &tokens[1], "hunter3").assert_status(status::Ok);
+    reset(&server, &tokens[2], "hunter4").assert_status(status::BadRequest);
+    login(&server, "user", "hunter3").assert_status(status::Ok);
+}
+
+#[test]
+fn reset_unknown_email() {
+    let (server, _, mailer) = server();
+    // Tells as little as for a known email
+    request_reset(&server, "nobody@example.com").assert_status(status::Accepted);
+    assert!(mailer.sent().is_empty());
+    request_reset(&server, "not an email").assert_status(status::UnprocessableEntity);
+}
+
+#[test]
+fn reset_unlocks() {
+    let clock = FakeClock::new(UTC.ymd(2015, 6, 1).and_hms(12, 0, 0));
+    let mailer = MemoryMailer::new();
+    let config = Config::new().clock(clock.clone()).mailer(mailer.clone())
+        .lockout(LockoutPolicy { lock_after: 2, ..LockoutPolicy::new() });
+    let server = TestServer::with_config(storage::open("memory:demo").unwrap(), config);
+    login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    login(&server, "user", "hunter2").assert_status(status::Locked);
+
+    request_reset(&server, "user@example.com").assert_status(status::Accepted);
+    let token = token_in(&mailer.sent().last().unwrap().body);
+    reset(&server, &token, "hunter3").assert_status(status::Ok);
+    login(&server, "user", "hunter3").assert_status(status::Ok);
+}
+
+fn patch_user(server: &TestServer, id: i32, auth: (&str, &str), body: &str) -> TestResponse {
+    server.request(TestRequest::patch(&format!("/api/user/{}", id))
+                   .basic_auth(auth.0, auth.1).body(body))
+}
+
+fn delete_user(server: &TestServer, id: i32, username: &str, password: &str) -> TestResponse {
+    server.request(TestRequest::delete(&format!("/api/user/{}", id))
+                   .basic_auth(username, password))
+}
+
+#[test]
+fn updates_user() {
+    let (server, _, _) = server();
+    let id = signup(&server, "new").id.unwrap();
+    let user = patch_user(&server, id, ("new", "secret"), r#"{"username": "newer"}"#)
+        .assert_status(status::Ok)
+        .json::<User>();
+    assert_eq!((&user.username[..], &user.email[..]), ("newer", "new@example.com"));
+    login(&server, "new", "secret").assert_status(status::Unauthorized);
+    login(&server, "newer", "secret").assert_status(status::Ok);
+
+    // Taken by someone else
+    patch_user(&server, id, ("newer", "secret"), r#"{"username": "user"}"#)
+        .assert_status(status::Conflict);
+    patch_user(&server, id, ("newer", "secret"), r#"{"email": "USER@example.com"}"#)
+        .assert_status(status::Conflict);
+    patch_user(&server, id, ("newer", "secret"), r#"{"email": "not an email"}"#)
+        .assert_status(status::UnprocessableEntity);
+    // Keeping their own is fine
+    patch_user(&server, id, ("newer", "secret"), r#"{"username": "newer"}"#)
+        .assert_status(status::Ok);
+
+    // Only the user can
+    patch_user(&server, id, ("user", "hunter2"), r#"{"username": "mine"}"#)
+        .assert_status(status::Forbidden);
+    server.request(TestRequest::patch(&format!("/api/user/{}", id))
+                   .body(r#"{"username": "mine"}"#))
+        .assert_status(status::Unauthorized);
+}
+
+#[test]
+fn new_email_is_verified_again() {
+    let (server, _, mailer) = server();
+    let id = signup(&server, "new").id.unwrap();
+    let old_token = token_in(&mailer.sent()[0].body);
+
+    let user = patch_user(&server, id, ("new", "secret"), r#"{"email": "new@example.org"}"#)
+        .assert_status(status::Ok)
+        .json::<User>();
+    assert_eq!(user.email, "new@example.org");
+    let status = email_status(&server, id, "new", "secret");
+    assert_eq!(status.email, "new@example.org");
+    assert!(!status.verified);
+
+    // The token mailed to the old email doesn't verify the new one
+    server.post("/api/verify", &format!(r#"{{"token": "{}"}}"#, old_token))
+        .assert_status(status::BadRequest);
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[1].to, "new@example.org");
+    server.post("/api/verify", &format!(r#"{{"token": "{}"}}"#, token_in(&sent[1].body)))
+        .assert_status(status::Ok);
+    assert!(email_status(&server, id, "new", "secret").verified);
+}
+
+#[test]
+fn changes_password() {
+    let (server, clock, _) = server();
+    let change = |username: &str, password: &str, body: &str| {
+        server.request(TestRequest::post("/api/user/1/password")
+                       .basic_auth(username, password).body(body))
+    };
+    change("user", "hunter2", r#"{"current": "wrong", "password": "hunter3"}"#)
+        .assert_status(status::Forbidden);
+    change("user", "hunter2", r#"{"current": "hunter2", "password": ""}"#)
+        .assert_status(status::UnprocessableEntity);
+    clock.advance(Duration::minutes(5));
+    change("user", "hunter2", r#"{"current": "hunter2", "password": "hunter3"}"#)
+        .assert_status(status::NoContent);
+    login(&server, "user", "hunter2").assert_status(status::Unauthorized);
+    login(&server, "user", "hunter3").assert_status(status::Ok);
+
+    let events = server.request(TestRequest::get("/api/user/1/events")
+                                .basic_auth("user", "hunter3"))
+        .assert_status(status::Ok)
+        .json::<Vec<AuthEvent>>();
+    assert!(events.iter().any(|x| x.kind == PASSWORD_CHANGED
+                              && x.created_at == "2015-06-01T12:05:00.000000Z"));
+
+    // Only for the user themselves
+    signup(&server, "other");
+    change("other", "secret", r#"{"current": "hunter3", "password": "mine"}"#)
+        .assert_status(status::Forbidden);
+    login(&server, "user", "hunter3").assert_status(status::Ok);
+}
+
+#[test]
+fn deletes_user() {
+    let (server, _, _) = server();
+    let id = signup(&server, "gone").id.unwrap();
+    add_entry(&server, id, ("gone", "secret"), 0);
+    add_entry(&server, 1, ("user", "hunter2"), 0);
+
+    delete_user(&server, id, "user", "hunter2").assert_status(status::Forbidden);
+    // A failed attempt and the one after it are kept
+    login(&server, "gone", "wrong").assert_status(status::Unauthorized);
+    let removed = delete_user(&server, id, "gone", "secret")
+        .assert_status(status::Ok)
+        .json::<UserRows>();
+    assert_eq!(removed, UserRows {
+        logins: 1,
+        library: 1,
+        entries: 1,
+        account_tokens: 1,
+        auth_events: 2,
+        game_popularity: 1,
+        ..UserRows::default()
+    });
+    assert_eq!(server.db().user_rows(id).unwrap().total(), 0);
+    login(&server, "gone", "secret").assert_status(status::Unauthorized);
+    server.get(&format!("/api/user/{}", id)).assert_status(status::NoContent);
+    server.get(&format!("/api/user/{}/library", id)).assert_status(status::NoContent);
+    // Nobody else's library is touched
+    server.get("/api/user/1/library").assert_status(status::Ok);
+    // Their name and email are free again
+    signup(&server, "gone");
+}
+
+#[test]
+fn reports_games_played_together() {
+    let (server, _, _) = server();
+    let id = signup(&server, "gone").id.unwrap();
+    for &game in [0, 1, 2, 0].iter() {
+        add_entry(&server, id, ("gone", "secret"), game);
+    }
+    // A game in the library twice only counts once
+    let rows = server.db().user_rows(id).unwrap();
+    assert_eq!((rows.library, rows.game_popularity, rows.game_cooccurrence), (4, 3, 6));
+
+    let removed = delete_user(&server, id, "gone", "secret")
+        .assert_status(status::Ok)
+        .json::<UserRows>();
+    assert_eq!((removed.game_popularity, removed.game_cooccurrence), (3, 6));
+    assert_eq!(server.db().user_rows(id).unwrap().total(), 0);
+}
+
+#[test]
+fn deletes_user_after_grace() {
+    let clock = FakeClock::new(UTC.ymd(2015, 6, 1).and_hms(12, 0, 0));
+    let config = Config::new().clock(clock.clone()).mailer(MemoryMailer::new())
+        .deletion_grace(Duration::days(7));
+    let server = TestServer::with_config(storage::open("memory:demo").unwrap(), config);
+    let id = signup(&server, "gone").id.unwrap();
+    let deletion = format!("/api/user/{}/deletion", id);
+    server.request(TestRequest::get(&deletion).basic_auth("gone", "secret"))
+        .assert_status(status::NotFound);
+
+    let res = delete_user(&server, id, "gone", "secret")
+        .assert_status(status::Accepted)
+        .json::<Deletion>();
+    assert_eq!(res.delete_at, "2015-06-08T12:00:00.000000Z");
+    // Until then nothing changes, and they can change their mind
+    login(&server, "gone", "secret").assert_status(status::Ok);
+    server.request(TestRequest::get(&deletion).basic_auth("gone", "secret"))
+        .assert_status(status::Ok);
+    server.request(TestRequest::delete(&deletion).basic_auth("gone", "secret"))
+        .assert_status(status::NoContent);
+    server.request(TestRequest::delete(&deletion).basic_auth("gone", "secret"))
+        .assert_status(status::NotFound);
+    clock.advance(Duration::days(7));
+    assert!(account::purge_deletions(&**server.db(), clock.now()).unwrap().is_empty());
+
+    delete_user(&server, id, "gone", "secret").assert_status(status::Accepted);
+    clock.advance(Duration::days(6));
+    assert!(account::purge_deletions(&**server.db(), clock.now()).unwrap().is_empty());
+    clock.advance(Duration::days(1));
+    assert_eq!(account::purge_deletions(&**server.db(), clock.now()).unwrap(), [id]);
+    login(&server, "gone", "secret").assert_status(status::Unauthorized);
+}
+
+#[test]
+fn counts_user_rows() {
+    let (server, _, _) = server();
+    let id = signup(&server, "new").id.unwrap();
+    login(&server, "new", "wrong").assert_status(status::Unauthorized);
+    let rows = server.db().user_rows(id).unwrap();
+    assert_eq!((rows.logins, rows.account_tokens, rows.auth_events, rows.auth_failures),
+               (1, 1, 1, 1));
+    assert_eq!(rows.total(), 4);
+    assert_eq!(server.db().user_rows(1000).unwrap(), UserRows::default());
+}
+
+#[test]
+fn archives_user() {
+    let clock = FakeClock::new(UTC.ymd(2015, 6, 1).and_hms(12, 0, 0));
+    let config = Config::new().clock(clock.clone()).mailer(MemoryMailer::new())
+        .deletion_grace(Duration::days(7));
+    let server = TestServer::with_config(storage::open("memory:demo").unwrap(), config);
+    let id = signup(&server, "new").id.unwrap();
+    add_entry(&server, id, ("new", "secret"), 0);
+    login(&server, "new", "wrong").assert_status(status::Unauthorized);
+    delete_user(&server, id, "new", "secret").assert_status(status::Accepted);
+
+    let path = format!("/api/user/{}/archive", id);
+    let archive = server.request(TestRequest::get(&path).basic_auth("new", "secret"))
+        .assert_status(status::Ok)
+        .json::<Archive>();
+    assert_eq!(archive.format, ARCHIVE_FORMAT);
+    assert_eq!(archive.exported_at, "2015-06-01T12:00:00.000000Z");
+    assert_eq!(archive.user.username, "new");
+    assert_eq!(archive.email.email, "new@example.com");
+    assert!(!archive.email.verified);
+    assert_eq!(archive.library.len(), 1);
+    assert_eq!(archive.tokens.len(), 1);
+    assert_eq!(archive.tokens[0].purpose, account::VERIFY);
+    assert_eq!(archive.tokens[0].expires_at, "2015-06-08T12:00:00.000000Z");
+    assert_eq!(archive.auth_events.len(), 2);
+    assert_eq!(archive.delete_at, Some("2015-06-08T12:00:00.000000Z".to_string()));
+    // Not the tokens themselves though
+    let token = server.db().tokens(id).unwrap().pop().unwrap().token;
+    let res = server.request(TestRequest::get(&path).basic_auth("new", "secret"));
+    assert!(!res.body.contains(&token));
+
+    server.get(&path).assert_status(status::Unauthorized);
+    server.request(TestRequest::get(&path).basic_auth("user", "hunter2"))
+        .assert_status(status::Forbidden);
+}
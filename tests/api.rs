@@ -0,0 +1,571 @@
+//! Every route of the API through `TestServer`, which runs on a fresh
+//! in-memory storage unless `BACKLOGRS_TEST_DATABASE` says otherwise.
+extern crate backlogrs;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use backlogrs::export::{Backup, ExportEntry};
+use backlogrs::models::*;
+use backlogrs::status;
+use backlogrs::storage;
+use backlogrs::testing::*;
+use rustc_serialize::json::Json;
+
+fn create_user(server: &TestServer) -> User {
+    let name = unique("u");
+    server.post("/api/user", &format!(
+            r#"{{"id": null, "username": "{0}", "password": "secret", "email": "{0}@example.com"}}"#,
+            name))
+        .assert_status(status::Ok)
+        .json::<User>()
+}
+
+fn game_id(server: &TestServer, name: &str) -> i32 {
+    let games = server.get("/api/game").assert_status(status::Ok).json::<Vec<Game>>();
+    games.iter().find(|x| x.name == name).and_then(|x| x.id).unwrap()
+}
+
+/// Posts `body` to `path` as `user`.
+fn post_as(server: &TestServer, user: &User, path: &str, body: &str) -> TestResponse {
+    server.request(TestRequest::post(path).basic_auth(&user.username, "secret").body(body))
+}
+
+fn add_entry(server: &TestServer, user: &User, game_id: i32) -> Entry {
+    post_as(server, user, &format!("/api/user/{}/library", user.id.unwrap()), &format!(
+            r#"{{"id": null, "game_id": {}, "time_played": null, "last_update": null,
+                "status": null, "game": null}}"#, game_id))
+        .assert_status(status::Ok)
+        .json::<Entry>()
+}
+
+#[test]
+fn requires_api_prefix() {
+    let server = TestServer::new();
+    server.get("/game").assert_status(status::NotFound);
+}
+
+#[test]
+fn get_status() {
+    let server = TestServer::new();
+    let statuses = server.get("/api/status")
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "application/json")
+        .json::<Vec<Status>>();
+    assert_eq!(statuses, vec![Status::Frozen, Status::CurrentlyPlaying,
+                              Status::Dropped, Status::PlanToPlay]);
+}
+
+#[test]
+fn get_games() {
+    let server = TestServer::new();
+    let games = server.get("/api/game").assert_status(status::Ok).json::<Vec<Game>>();
+    assert!(games.iter().any(|x| x.name == "Skyrim"));
+    let mut names = games.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(games.iter().map(|x| x.name.clone()).collect::<Vec<_>>(), names);
+}
+
+#[test]
+fn get_game_by_id() {
+    let server = TestServer::new();
+    let id = game_id(&server, "Skyrim");
+    let game = server.get(&format!("/api/game/{}", id)).assert_status(status::Ok).json::<Game>();
+    assert_eq!(game.name, "Skyrim");
+
+    server.get("/api/game/-1").assert_status(status::NoContent);
+    server.get("/api/game/skyrim").assert_status(status::BadRequest);
+}
+
+#[test]
+fn get_similar_games() {
+    let server = TestServer::new();
+    let skyrim = game_id(&server, "Skyrim");
+    let witcher = game_id(&server, "The Witcher 3");
+    for _ in 0..2 {
+        let user = create_user(&server);
+        add_entry(&server, &user, skyrim);
+        add_entry(&server, &user, witcher);
+    }
+
+    let similar = server.get(&format!("/api/game/{}/similar", skyrim))
+        .assert_status(status::Ok)
+        .json::<Json>();
+    let similar = similar.as_array().unwrap();
+    assert!(similar.iter().any(|x| x.find("name").and_then(|x| x.as_string())
+                               == Some("The Witcher 3")));
+    assert!(similar.iter().all(|x| x.find("score").and_then(|x| x.as_f64()).unwrap() > 0.0));
+
+    // Games already in the library of the current user are left out
+    let user = create_user(&server);
+    add_entry(&server, &user, witcher);
+    let similar = server.request(TestRequest::get(&format!("/api/game/{}/similar", skyrim))
+                                 .basic_auth(&user.username, "secret"))
+        .assert_status(status::Ok)
+        .json::<Json>();
+    assert!(similar.as_array().unwrap().iter()
+            .all(|x| x.find("name").and_then(|x| x.as_string()) != Some("The Witcher 3")));
+}
+
+#[test]
+fn similar_games_forget_deleted_users() {
+    let server = TestServer::new();
+    let games = (0..3).map(|_| server.db().add_game(&Game {
+        id: None,
+        name: unique("g"),
+        description: String::new(),
+    }).unwrap()).collect::<Vec<_>>();
+    let assert_similar = |game: &Game, expected: Vec<(&Game, f64)>| {
+        let similar = server.get(&format!("/api/game/{}/similar", game.id.unwrap()))
+            .assert_status(status::Ok)
+            .json::<Json>();
+        let similar = similar.as_array().unwrap();
+        assert_eq!(similar.len(), expected.len());
+        for (found, &(game, score)) in similar.iter().zip(expected.iter()) {
+            assert_eq!(found.find("name").and_then(|x| x.as_string()), Some(&game.name[..]));
+            assert!((found.find("score").and_then(|x| x.as_f64()).unwrap() - score).abs() < 1e-6);
+        }
+    };
+    let first = create_user(&server);
+    add_entry(&server, &first, games[0].id.unwrap());
+    add_entry(&server, &first, games[1].id.unwrap());
+    let second = create_user(&server);
+    for game in games.iter() {
+        add_entry(&server, &second, game.id.unwrap());
+    }
+    assert_similar(&games[0], vec![(&games[1], 1.0), (&games[2], 1.0 / 2f64.sqrt())]);
+
+    server.request(TestRequest::delete(&format!("/api/user/{}", first.id.unwrap()))
+                   .basic_auth(&first.username, "secret"))
+        .assert_status(status::Ok);
+    // Only the second user is left, who plays all of them together
+    let mut left = vec![(&games[1], 1.0), (&games[2], 1.0)];
+    left.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    assert_similar(&games[0], left);
+
+    server.request(TestRequest::delete(&format!("/api/user/{}", second.id.unwrap()))
+                   .basic_auth(&second.username, "secret"))
+        .assert_status(status::Ok);
+    assert_similar(&games[0], vec![]);
+    assert_similar(&games[2], vec![]);
+}
+
+#[test]
+fn post_and_get_users() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    assert!(user.id.is_some());
+
+    let path = format!("/api/user/{}", user.id.unwrap());
+    let found = server.get(&path).assert_status(status::Ok).json::<PublicUser>();
+    assert_eq!(found.username, user.username);
+    // Only users themselves see their email
+    assert!(!server.get(&path).body.contains("email"));
+    let found = server.request(TestRequest::get(&path).basic_auth(&user.username, "secret"))
+        .assert_status(status::Ok)
+        .json::<User>();
+    assert_eq!(found.email, user.email);
+
+    let res = server.get("/api/user");
+    res.assert_status(status::Ok);
+    assert!(res.json::<Vec<PublicUser>>().iter().any(|x| x.id == user.id));
+    assert!(!res.body.contains("email"));
+
+    server.get("/api/user/-1").assert_status(status::NoContent);
+}
+
+#[test]
+fn post_user_taken() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    server.post("/api/user", &format!(
+            r#"{{"id": null, "username": "{}", "password": "x", "email": "{}"}}"#,
+            user.username, unique("e")))
+        .assert_status(status::InternalServerError);
+}
+
+#[test]
+fn post_user_invalid() {
+    let server = TestServer::new();
+    let errors = server.post("/api/user",
+            r#"{"id": null, "username": "", "password": "x", "email": "nope"}"#)
+        .assert_status(status::UnprocessableEntity)
+        .json::<Json>();
+    let fields = errors.find("errors").and_then(|x| x.as_array()).unwrap().iter()
+        .filter_map(|x| x.find("field").and_then(|x| x.as_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["username", "email"]);
+
+    server.post("/api/user", &format!(
+            r#"{{"id": null, "username": "{}", "password": "x", "email": "a@example.com"}}"#,
+            ::std::iter::repeat("u").take(21).collect::<String>()))
+        .assert_status(status::UnprocessableEntity);
+}
+
+#[test]
+fn invalid_credentials() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    server.request(TestRequest::get("/api/game").basic_auth(&user.username, "wrong"))
+        .assert_status(status::Unauthorized);
+    server.request(TestRequest::get("/api/game").basic_auth(&user.username, "secret"))
+        .assert_status(status::Ok);
+}
+
+#[test]
+fn post_entry() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    let entry = add_entry(&server, &user, game_id(&server, "Skyrim"));
+    assert_eq!(entry.status, Some(Status::PlanToPlay));
+    assert_eq!(entry.time_played, Some(0.0));
+    assert!(entry.last_update.is_some());
+
+    // Fields left out are kept when updating
+    let path = format!("/api/user/{}/library", user.id.unwrap());
+    post_as(&server, &user, &path, &format!(
+            r#"{{"id": {}, "game_id": null, "time_played": 3.5, "last_update": null,
+                "status": null, "game": null}}"#, entry.id.unwrap()))
+        .assert_status(status::Ok);
+    let updated = server.get(&format!("{}/{}", path, entry.id.unwrap()))
+        .assert_status(status::Ok)
+        .json::<Entry>();
+    assert_eq!(updated.time_played, Some(3.5));
+    assert_eq!(updated.status, Some(Status::PlanToPlay));
+
+    // Entries that aren't in the library can't be updated
+    let missing = r#"{"id": -1, "game_id": null, "time_played": 1, "last_update": null,
+                      "status": null, "game": null}"#;
+    post_as(&server, &user, &path, missing).assert_status(status::NoContent);
+    post_as(&server, &user, &format!("/api/v2/user/{}/library", user.id.unwrap()), missing)
+        .assert_status(status::NotFound);
+
+    let errors = post_as(&server, &user, &path,
+                         r#"{"id": null, "game_id": null, "time_played": -1,
+                             "last_update": null, "status": null, "game": null}"#)
+        .assert_status(status::UnprocessableEntity)
+        .json::<Json>();
+    let fields = errors.find("errors").and_then(|x| x.as_array()).unwrap().iter()
+        .filter_map(|x| x.find("field").and_then(|x| x.as_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["game_id", "time_played"]);
+    post_as(&server, &user, &path, "not json").assert_status(status::BadRequest);
+
+    // Only the user can change their library
+    let other = create_user(&server);
+    let entry = r#"{"id": null, "game_id": 1, "time_played": null, "last_update": null,
+                    "status": null, "game": null}"#;
+    server.post(&path, entry).assert_status(status::Unauthorized);
+    post_as(&server, &other, &path, entry).assert_status(status::Forbidden);
+}
+
+#[test]
+fn get_library() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    let path = format!("/api/user/{}/library", user.id.unwrap());
+    server.get(&path).assert_status(status::NoContent);
+
+    let entry = add_entry(&server, &user, game_id(&server, "Skyrim"));
+    let entries = server.get(&path).assert_status(status::Ok).json::<Vec<Entry>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, entry.id);
+    assert!(entries[0].game.is_none());
+
+    let entries = server.get(&format!("{}?expand=game", path))
+        .assert_status(status::Ok)
+        .json::<Vec<Entry>>();
+    assert_eq!(entries[0].game.as_ref().map(|x| &x.name[..]), Some("Skyrim"));
+
+    let library = server.get(&format!("{}?expand=user,game", path))
+        .assert_status(status::Ok)
+        .json::<Vec<Library>>();
+    assert_eq!(library[0].user.as_ref().map(|x| &x.username), Some(&user.username));
+    assert!(library[0].entry.as_ref().unwrap().game.is_some());
+
+    server.get(&format!("{}?expand=nothing", path)).assert_status(status::BadRequest);
+}
+
+#[test]
+fn get_entry() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    let other = create_user(&server);
+    let entry = add_entry(&server, &user, game_id(&server, "Skyrim"));
+
+    let path = format!("/api/user/{}/library/{}", user.id.unwrap(), entry.id.unwrap());
+    let found = server.get(&path).assert_status(status::Ok).json::<Entry>();
+    assert_eq!(found.game_id, entry.game_id);
+
+    let library = server.get(&format!("{}?expand=user", path))
+        .assert_status(status::Ok)
+        .json::<Library>();
+    assert_eq!(library.login_id, user.id.unwrap());
+
+    // Entries can only be found through the library they are in
+    server.get(&format!("/api/user/{}/library/{}", other.id.unwrap(), entry.id.unwrap()))
+        .assert_status(status::NoContent);
+}
+
+#[test]
+fn import_library() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    let path = format!("/api/user/{}/library/import", user.id.unwrap());
+    let csv = "game,status,time_played,tags\nSkyrim,Dropped,2,rpg\nNo Such Game,,,\n";
+
+    let report = server.request(TestRequest::post(&format!("{}?dry_run=true", path))
+                                .basic_auth(&user.username, "secret")
+                                .header("Content-Type", "text/csv").body(csv))
+        .assert_status(status::Ok)
+        .json::<Json>();
+    assert_eq!(report.find("matched").and_then(|x| x.as_u64()), Some(1));
+    assert_eq!(report.find("unmatched").and_then(|x| x.as_u64()), Some(1));
+
+    server.request(TestRequest::post(&path).basic_auth(&user.username, "secret")
+                   .header("Content-Type", "text/csv").body(csv))
+        .assert_status(status::UnprocessableEntity);
+    server.get(&format!("/api/user/{}/library", user.id.unwrap()))
+        .assert_status(status::NoContent);
+
+    let report = post_as(&server, &user, &format!("{}?format=json", path),
+                         r#"[{"game": "Skyrim", "status": "Dropped", "tags": ["rpg"]}]"#)
+        .assert_status(status::Ok)
+        .json::<Json>();
+    assert_eq!(report.find("committed").and_then(|x| x.as_boolean()), Some(true));
+    let entries = server.get(&format!("/api/user/{}/library", user.id.unwrap()))
+        .assert_status(status::Ok)
+        .json::<Vec<Entry>>();
+    assert_eq!(entries[0].status, Some(Status::Dropped));
+
+    post_as(&server, &user, &format!("{}?format=nope", path), "")
+        .assert_status(status::BadRequest);
+
+    // Only the user can import into their library
+    let json = r#"[{"game": "Skyrim"}]"#;
+    server.post(&format!("{}?format=json", path), json).assert_status(status::Unauthorized);
+    let other = create_user(&server);
+    post_as(&server, &other, &format!("{}?format=json", path), json)
+        .assert_status(status::Forbidden);
+}
+
+#[test]
+fn import_games_named_like_ids() {
+    let storage = storage::open("memory:").unwrap();
+    for name in ["Skyrim", "1942"].iter() {
+        storage.add_game(&Game {
+            id: None,
+            name: name.to_string(),
+            description: String::new(),
+        }).unwrap();
+    }
+    let server = TestServer::with_storage(storage);
+    let user = create_user(&server);
+    let skyrim = game_id(&server, "Skyrim");
+    let path = format!("/api/user/{}/library/import?dry_run=true", user.id.unwrap());
+    let game_ids = |report: Json| report.find("rows").and_then(|x| x.as_array()).unwrap().iter()
+        .map(|x| x.find("game_id").and_then(|x| x.as_i64()))
+        .collect::<Vec<_>>();
+
+    // In CSV a number is an id if there is a game with it, a name otherwise
+    let report = post_as(&server, &user, &format!("{}&format=csv", path),
+                         &format!("game\n{}\n1942\n", skyrim))
+        .assert_status(status::Ok)
+        .json::<Json>();
+    assert_eq!(game_ids(report), vec![Some(skyrim as i64), Some(game_id(&server, "1942") as i64)]);
+
+    // In JSON a string is always a name
+    let report = post_as(&server, &user, &format!("{}&format=json", path),
+                         &format!(r#"[{{"game": "{}"}}]"#, skyrim))
+        .assert_status(status::Ok)
+        .json::<Json>();
+    assert_eq!(game_ids(report), vec![None]);
+}
+
+#[test]
+fn export_library() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    add_entry(&server, &user, game_id(&server, "Skyrim"));
+    let path = format!("/api/user/{}/library/export", user.id.unwrap());
+
+    let entries = server.get(&path).assert_status(status::Ok).json::<Vec<ExportEntry>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].game.name, "Skyrim");
+
+    let csv = server.get(&format!("{}?format=csv", path))
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "text/csv")
+        .body.clone();
+    assert!(csv.starts_with("id,game,name,description,status,time_played,last_update,tags"));
+    assert!(csv.contains("Skyrim"));
+
+    server.get(&format!("{}?format=xml", path)).assert_status(status::BadRequest);
+    server.get("/api/user/-1/library/export?format=backup").assert_status(status::NotFound);
+}
+
+#[test]
+fn backup_round_trip() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    let entry = add_entry(&server, &user, game_id(&server, "Skyrim"));
+    let backup = server.get(&format!("/api/user/{}/library/export?format=backup",
+                                     user.id.unwrap()))
+        .assert_status(status::Ok)
+        .body.clone();
+    assert_eq!(Backup::from_json(&backup).unwrap().entries.len(), 1);
+
+    let other = create_user(&server);
+    let path = format!("/api/user/{}/library/import/backup", other.id.unwrap());
+    server.post(&path, &backup).assert_status(status::Unauthorized);
+    post_as(&server, &user, &path, &backup).assert_status(status::Forbidden);
+    let restored = post_as(&server, &other, &path, &backup)
+        .assert_status(status::Ok)
+        .json::<usize>();
+    assert_eq!(restored, 1);
+    // Restoring again would add every entry twice
+    post_as(&server, &other, &path, &backup).assert_status(status::Conflict);
+
+    let entries = server.get(&format!("/api/user/{}/library", other.id.unwrap()))
+        .assert_status(status::Ok)
+        .json::<Vec<Entry>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].game_id, entry.game_id);
+    assert_eq!(entries[0].last_update, entry.last_update);
+
+    post_as(&server, &other, &path, "{}").assert_status(status::BadRequest);
+}
+
+#[test]
+fn anonymous_backup_has_no_email() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    add_entry(&server, &user, game_id(&server, "Skyrim"));
+    let backup = server.get(&format!("/api/user/{}/library/export?format=backup",
+                                     user.id.unwrap()))
+        .assert_status(status::Ok)
+        .body.clone();
+    assert!(!backup.contains(&user.email));
+    assert_eq!(Backup::from_json(&backup).unwrap().user, user.public());
+}
+
+#[test]
+fn csv_export_to_other_instance() {
+    let server = TestServer::new();
+    let user = create_user(&server);
+    let skyrim = game_id(&server, "Skyrim");
+    add_entry(&server, &user, skyrim);
+    let csv = server.get(&format!("/api/user/{}/library/export?format=csv", user.id.unwrap()))
+        .assert_status(status::Ok)
+        .body.clone();
+
+    // Where the id of Skyrim is that of another game
+    let storage = storage::open("memory:").unwrap();
+    for i in 0..skyrim {
+        storage.add_game(&Game {
+            id: None,
+            name: format!("Game {}", i),
+            description: String::new(),
+        }).unwrap();
+    }
+    storage.add_game(&Game {
+        id: None,
+        name: "Skyrim".to_string(),
+        description: String::new(),
+    }).unwrap();
+    let other = TestServer::with_storage(storage);
+    let other_user = create_user(&other);
+    post_as(&other, &other_user,
+            &format!("/api/user/{}/library/import?format=csv", other_user.id.unwrap()), &csv)
+        .assert_status(status::Ok);
+
+    let entries = other.get(&format!("/api/user/{}/library?expand=game", other_user.id.unwrap()))
+        .assert_status(status::Ok)
+        .json::<Vec<Entry>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].game.as_ref().map(|x| &x.name[..]), Some("Skyrim"));
+    assert!(entries[0].game_id != Some(skyrim));
+}
+
+#[test]
+fn api_versions() {
+    let server = TestServer::new();
+    let res = server.get("/api/v2/status");
+    res.assert_status(status::Ok);
+    assert!(res.headers.get_raw("Deprecation").is_none());
+
+    // Without a version is the same as v1, which is deprecated
+    for path in ["/api/status", "/api/v1/status"].iter() {
+        server.get(path)
+            .assert_status(status::Ok)
+            .assert_header("Deprecation", "true")
+            .assert_header("Link", "</api/v2/status>; rel=\"successor-version\"");
+    }
+
+    // Only v2 says that what isn't there is not found
+    for path in ["/game/-1", "/user/-1", "/user/-1/library"].iter() {
+        server.get(&format!("/api{}", path)).assert_status(status::NoContent);
+        server.get(&format!("/api/v1{}", path)).assert_status(status::NoContent);
+        server.get(&format!("/api/v2{}", path)).assert_status(status::NotFound);
+    }
+    // whereas an empty library is there
+    let user = create_user(&server);
+    server.get(&format!("/api/v2/user/{}/library", user.id.unwrap()))
+        .assert_status(status::NoContent);
+
+    server.get("/api/v3/status").assert_status(status::NotFound);
+    server.get("/api").assert_status(status::NotFound);
+    server.get("/").assert_status(status::NotFound);
+}
+
+#[test]
+fn content_negotiation() {
+    let server = TestServer::new();
+    let accept = |path: &str, accept: &str| {
+        server.request(TestRequest::get(path).header("Accept", accept))
+    };
+
+    accept("/api/game", "application/msgpack")
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "application/msgpack")
+        .assert_header("Vary", "Accept");
+    accept("/api/game", "application/cbor")
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "application/cbor");
+    let csv = accept("/api/game", "text/csv").assert_status(status::Ok).body.clone();
+    // Columns are ordered by name
+    assert!(csv.starts_with("description,id,name\n"));
+
+    // CSV is only for lists
+    let id = game_id(&server, "Skyrim");
+    accept(&format!("/api/game/{}", id), "text/csv").assert_status(status::NotAcceptable);
+    accept(&format!("/api/game/{}", id), "text/csv, application/*;q=0.5")
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "application/json");
+
+    accept("/api/game", "text/html").assert_status(status::NotAcceptable);
+    accept("/api/game", "text/html, */*;q=0.1")
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "application/json");
+}
+
+#[test]
+fn body_limits() {
+    let server = TestServer::new();
+    let long = ::std::iter::repeat("x").take(5000).collect::<String>();
+    server.post("/api/user", &format!(
+            r#"{{"id": null, "username": "a", "password": "{}", "email": "a@example.com"}}"#,
+            long))
+        .assert_status(status::RequestEntityTooLarge);
+
+    server.post("/api/user", r#"{"id": null, "username": [[["a"]]], "password": "x",
+                                  "email": "a@example.com"}"#)
+        .assert_status(status::BadRequest);
+
+    // Only JSON imports are limited in depth, CSV may well have brackets
+    let user = create_user(&server);
+    let path = format!("/api/user/{}/library/import?dry_run=true", user.id.unwrap());
+    post_as(&server, &user, &format!("{}&format=json", &path),
+            r#"[{"game": "Skyrim", "tags": [[["rpg"]]]}]"#)
+        .assert_status(status::BadRequest);
+    post_as(&server, &user, &format!("{}&format=csv", &path), "game,tags\nSkyrim,[[[rpg]]]\n")
+        .assert_status(status::Ok);
+}
@@ -0,0 +1,50 @@
+extern crate backlogrs;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use backlogrs::health::*;
+use backlogrs::status;
+use backlogrs::storage::{self, SCHEMA_VERSION};
+use backlogrs::testing::*;
+use rustc_serialize::json::Json;
+
+#[test]
+fn health() {
+    let server = TestServer::new();
+    let res = server.get("/health");
+    res.assert_status(status::Ok).assert_header("Content-Type", "application/json");
+    let json = Json::from_str(&res.body).unwrap();
+    assert_eq!(json.find("status").and_then(|x| x.as_string()), Some("ok"));
+}
+
+#[test]
+fn ready() {
+    let server = TestServer::new();
+    let readiness = server.get("/ready").assert_status(status::Ok).json::<Readiness>();
+    assert!(readiness.ready);
+    let names = readiness.checks.iter().map(|x| &x.name[..]).collect::<Vec<_>>();
+    assert_eq!(names, ["database", "schema", "migrations"]);
+    assert!(readiness.checks.iter().all(|x| x.ok));
+    assert_eq!(readiness.checks[1].detail,
+               format!("version {0}, expected {0}", SCHEMA_VERSION));
+}
+
+#[test]
+fn readiness_of_storage() {
+    let storage = storage::open("memory:").unwrap();
+    assert!(readiness(&*storage).ready);
+}
+
+#[test]
+fn outside_of_api() {
+    let server = TestServer::new();
+    // No credentials are checked, not even bad ones
+    server.request(TestRequest::get("/health").basic_auth("user", "wrong"))
+        .assert_status(status::Ok);
+    server.request(TestRequest::get("/ready").basic_auth("user", "wrong"))
+        .assert_status(status::Ok);
+    server.request(TestRequest::get("/api/game").basic_auth("user", "wrong"))
+        .assert_status(status::Unauthorized);
+
+    server.get("/api/health").assert_status(status::NotFound);
+    server.post("/health", "").assert_status(status::NotFound);
+}
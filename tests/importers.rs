@@ -0,0 +1,102 @@
+extern crate backlogrs;
+
+use backlogrs::import::*;
+use backlogrs::models::Status;
+
+fn row(game: GameRef, status: Option<Status>, time_played: Option<f32>, tags: &[&str]) -> ParsedRow {
+    Ok(ImportRow {
+        game: game,
+        status: status,
+        time_played: time_played,
+        tags: tags.iter().map(|x| x.to_string()).collect(),
+    })
+}
+
+fn name(s: &str) -> GameRef {
+    GameRef::Name(s.to_string())
+}
+
+/// Both native formats describe the same library in the fixtures, so they
+/// should be read the same way but for how the first game is referred to.
+fn check_native(rows: Vec<ParsedRow>, first: GameRef) {
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], row(first, Some(Status::CurrentlyPlaying), Some(12.5), &["rpg", "loot"]));
+    assert_eq!(rows[1], row(name("Skyrim"), None, None, &[]));
+    assert_eq!(rows[2].as_ref().unwrap_err().len(), 2);
+    assert_eq!(rows[3], Err(vec!["game is required".to_string()]));
+}
+
+#[test]
+fn csv() {
+    check_native(CsvImporter.parse(include_str!("fixtures/library.csv")).unwrap(),
+                 GameRef::IdOrName(1, "1".to_string()));
+}
+
+#[test]
+fn csv_export() {
+    let rows = CsvImporter.parse("id,game,name,status\n7,1,Skyrim,Dropped\n8,Skyrim,,\n").unwrap();
+    assert_eq!(rows[0], row(GameRef::IdNamed(1, "Skyrim".to_string()), Some(Status::Dropped),
+                            None, &[]));
+    assert_eq!(rows[1], row(name("Skyrim"), None, None, &[]));
+}
+
+#[test]
+fn csv_without_game_column() {
+    assert!(CsvImporter.parse("status,time_played\nDropped,1\n").is_err());
+}
+
+#[test]
+fn json() {
+    check_native(JsonImporter.parse(include_str!("fixtures/library.json")).unwrap(), GameRef::Id(1));
+}
+
+#[test]
+fn json_string_is_a_name() {
+    let rows = JsonImporter.parse(r#"[{"game": "1942"}]"#).unwrap();
+    assert_eq!(rows, vec![row(name("1942"), None, None, &[])]);
+}
+
+#[test]
+fn json_not_an_array() {
+    assert!(JsonImporter.parse("{\"game\": 1}").is_err());
+}
+
+#[test]
+fn steam() {
+    let rows = SteamImporter.parse(include_str!("fixtures/steam.json")).unwrap();
+    assert_eq!(rows, vec![
+        row(name("The Witcher 3: Wild Hunt"), Some(Status::CurrentlyPlaying), Some(90.5), &[]),
+        row(name("The Elder Scrolls V: Skyrim"), Some(Status::Frozen), Some(1.5), &[]),
+        row(name("BioShock Infinite"), Some(Status::PlanToPlay), Some(0.0), &[]),
+    ]);
+}
+
+#[test]
+fn backloggery() {
+    let rows = BackloggeryImporter.parse(include_str!("fixtures/backloggery.csv")).unwrap();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], row(name("Diablo III"), Some(Status::Frozen), None, &["PC"]));
+    assert_eq!(rows[1], row(name("Darksiders"), Some(Status::CurrentlyPlaying), None, &["PS3"]));
+    assert_eq!(rows[2], row(name("Mass Effect 3"), Some(Status::Dropped), None, &["X360"]));
+    assert_eq!(rows[3], Err(vec!["Unknown Backloggery status: Abandoned".to_string()]));
+}
+
+#[test]
+fn howlongtobeat() {
+    let rows = HowLongToBeatImporter.parse(include_str!("fixtures/howlongtobeat.csv")).unwrap();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], row(name("Darksiders II"), Some(Status::Frozen), Some(21.5), &["PC"]));
+    assert_eq!(rows[1], row(name("The Witcher"), Some(Status::CurrentlyPlaying), Some(4.25), &["PC"]));
+    assert_eq!(rows[2], row(name("Skyrim"), Some(Status::PlanToPlay), None, &["PC"]));
+    assert!(rows[3].is_err());
+}
+
+#[test]
+fn format_names() {
+    for &(s, format) in [("csv", Format::Csv), ("json", Format::Json), ("steam", Format::Steam),
+                         ("backloggery", Format::Backloggery),
+                         ("howlongtobeat", Format::HowLongToBeat)].iter() {
+        assert_eq!(s.parse::<Format>().unwrap(), format);
+    }
+    assert!("goodreads".parse::<Format>().is_err());
+}
@@ -0,0 +1,17 @@
+extern crate backlogrs;
+
+use backlogrs::limit::json_depth;
+
+#[test]
+fn depth() {
+    assert_eq!(json_depth(""), 0);
+    assert_eq!(json_depth("1"), 0);
+    assert_eq!(json_depth(r#"{"a": 1}"#), 1);
+    assert_eq!(json_depth(r#"[{"a": [1]}, {}]"#), 3);
+}
+
+#[test]
+fn brackets_in_strings() {
+    assert_eq!(json_depth(r#"{"a": "[[[{{"}"#), 1);
+    assert_eq!(json_depth(r#"{"a": "\"[[["}"#), 1);
+}
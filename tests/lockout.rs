@@ -0,0 +1,163 @@
+extern crate backlogrs;
+extern crate chrono;
+
+use chrono::{Duration, TimeZone, UTC};
+use backlogrs::clock::FakeClock;
+use backlogrs::handlers::Config;
+use backlogrs::lockout::{LockoutPolicy, FAILURE, REFUSED, SUCCESS};
+use backlogrs::mail::MemoryMailer;
+use backlogrs::models::{AuthEvent, User};
+use backlogrs::status;
+use backlogrs::storage;
+use backlogrs::testing::*;
+
+fn server(policy: LockoutPolicy) -> (TestServer, FakeClock, MemoryMailer) {
+    let clock = FakeClock::new(UTC.ymd(2015, 6, 1).and_hms(12, 0, 0));
+    let mailer = MemoryMailer::new();
+    let config = Config::new().clock(clock.clone()).mailer(mailer.clone()).lockout(policy);
+    (TestServer::with_config(storage::open("memory:demo").unwrap(), config), clock, mailer)
+}
+
+fn login(server: &TestServer, username: &str, password: &str) -> TestResponse {
+    server.request(TestRequest::get("/api/game").basic_auth(username, password))
+}
+
+fn unlock(server: &TestServer, token: &str) -> TestResponse {
+    server.post("/api/unlock", &format!(r#"{{"token": "{}"}}"#, token))
+}
+
+/// The token is on a line of its own, indented.
+fn token_in(body: &str) -> String {
+    body.lines().find(|x| x.starts_with("    ")).expect("no token in the mail").trim().to_string()
+}
+
+#[test]
+fn backoff_doubles() {
+    let policy = LockoutPolicy::new();
+    assert_eq!(policy.backoff_after(0), Duration::zero());
+    assert_eq!(policy.backoff_after(1), Duration::seconds(1));
+    assert_eq!(policy.backoff_after(4), Duration::seconds(8));
+    assert_eq!(policy.backoff_after(1000), Duration::minutes(5));
+}
+
+#[test]
+fn backs_off() {
+    let (server, clock, _) = server(LockoutPolicy::new());
+    for _ in 0..4 {
+        login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    }
+    // Even with the right password
+    login(&server, "user", "hunter2")
+        .assert_status(status::TooManyRequests)
+        .assert_header("Retry-After", "1");
+
+    clock.advance(Duration::seconds(1));
+    login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    login(&server, "user", "hunter2")
+        .assert_status(status::TooManyRequests)
+        .assert_header("Retry-After", "2");
+
+    clock.advance(Duration::seconds(2));
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+    // Which forgets the failures
+    for _ in 0..3 {
+        login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    }
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+}
+
+#[test]
+fn backs_off_by_ip() {
+    let (server, clock, _) = server(LockoutPolicy { ip_free_failures: 2, ..LockoutPolicy::new() });
+    for name in ["nobody1", "nobody2", "nobody3"].iter() {
+        login(&server, name, "wrong").assert_status(status::Unauthorized);
+    }
+    login(&server, "user", "hunter2").assert_status(status::TooManyRequests);
+    clock.advance(Duration::seconds(1));
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+}
+
+#[test]
+fn forgets_failures() {
+    let (server, clock, _) = server(LockoutPolicy::new());
+    for _ in 0..3 {
+        login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    }
+    clock.advance(Duration::days(1));
+    for _ in 0..3 {
+        login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    }
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+}
+
+#[test]
+fn locks_and_unlocks() {
+    let (server, clock, mailer) = server(LockoutPolicy::new());
+    for _ in 0..10 {
+        login(&server, "user", "wrong").assert_status(status::Unauthorized);
+        // Past any back-off
+        clock.advance(Duration::minutes(5));
+    }
+    login(&server, "user", "hunter2")
+        .assert_status(status::Locked)
+        .assert_header("Retry-After", "3300");
+
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "user@example.com");
+    let token = token_in(&sent[0].body);
+    assert_eq!(token.len(), 64);
+
+    unlock(&server, "nope").assert_status(status::BadRequest);
+    let user = unlock(&server, &token).assert_status(status::Ok).json::<User>();
+    assert_eq!(user.username, "user");
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+    // Tokens only work once
+    unlock(&server, &token).assert_status(status::BadRequest);
+}
+
+#[test]
+fn locks_expire() {
+    let policy = LockoutPolicy { lock_after: 2, ..LockoutPolicy::new() };
+    let (server, clock, mailer) = server(policy);
+    for _ in 0..2 {
+        login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    }
+    login(&server, "user", "hunter2").assert_status(status::Locked);
+
+    clock.advance(Duration::hours(1));
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+    // So do their tokens
+    unlock(&server, &token_in(&mailer.sent()[0].body)).assert_status(status::BadRequest);
+}
+
+#[test]
+fn audit_events() {
+    let (server, clock, _) = server(LockoutPolicy { free_failures: 1, ..LockoutPolicy::new() });
+    login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    login(&server, "user", "wrong").assert_status(status::Unauthorized);
+    login(&server, "user", "hunter2").assert_status(status::TooManyRequests);
+    clock.advance(Duration::seconds(1));
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+    // Logging in without failures isn't kept
+    login(&server, "user", "hunter2").assert_status(status::Ok);
+
+    let events = server.request(TestRequest::get("/api/user/1/events")
+                                .basic_auth("user", "hunter2"))
+        .assert_status(status::Ok)
+        .json::<Vec<AuthEvent>>();
+    let kinds = events.iter().map(|x| &x.kind[..]).collect::<Vec<_>>();
+    assert_eq!(kinds, [SUCCESS, REFUSED, FAILURE, FAILURE]);
+    assert!(events.iter().all(|x| x.login_id == Some(1) && x.username == "user"
+                              && x.ip == "127.0.0.1"));
+    assert_eq!(events[0].created_at, "2015-06-01T12:00:01.000000Z");
+    assert_eq!(events[3].created_at, "2015-06-01T12:00:00.000000Z");
+
+    // Only for the user themselves
+    server.get("/api/user/1/events").assert_status(status::Unauthorized);
+    server.post("/api/user", r#"{"id": null, "username": "other", "password": "secret",
+                                 "email": "other@example.com"}"#)
+        .assert_status(status::Ok);
+    server.request(TestRequest::get("/api/user/1/events").basic_auth("other", "secret"))
+        .assert_status(status::Forbidden);
+}
@@ -0,0 +1,116 @@
+extern crate backlogrs;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use backlogrs::logging::*;
+use backlogrs::status;
+use backlogrs::storage;
+use backlogrs::testing::*;
+use rustc_serialize::json::Json;
+
+fn server(level: LogLevel) -> (TestServer, LogBuffer) {
+    let buffer = LogBuffer::new();
+    let logger = Logger::with_writer(level, Box::new(buffer.clone()));
+    (TestServer::with_logger(storage::open("memory:demo").unwrap(), logger), buffer)
+}
+
+fn field<'a>(line: &'a Json, name: &str) -> &'a Json {
+    line.find(name).expect(&format!("{} missing from {}", name, line))
+}
+
+#[test]
+fn log_levels() {
+    assert_eq!("WARN".parse::<LogLevel>().unwrap(), LogLevel::Warn);
+    assert_eq!("off".parse::<LogLevel>().unwrap(), LogLevel::Off);
+    assert!("loud".parse::<LogLevel>().is_err());
+
+    let logger = Logger::with_writer(LogLevel::Warn, Box::new(LogBuffer::new()));
+    assert!(logger.enabled(LogLevel::Error));
+    assert!(logger.enabled(LogLevel::Warn));
+    assert!(!logger.enabled(LogLevel::Info));
+    assert!(!logger.enabled(LogLevel::Off));
+}
+
+#[test]
+fn redacts_query() {
+    assert_eq!(redact_query("a=1&password=hunter2&reset_token=x&b"),
+               "a=1&password=[REDACTED]&reset_token=[REDACTED]&b");
+}
+
+#[test]
+fn redacts_path() {
+    assert_eq!(redact_path("/api/shared/abc"), "/api/shared/[REDACTED]");
+    assert_eq!(redact_path("/api/user/1/shares/abc"), "/api/user/1/shares/[REDACTED]");
+    assert_eq!(redact_path("/api/user/1/shares"), "/api/user/1/shares");
+    assert_eq!(redact_path("/api/game/1"), "/api/game/1");
+}
+
+#[test]
+fn access_log() {
+    let (server, buffer) = server(LogLevel::Info);
+    let res = server.request(TestRequest::get("/api/game?limit=1").basic_auth("user", "hunter2"));
+    res.assert_status(status::Ok);
+
+    let lines = buffer.lines();
+    assert_eq!(lines.len(), 1);
+    let line = &lines[0];
+    assert_eq!(field(line, "level").as_string(), Some("info"));
+    assert_eq!(field(line, "method").as_string(), Some("GET"));
+    assert_eq!(field(line, "path").as_string(), Some("/api/game"));
+    assert_eq!(field(line, "query").as_string(), Some("limit=1"));
+    assert_eq!(field(line, "status").as_u64(), Some(200));
+    assert!(field(line, "user_id").as_i64().is_some());
+    assert!(field(line, "latency_ms").as_f64().unwrap() >= 0.0);
+    assert!(field(line, "ts").is_string());
+    let id = field(line, "request_id").as_string().unwrap();
+    res.assert_header("X-Request-Id", id);
+    assert!(line.find("headers").is_none());
+}
+
+#[test]
+fn request_id() {
+    let (server, buffer) = server(LogLevel::Info);
+    server.request(TestRequest::get("/api/game").header("X-Request-Id", "proxy-42"))
+        .assert_header("X-Request-Id", "proxy-42");
+    assert_eq!(field(&buffer.lines()[0], "request_id").as_string(), Some("proxy-42"));
+
+    // Anything that could mess up the logs gets an id of our own
+    let res = server.request(TestRequest::get("/api/game").header("X-Request-Id", "a b\"c"));
+    let id = field(&buffer.lines()[1], "request_id").as_string().unwrap().to_string();
+    assert!(id != "a b\"c");
+    res.assert_header("X-Request-Id", &id);
+
+    let a = server.get("/api/game");
+    let b = server.get("/api/game");
+    assert!(a.headers.get_raw("X-Request-Id") != b.headers.get_raw("X-Request-Id"));
+}
+
+#[test]
+fn levels_by_status() {
+    let (server, buffer) = server(LogLevel::Warn);
+    server.get("/api/game").assert_status(status::Ok);
+    server.get("/nope").assert_status(status::NotFound);
+
+    let lines = buffer.lines();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(field(&lines[0], "level").as_string(), Some("warn"));
+    assert_eq!(field(&lines[0], "path").as_string(), Some("/nope"));
+    assert_eq!(field(&lines[0], "status").as_u64(), Some(404));
+}
+
+#[test]
+fn redacts_credentials() {
+    let (server, buffer) = server(LogLevel::Debug);
+    server.request(TestRequest::post("/api/user")
+                   .basic_auth("user", "hunter2")
+                   .header("Cookie", "session=abc")
+                   .body(r#"{"id": null, "username": "", "password": "hunter3", "email": "x"}"#));
+
+    let lines = buffer.lines();
+    let line = &lines[0];
+    let headers = field(line, "headers");
+    assert_eq!(field(headers, "Authorization").as_string(), Some("[REDACTED]"));
+    assert_eq!(field(headers, "Cookie").as_string(), Some("[REDACTED]"));
+    assert_eq!(field(headers, "Host").as_string(), Some("127.0.0.1:3000"));
+    let logged = line.to_string();
+    assert!(!logged.contains("hunter2") && !logged.contains("hunter3") && !logged.contains("abc"));
+}
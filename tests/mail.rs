@@ -0,0 +1,140 @@
+extern crate backlogrs;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use backlogrs::logging::Logger;
+use backlogrs::mail::{self, templates, FileMailer, Mailer, Message, SmtpMailer, Template};
+use backlogrs::testing::unique;
+
+fn message() -> Message {
+    Message {
+        to: "user@example.com".to_string(),
+        subject: "Hi".to_string(),
+        body: "Hello\n.\nBye\n".to_string(),
+    }
+}
+
+#[test]
+fn templates_render() {
+    let vars = [("username", "user"), ("token", "abc"), ("until", "2015-06-01 12:00 UTC")];
+    for source in [templates::UNLOCK, templates::VERIFY, templates::RESET].iter() {
+        let message = Template::parse(source).unwrap().render("user@example.com", &vars).unwrap();
+        assert_eq!(message.to, "user@example.com");
+        assert!(!message.subject.is_empty());
+        assert!(message.body.starts_with("Hi user,\n"));
+        assert!(message.body.lines().any(|x| x == "    abc"));
+        assert!(!message.body.contains("{{"));
+    }
+}
+
+#[test]
+fn template_errors() {
+    assert!(Template::parse("Hi {{username}}").is_err());
+    let template = Template::parse("Subject: Hi {{username}}\n\n{{token}}").unwrap();
+    let message = template.render("a@example.com", &[("username", "a"), ("token", "b")]).unwrap();
+    assert_eq!((&message.subject[..], &message.body[..]), ("Hi a", "b"));
+    assert!(template.render("a@example.com", &[("username", "a")]).is_err());
+    assert!(Template::parse("Subject: Hi {{username").unwrap().render("a", &[]).is_err());
+}
+
+#[test]
+fn rfc5322() {
+    let mut message = message();
+    message.subject = "Hi\r\nBcc: everyone@example.com".to_string();
+    let raw = message.to_rfc5322("backlogrs@example.com");
+    assert!(raw.starts_with("From: backlogrs@example.com\r\nTo: user@example.com\r\n\
+                             Subject: Hi Bcc: everyone@example.com\r\n"));
+    assert!(raw.ends_with("\r\n\r\nHello\r\n.\r\nBye\r\n"));
+}
+
+#[test]
+fn open() {
+    assert!(mail::open("log", "a@example.com", Logger::off()).is_ok());
+    assert!(mail::open("smtp://localhost", "a@example.com", Logger::off()).is_ok());
+    assert!(mail::open("carrier-pigeon://", "a@example.com", Logger::off()).is_err());
+}
+
+#[test]
+fn file_mailer() {
+    let dir = env::temp_dir().join(unique("backlogrs-mail"));
+    let mailer = FileMailer::new(dir.to_str().unwrap(), "backlogrs@example.com").unwrap();
+    mailer.send(&message()).unwrap();
+    mailer.send(&message()).unwrap();
+
+    let files = fs::read_dir(&dir).unwrap().map(|x| x.unwrap().path()).collect::<Vec<_>>();
+    assert_eq!(files.len(), 2);
+    let mut raw = String::new();
+    File::open(&files[0]).unwrap().read_to_string(&mut raw).unwrap();
+    assert!(raw.contains("To: user@example.com\r\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Answers a single SMTP session, returning every line it was sent.
+fn fake_smtp_server(listener: TcpListener) -> Vec<String> {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut lines = vec![];
+    let mut data = false;
+    stream.write_all(b"220 localhost ready\r\n").unwrap();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap() == 0 {
+            return lines;
+        }
+        let line = line.trim_right_matches("\r\n").to_string();
+        let reply: &[u8] = if data {
+            if line == "." {
+                data = false;
+                b"250 queued\r\n"
+            } else {
+                b""
+            }
+        } else if line.starts_with("EHLO") {
+            b"250-localhost\r\n250 SIZE 1000000\r\n"
+        } else if line == "DATA" {
+            data = true;
+            b"354 go ahead\r\n"
+        } else if line == "QUIT" {
+            b"221 bye\r\n"
+        } else {
+            b"250 ok\r\n"
+        };
+        stream.write_all(reply).unwrap();
+        lines.push(line);
+    }
+}
+
+#[test]
+fn smtp_mailer() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || fake_smtp_server(listener));
+
+    let mailer = SmtpMailer::new(&addr.to_string(), "backlogrs@example.com");
+    mailer.send(&message()).unwrap();
+    let lines = server.join().unwrap();
+
+    assert_eq!(&lines[..4], ["EHLO localhost", "MAIL FROM:<backlogrs@example.com>",
+                             "RCPT TO:<user@example.com>", "DATA"]);
+    assert!(lines.iter().any(|x| x == "Subject: Hi"));
+    // The lone dot of the body is escaped
+    let body = lines.iter().position(|x| x == "Hello").unwrap();
+    assert_eq!(&lines[body..], ["Hello", "..", "Bye", ".", "QUIT"]);
+}
+
+#[test]
+fn smtp_mailer_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"554 go away\r\n").unwrap();
+    });
+
+    let mailer = SmtpMailer::new(&addr.to_string(), "backlogrs@example.com");
+    assert!(mailer.send(&message()).is_err());
+    server.join().unwrap();
+}
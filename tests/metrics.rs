@@ -0,0 +1,84 @@
+extern crate backlogrs;
+
+use backlogrs::status;
+use backlogrs::testing::*;
+
+fn metrics(server: &TestServer) -> String {
+    server.get("/metrics")
+        .assert_status(status::Ok)
+        .assert_header("Content-Type", "text/plain; version=0.0.4")
+        .body.clone()
+}
+
+fn has_line(metrics: &str, line: &str) -> bool {
+    metrics.lines().any(|x| x == line)
+}
+
+#[test]
+fn request_counts() {
+    let server = TestServer::new();
+    server.get("/api/game").assert_status(status::Ok);
+    server.get("/api/v2/game").assert_status(status::Ok);
+    server.get("/api/game/1000000").assert_status(status::NoContent);
+    server.get("/api/nope").assert_status(status::NotFound);
+
+    let metrics = metrics(&server);
+    assert!(has_line(&metrics,
+        r#"backlogrs_http_requests_total{method="GET",route="/game",status="200"} 2"#), metrics);
+    assert!(has_line(&metrics,
+        r#"backlogrs_http_requests_total{method="GET",route="/game/:id",status="204"} 1"#),
+        metrics);
+    // Paths that aren't routes don't get a label each
+    assert!(has_line(&metrics,
+        r#"backlogrs_http_requests_total{method="GET",route="unmatched",status="404"} 1"#),
+        metrics);
+    assert!(!metrics.contains("nope"));
+}
+
+#[test]
+fn latency_histogram() {
+    let server = TestServer::new();
+    server.get("/api/status").assert_status(status::Ok);
+    server.get("/api/status").assert_status(status::Ok);
+
+    let metrics = metrics(&server);
+    let labels = r#"method="GET",route="/status""#;
+    assert!(has_line(&metrics, &format!(
+        "backlogrs_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2", labels)), metrics);
+    assert!(has_line(&metrics, &format!(
+        "backlogrs_http_request_duration_seconds_count{{{}}} 2", labels)), metrics);
+    // Buckets are cumulative
+    let prefix = format!("backlogrs_http_request_duration_seconds_bucket{{{}", labels);
+    let buckets = metrics.lines()
+        .filter(|x| x.starts_with(&prefix))
+        .map(|x| x.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(buckets.len(), 12);
+    assert!(buckets.windows(2).all(|x| x[0] <= x[1]));
+}
+
+#[test]
+fn errors_by_kind() {
+    let server = TestServer::new();
+    server.get("/nope").assert_status(status::NotFound);
+    server.post("/api/user", r#"{"id": null, "username": "", "password": "x", "email": "x"}"#)
+        .assert_status(status::UnprocessableEntity);
+
+    let metrics = metrics(&server);
+    assert!(has_line(&metrics, r#"backlogrs_errors_total{kind="cause"} 1"#), metrics);
+    assert!(has_line(&metrics, r#"backlogrs_errors_total{kind="validation"} 1"#), metrics);
+}
+
+#[test]
+fn business_gauges() {
+    let server = TestServer::with_storage(backlogrs::storage::open("memory:demo").unwrap());
+    let metrics = metrics(&server);
+    assert!(has_line(&metrics, "backlogrs_users 1"), metrics);
+    assert!(has_line(&metrics, "backlogrs_games 9"), metrics);
+    for status in ["Frozen", "CurrentlyPlaying", "Dropped", "PlanToPlay"].iter() {
+        assert!(has_line(&metrics, &format!("backlogrs_entries{{status=\"{}\"}} 0", status)),
+                metrics);
+    }
+    // Memory has no connection pool
+    assert!(!metrics.contains("backlogrs_db_pool"));
+}
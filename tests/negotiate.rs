@@ -0,0 +1,34 @@
+extern crate backlogrs;
+
+use backlogrs::negotiate::*;
+use backlogrs::negotiate::MediaType::*;
+
+#[test]
+fn anything_without_accept() {
+    assert_eq!(acceptable(None), vec![Json, MsgPack, Cbor, Csv]);
+    assert_eq!(acceptable(Some("")), vec![Json, MsgPack, Cbor, Csv]);
+    assert_eq!(acceptable(Some("*/*")), vec![Json, MsgPack, Cbor, Csv]);
+}
+
+#[test]
+fn by_quality_then_order() {
+    assert_eq!(acceptable(Some("application/cbor;q=0.5, text/csv, application/msgpack")),
+               vec![Csv, MsgPack, Cbor]);
+    assert_eq!(acceptable(Some("text/*;q=0.2, application/*")),
+               vec![Json, MsgPack, Cbor, Csv]);
+}
+
+#[test]
+fn excluded_and_unknown() {
+    assert_eq!(acceptable(Some("application/json;q=0, application/*")),
+               vec![Json, MsgPack, Cbor]);
+    assert_eq!(acceptable(Some("text/html, image/png")), vec![]);
+    assert_eq!(acceptable(Some("application/x-msgpack")), vec![MsgPack]);
+}
+
+#[test]
+fn csv_only_for_lists() {
+    assert!(Csv.encode(&vec![1, 2]).unwrap().is_some());
+    assert!(Csv.encode(&1).unwrap().is_none());
+    assert!(Json.encode(&1).unwrap().is_some());
+}
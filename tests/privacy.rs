@@ -0,0 +1,305 @@
+extern crate backlogrs;
+
+use backlogrs::export::Archive;
+use backlogrs::models::{Entry, Privacy, Profile, PublicUser, ShareLink, Status, UserRows,
+                        Visibility};
+use backlogrs::privacy::SharedLibrary;
+use backlogrs::status;
+use backlogrs::storage;
+use backlogrs::testing::*;
+
+fn server() -> TestServer {
+    TestServer::with_storage(storage::open("memory:demo").unwrap())
+}
+
+fn set_library(server: &TestServer, id: i32, username: &str, visibility: &str) -> TestResponse {
+    server.request(TestRequest::new("PUT", &format!("/api/user/{}/privacy", id))
+                   .basic_auth(username, "secret")
+                   .body(&format!(r#"{{"library": "{}"}}"#, visibility)))
+}
+
+fn add_friend(server: &TestServer, id: i32, username: &str, friend: &str) -> TestResponse {
+    server.request(TestRequest::post(&format!("/api/user/{}/friends", id))
+                   .basic_auth(username, "secret")
+                   .body(&format!(r#"{{"username": "{}"}}"#, friend)))
+}
+
+fn import(server: &TestServer, id: i32, username: &str, entries: &str) {
+    server.request(TestRequest::post(&format!("/api/user/{}/library/import?format=json", id))
+                   .basic_auth(username, "secret")
+                   .body(entries))
+        .assert_status(status::Ok);
+}
+
+fn share(server: &TestServer, id: i32, username: &str, body: &str) -> TestResponse {
+    server.request(TestRequest::post(&format!("/api/user/{}/shares", id))
+                   .basic_auth(username, "secret")
+                   .body(body))
+}
+
+/// The names of the games shared by the link with `token`.
+fn shared_games(server: &TestServer, token: &str) -> Vec<String> {
+    server.get(&format!("/api/shared/{}", token))
+        .assert_status(status::Ok)
+        .json::<SharedLibrary>()
+        .entries.into_iter().map(|x| x.game.name).collect()
+}
+
+/// Everything showing the library of `id`, as `username` if given.
+fn library_statuses(server: &TestServer, id: i32, entry: &Entry, username: Option<&str>)
+    -> Vec<status::Status>
+{
+    ["library", &format!("library/{}", entry.id.unwrap())[..], "library/export"].iter().map(|x| {
+        let req = TestRequest::get(&format!("/api/user/{}/{}", id, x));
+        let req = match username {
+            Some(username) => req.basic_auth(username, "secret"),
+            None => req,
+        };
+        server.request(req).status
+    }).collect()
+}
+
+#[test]
+fn libraries_are_public_by_default() {
+    let server = server();
+    let owner = signup(&server, "owner");
+    let id = owner.id.unwrap();
+    let entry = add_entry(&server, id, ("owner", "secret"), 0);
+    let res = server.request(TestRequest::get(&format!("/api/user/{}/privacy", id))
+                             .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<Privacy>();
+    assert_eq!(res.library, Visibility::Public);
+    assert_eq!(library_statuses(&server, id, &entry, None), vec![status::Ok; 3]);
+
+    // Without the email of the owner
+    let res = server.get(&format!("/api/user/{}/library?expand=user", id));
+    res.assert_status(status::Ok);
+    assert!(!res.body.contains("owner@example.com"));
+}
+
+#[test]
+fn private_library() {
+    let server = server();
+    let id = signup(&server, "owner").id.unwrap();
+    signup(&server, "other");
+    let entry = add_entry(&server, id, ("owner", "secret"), 0);
+    set_library(&server, id, "owner", "Private").assert_status(status::Ok);
+
+    assert_eq!(library_statuses(&server, id, &entry, None), vec![status::Unauthorized; 3]);
+    assert_eq!(library_statuses(&server, id, &entry, Some("other")), vec![status::Forbidden; 3]);
+    assert_eq!(library_statuses(&server, id, &entry, Some("owner")), vec![status::Ok; 3]);
+    // Even for friends
+    add_friend(&server, id, "owner", "other").assert_status(status::Ok);
+    assert_eq!(library_statuses(&server, id, &entry, Some("other")), vec![status::Forbidden; 3]);
+
+    set_library(&server, id, "owner", "Public").assert_status(status::Ok);
+    assert_eq!(library_statuses(&server, id, &entry, None), vec![status::Ok; 3]);
+}
+
+#[test]
+fn friends_library() {
+    let server = server();
+    let id = signup(&server, "owner").id.unwrap();
+    let friend = signup(&server, "friend");
+    signup(&server, "other");
+    let entry = add_entry(&server, id, ("owner", "secret"), 0);
+    set_library(&server, id, "owner", "Friends").assert_status(status::Ok);
+
+    let added = add_friend(&server, id, "owner", "friend")
+        .assert_status(status::Ok)
+        .json::<PublicUser>();
+    assert_eq!(added, friend.public());
+    // Adding twice changes nothing
+    add_friend(&server, id, "owner", "friend").assert_status(status::Ok);
+    add_friend(&server, id, "owner", "nobody").assert_status(status::NotFound);
+    add_friend(&server, id, "owner", "owner").assert_status(status::BadRequest);
+    let friends = server.request(TestRequest::get(&format!("/api/user/{}/friends", id))
+                                 .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<Vec<PublicUser>>();
+    assert_eq!(friends, [friend.public()]);
+
+    assert_eq!(library_statuses(&server, id, &entry, Some("friend")), vec![status::Ok; 3]);
+    assert_eq!(library_statuses(&server, id, &entry, Some("other")), vec![status::Forbidden; 3]);
+    assert_eq!(library_statuses(&server, id, &entry, None), vec![status::Unauthorized; 3]);
+    // Friends only go one way
+    set_library(&server, friend.id.unwrap(), "friend", "Friends").assert_status(status::Ok);
+    let friend_entry = add_entry(&server, friend.id.unwrap(), ("friend", "secret"), 0);
+    assert_eq!(library_statuses(&server, friend.id.unwrap(), &friend_entry, Some("owner")),
+               vec![status::Forbidden; 3]);
+
+    let path = format!("/api/user/{}/friends/{}", id, friend.id.unwrap());
+    server.request(TestRequest::delete(&path).basic_auth("other", "secret"))
+        .assert_status(status::Forbidden);
+    server.request(TestRequest::delete(&path).basic_auth("owner", "secret"))
+        .assert_status(status::NoContent);
+    server.request(TestRequest::delete(&path).basic_auth("owner", "secret"))
+        .assert_status(status::NotFound);
+    assert_eq!(library_statuses(&server, id, &entry, Some("friend")), vec![status::Forbidden; 3]);
+}
+
+#[test]
+fn privacy_settings_are_private() {
+    let server = server();
+    let id = signup(&server, "owner").id.unwrap();
+    signup(&server, "other");
+    set_library(&server, id, "other", "Public").assert_status(status::Forbidden);
+    set_library(&server, id, "owner", "Hidden").assert_status(status::BadRequest);
+    server.get(&format!("/api/user/{}/privacy", id)).assert_status(status::Unauthorized);
+    server.get(&format!("/api/user/{}/friends", id)).assert_status(status::Unauthorized);
+    add_friend(&server, id, "other", "other").assert_status(status::Forbidden);
+}
+
+#[test]
+fn archived_and_deleted() {
+    let server = server();
+    let id = signup(&server, "owner").id.unwrap();
+    let friend = signup(&server, "friend");
+    set_library(&server, id, "owner", "Friends").assert_status(status::Ok);
+    add_friend(&server, id, "owner", "friend").assert_status(status::Ok);
+    add_friend(&server, friend.id.unwrap(), "friend", "owner").assert_status(status::Ok);
+
+    let archive = server.request(TestRequest::get(&format!("/api/user/{}/archive", id))
+                                 .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<Archive>();
+    assert_eq!(archive.library_visibility, Visibility::Friends);
+    assert_eq!(archive.friends, [friend.public()]);
+
+    let removed = server.request(TestRequest::delete(&format!("/api/user/{}", id))
+                                 .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<UserRows>();
+    assert_eq!((removed.privacy_settings, removed.friends), (1, 2));
+    let friends = server.request(TestRequest::get(&format!("/api/user/{}/friends",
+                                                           friend.id.unwrap()))
+                                 .basic_auth("friend", "secret"))
+        .assert_status(status::Ok)
+        .json::<Vec<PublicUser>>();
+    assert!(friends.is_empty());
+}
+
+#[test]
+fn profile_by_username() {
+    let server = server();
+    let owner = signup(&server, "owner");
+    let id = owner.id.unwrap();
+    import(&server, id, "owner", r#"[{"game": "Skyrim", "status": "Dropped"}]"#);
+
+    let res = server.get("/api/profile/owner");
+    res.assert_status(status::Ok);
+    assert!(!res.body.contains("owner@example.com"));
+    let profile = res.json::<Profile>();
+    assert_eq!(profile.user, owner.public());
+    let library = profile.library.unwrap();
+    assert_eq!(library.len(), 1);
+    assert_eq!(library[0].status, Some(Status::Dropped));
+    assert_eq!(library[0].game.as_ref().map(|x| &x.name[..]), Some("Skyrim"));
+
+    // Only with the library for those who can see it
+    set_library(&server, id, "owner", "Private").assert_status(status::Ok);
+    let profile = server.get("/api/profile/owner").assert_status(status::Ok).json::<Profile>();
+    assert_eq!(profile.user, owner.public());
+    assert!(profile.library.is_none());
+    let profile = server.request(TestRequest::get("/api/profile/owner")
+                                 .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<Profile>();
+    assert_eq!(profile.library.map(|x| x.len()), Some(1));
+
+    server.get("/api/profile/nobody").assert_status(status::NotFound);
+}
+
+#[test]
+fn share_links() {
+    let server = server();
+    let id = signup(&server, "owner").id.unwrap();
+    import(&server, id, "owner", r#"[{"game": "Skyrim", "status": "Dropped", "tags": ["rpg"]},
+                                     {"game": "The Witcher", "status": "PlanToPlay",
+                                      "tags": ["rpg"]},
+                                     {"game": "Diablo III", "status": "PlanToPlay"}]"#);
+    // Links work whoever can see the library otherwise
+    set_library(&server, id, "owner", "Private").assert_status(status::Ok);
+
+    let everything = share(&server, id, "owner", r#"{"status": null, "tag": null}"#)
+        .assert_status(status::Ok)
+        .json::<ShareLink>();
+    assert_eq!(everything.token.len(), 64);
+    assert_eq!((everything.login_id, everything.status, everything.tag), (id, None, None));
+    assert_eq!(shared_games(&server, &everything.token),
+               ["Skyrim", "The Witcher", "Diablo III"]);
+
+    let by_status = share(&server, id, "owner", r#"{"status": "PlanToPlay", "tag": null}"#)
+        .assert_status(status::Ok)
+        .json::<ShareLink>();
+    assert_eq!(shared_games(&server, &by_status.token), ["The Witcher", "Diablo III"]);
+    let by_tag = share(&server, id, "owner", r#"{"status": null, "tag": "rpg"}"#)
+        .assert_status(status::Ok)
+        .json::<ShareLink>();
+    assert_eq!(shared_games(&server, &by_tag.token), ["Skyrim", "The Witcher"]);
+    let both = share(&server, id, "owner", r#"{"status": "PlanToPlay", "tag": "rpg"}"#)
+        .assert_status(status::Ok)
+        .json::<ShareLink>();
+    let shared = server.get(&format!("/api/shared/{}", both.token))
+        .assert_status(status::Ok)
+        .json::<SharedLibrary>();
+    assert_eq!(shared.user.username, "owner");
+    assert_eq!(shared.status, Some(Status::PlanToPlay));
+    assert_eq!(shared.tag, Some("rpg".to_string()));
+    assert_eq!(shared.entries.len(), 1);
+    assert_eq!(shared.entries[0].tags, ["rpg"]);
+
+    let links = server.request(TestRequest::get(&format!("/api/user/{}/shares", id))
+                               .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<Vec<ShareLink>>();
+    assert_eq!(links.len(), 4);
+    assert!(links.contains(&by_tag));
+
+    let path = format!("/api/user/{}/shares/{}", id, by_tag.token);
+    server.request(TestRequest::delete(&path).basic_auth("owner", "secret"))
+        .assert_status(status::NoContent);
+    server.request(TestRequest::delete(&path).basic_auth("owner", "secret"))
+        .assert_status(status::NotFound);
+    server.get(&format!("/api/shared/{}", by_tag.token)).assert_status(status::NotFound);
+    server.get("/api/shared/nope").assert_status(status::NotFound);
+    assert_eq!(shared_games(&server, &by_status.token), ["The Witcher", "Diablo III"]);
+}
+
+#[test]
+fn share_links_are_private() {
+    let server = server();
+    let id = signup(&server, "owner").id.unwrap();
+    signup(&server, "other");
+    let link = share(&server, id, "owner", r#"{"status": null, "tag": null}"#)
+        .assert_status(status::Ok)
+        .json::<ShareLink>();
+
+    share(&server, id, "other", r#"{"status": null, "tag": null}"#)
+        .assert_status(status::Forbidden);
+    share(&server, id, "owner", r#"{"status": "Later", "tag": null}"#)
+        .assert_status(status::BadRequest);
+    share(&server, id, "owner", r#"{"status": null, "tag": ""}"#)
+        .assert_status(status::UnprocessableEntity);
+    server.get(&format!("/api/user/{}/shares", id)).assert_status(status::Unauthorized);
+    server.request(TestRequest::get(&format!("/api/user/{}/shares", id))
+                   .basic_auth("other", "secret"))
+        .assert_status(status::Forbidden);
+    server.request(TestRequest::delete(&format!("/api/user/{}/shares/{}", id, link.token))
+                   .basic_auth("other", "secret"))
+        .assert_status(status::Forbidden);
+    shared_games(&server, &link.token);
+
+    let archive = server.request(TestRequest::get(&format!("/api/user/{}/archive", id))
+                                 .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<Archive>();
+    assert_eq!(archive.share_links, [link.clone()]);
+    let removed = server.request(TestRequest::delete(&format!("/api/user/{}", id))
+                                 .basic_auth("owner", "secret"))
+        .assert_status(status::Ok)
+        .json::<UserRows>();
+    assert_eq!(removed.share_links, 1);
+    server.get(&format!("/api/shared/{}", link.token)).assert_status(status::NotFound);
+}
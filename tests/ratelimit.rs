@@ -0,0 +1,128 @@
+extern crate backlogrs;
+
+use backlogrs::handlers::Config;
+use backlogrs::models::User;
+use backlogrs::ratelimit::*;
+use backlogrs::status;
+use backlogrs::storage;
+use backlogrs::testing::*;
+
+fn server(rate_limits: RateLimits) -> TestServer {
+    TestServer::with_config(storage::open("memory:demo").unwrap(),
+                            Config::new().rate_limits(rate_limits))
+}
+
+#[test]
+fn token_bucket() {
+    // A token a second
+    let limit = Limit::new(2, 2.0);
+    let mut bucket = Bucket::full(limit, 100.0);
+
+    let first = bucket.take(limit, 100.0);
+    assert!(first.allowed);
+    assert_eq!((first.limit, first.remaining, first.reset), (2, 1, 1));
+    let second = bucket.take(limit, 100.0);
+    assert!(second.allowed);
+    assert_eq!((second.remaining, second.reset, second.retry_after), (0, 2, 0));
+    let third = bucket.take(limit, 100.5);
+    assert!(!third.allowed);
+    assert_eq!(third.retry_after, 1);
+
+    assert!(bucket.peek(limit, 101.0).allowed);
+    assert!(bucket.take(limit, 101.0).allowed);
+    assert!(!bucket.take(limit, 101.0).allowed);
+    // Never more than the capacity
+    bucket.take(limit, 1000.0);
+    assert_eq!(bucket.tokens, 1.0);
+}
+
+#[test]
+fn memory_store() {
+    let store = MemoryRateLimitStore::new();
+    let limit = Limit::new(1, 60.0);
+    assert!(store.take("a", limit, 0.0).unwrap().allowed);
+    assert!(!store.take("a", limit, 1.0).unwrap().allowed);
+    assert!(store.take("b", limit, 1.0).unwrap().allowed);
+    assert!(store.take("a", limit, 60.0).unwrap().allowed);
+
+    assert!(store.peek("c", limit, 60.0).unwrap().allowed);
+    assert!(store.peek("c", limit, 60.0).unwrap().allowed);
+}
+
+#[test]
+fn headers() {
+    let server = server(RateLimits::in_memory());
+    server.get("/api/game")
+        .assert_status(status::Ok)
+        .assert_header("RateLimit-Limit", "300")
+        .assert_header("RateLimit-Remaining", "299");
+    server.request(TestRequest::get("/api/game").basic_auth("user", "hunter2"))
+        .assert_status(status::Ok)
+        .assert_header("RateLimit-Limit", "600");
+}
+
+#[test]
+fn signup_by_ip() {
+    let server = server(RateLimits { signup: Limit::per_hour(2), ..RateLimits::in_memory() });
+    try_signup(&server, &unique("r")).assert_status(status::Ok);
+    try_signup(&server, &unique("r")).assert_status(status::Ok);
+    let res = try_signup(&server, &unique("r"));
+    res.assert_status(status::TooManyRequests)
+        .assert_header("RateLimit-Limit", "2")
+        .assert_header("RateLimit-Remaining", "0");
+    let header = |name: &str| {
+        let value = res.headers.get_raw(name).unwrap()[0].clone();
+        String::from_utf8(value).unwrap().parse::<u64>().unwrap()
+    };
+    // A token every half hour, give or take the time the test takes
+    assert!(header("RateLimit-Reset") > 3590 && header("RateLimit-Reset") <= 3600);
+    assert!(header("Retry-After") > 1790 && header("Retry-After") <= 1800);
+
+    // Only creating accounts is limited that much
+    server.get("/api/user").assert_status(status::Ok);
+}
+
+#[test]
+fn entries_by_user() {
+    let server = server(RateLimits { entries: Limit::per_hour(1), ..RateLimits::in_memory() });
+    let entry = r#"{"id": null, "game_id": 1, "time_played": null, "last_update": null,
+                   "status": null, "game": null}"#;
+    let users = (0..2).map(|_| signup(&server, &unique("r"))).collect::<Vec<_>>();
+    let post = |user: &User| {
+        server.request(TestRequest::post(&format!("/api/user/{}/library", user.id.unwrap()))
+                       .basic_auth(&user.username, "secret")
+                       .body(entry))
+    };
+
+    post(&users[0]).assert_status(status::Ok);
+    post(&users[0]).assert_status(status::TooManyRequests);
+    post(&users[1]).assert_status(status::Ok);
+}
+
+#[test]
+fn failed_logins_by_ip() {
+    let server = server(RateLimits { login: Limit::per_hour(2), ..RateLimits::in_memory() });
+    // Logging in fine doesn't count
+    for _ in 0..3 {
+        server.request(TestRequest::get("/api/game").basic_auth("user", "hunter2"))
+            .assert_status(status::Ok);
+    }
+    for _ in 0..2 {
+        server.request(TestRequest::get("/api/game").basic_auth("user", "wrong"))
+            .assert_status(status::Unauthorized);
+    }
+    server.request(TestRequest::get("/api/game").basic_auth("user", "hunter2"))
+        .assert_status(status::TooManyRequests)
+        .assert_header("RateLimit-Limit", "2");
+    // Requests without credentials aren't attempts to log in
+    server.get("/api/game").assert_status(status::Ok);
+}
+
+#[test]
+fn outside_of_api() {
+    let server = server(RateLimits { ip: Limit::per_hour(1), ..RateLimits::in_memory() });
+    server.get("/api/game").assert_status(status::Ok);
+    server.get("/api/game").assert_status(status::TooManyRequests);
+    server.get("/health").assert_status(status::Ok);
+    server.get("/ready").assert_status(status::Ok);
+}
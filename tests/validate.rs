@@ -0,0 +1,72 @@
+extern crate backlogrs;
+
+use backlogrs::models::{Entry, Login, UserUpdate};
+use backlogrs::validate::*;
+
+fn login(username: &str, email: &str) -> Login {
+    Login {
+        id: None,
+        username: username.to_string(),
+        password: "secret".to_string(),
+        email: email.to_string(),
+    }
+}
+
+#[test]
+fn length() {
+    assert_eq!(rules::length("ab", Some(1), Some(2)), None);
+    assert!(rules::length("", Some(1), Some(2)).is_some());
+    assert!(rules::length("abc", Some(1), Some(2)).is_some());
+    // Characters rather than bytes
+    assert_eq!(rules::length("åäö", None, Some(3)), None);
+}
+
+#[test]
+fn range() {
+    assert_eq!(rules::range(0.0, Some(0.0), None), None);
+    assert!(rules::range(-0.5, Some(0.0), None).is_some());
+    assert!(rules::range(::std::f64::NAN, Some(0.0), None).is_some());
+}
+
+#[test]
+fn email() {
+    assert_eq!(rules::email("user@example.com"), None);
+    for x in ["", "user", "@example.com", "user@", "user@example", "a@b@c.com",
+              "us er@example.com", "user@example..com"].iter() {
+        assert!(rules::email(x).is_some(), "{} should be invalid", x);
+    }
+}
+
+#[test]
+fn login_rules() {
+    assert_eq!(login("user", "user@example.com").validate(), Ok(()));
+    let errors = login("", "user").validate().unwrap_err();
+    assert_eq!(errors.errors.iter().map(|x| &x.field[..]).collect::<Vec<_>>(),
+               vec!["username", "email"]);
+}
+
+#[test]
+fn entry_game_required_if_new() {
+    let mut entry = Entry {
+        id: None,
+        game_id: None,
+        time_played: None,
+        last_update: None,
+        status: None,
+        game: None,
+    };
+    assert_eq!(entry.validate().unwrap_err().errors[0].field, "game_id");
+    entry.id = Some(1);
+    assert_eq!(entry.validate(), Ok(()));
+}
+
+#[test]
+fn optional_fields_only_checked_when_set() {
+    let mut update = UserUpdate {
+        username: None,
+        email: None,
+    };
+    assert_eq!(update.validate(), Ok(()));
+    update.email = Some("user".to_string());
+    assert_eq!(update.validate().unwrap_err().errors[0].field, "email");
+}